@@ -2,11 +2,12 @@ use gpui::{
     px, size, App, Bounds, VisualContext, WindowBackgroundAppearance, WindowBounds, WindowOptions,
 };
 
-use crate::{asserts::Assets, root::Root, theme::Theme, window::Window};
+use crate::{asserts::Assets, root::Root, settings::Settings, theme::Theme, window::Window};
 
 pub fn run_app() {
     App::new().with_assets(Assets).run(move |cx| {
         Theme::init(cx);
+        Settings::init(cx);
 
         let window_options = WindowOptions {
             titlebar: None,
@@ -1,38 +1,403 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use gpui::{
-    div, px, InteractiveElement, ParentElement, Render, StatefulInteractiveElement, Styled, View,
-    VisualContext, WindowContext,
+    div, prelude::FluentBuilder, px, AnyElement, ElementId, InteractiveElement, IntoElement,
+    ParentElement, Render, StatefulInteractiveElement, Styled, View, ViewContext, VisualContext,
+    WindowContext,
 };
 
 use crate::{
-    metadata::{library::LibraryModel, track::Tracks},
-    playing::Playing,
+    events::{InputEvent, UiEvent},
+    history::History,
+    input::TextInput,
+    metadata::{
+        database::DatabaseModel, library::LibraryModel, playlist::PlaylistsModel,
+        search::SearchResults, track::Tracks,
+    },
+    playlists::PlaylistsView,
+    theme::Theme,
 };
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Tracks,
+    Playlists,
+    History,
+    MostPlayed,
+    RecentlyPlayed,
+    NeverPlayed,
+}
+
+impl Section {
+    const ALL: [Section; 6] = [
+        Section::Tracks,
+        Section::Playlists,
+        Section::History,
+        Section::MostPlayed,
+        Section::RecentlyPlayed,
+        Section::NeverPlayed,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Section::Tracks => "Tracks",
+            Section::Playlists => "Playlists",
+            Section::History => "History",
+            Section::MostPlayed => "Most Played",
+            Section::RecentlyPlayed => "Recently Played",
+            Section::NeverPlayed => "Never Played",
+        }
+    }
+}
+
 pub struct Browse {
+    section: Section,
+    library: LibraryModel,
+    database: DatabaseModel,
     pub tracks: View<Tracks>,
+    playlists: View<PlaylistsView>,
+    history: View<History>,
+    stats: View<Tracks>,
+    search_input: View<TextInput>,
+    // the hidden tracks the lists were made without
+    hidden: HashSet<String>,
+    // `None` while the search box is empty
+    search: Option<SearchResults>,
+    results: View<Tracks>,
     // albums: View<Albums>,
     // playing: View<Playing>,
 }
 
 impl Browse {
-    pub fn init(cx: &mut WindowContext, model: LibraryModel) -> View<Self> {
-        let tracks = cx.new_view(|cx| model.get_tracks(cx));
+    pub fn init(
+        cx: &mut WindowContext,
+        model: LibraryModel,
+        database: DatabaseModel,
+        playlists: PlaylistsModel,
+    ) -> View<Self> {
+        let hidden = database.read(cx).hidden.clone();
+        let all = model.read(cx).visible(database.read(cx));
+        let tracks = Tracks::new(cx, all);
+        let playlists = PlaylistsView::new(cx, &playlists, &model, &database);
+        let history = History::new(cx, &model, &database);
+        let stats = Tracks::new(cx, Vec::new());
+        let search_input = TextInput::new(cx, "Search");
+        let results = Tracks::new(cx, Vec::new());
         // let albums = model.get_albums(cx);
 
-        cx.new_view(|cx| Self { tracks })
+        cx.new_view(|cx| {
+            cx.observe(database.model(), |this: &mut Self, database, cx| {
+                if this.hidden != database.read(cx).hidden {
+                    this.hidden = database.read(cx).hidden.clone();
+                    this.refresh_library(cx);
+                } else {
+                    this.refresh_stats(cx);
+                }
+            })
+            .detach();
+            cx.observe(model.model(), |this: &mut Self, _, cx| {
+                this.refresh_library(cx)
+            })
+            .detach();
+
+            cx.subscribe(
+                &search_input,
+                |this: &mut Self, _, event: &InputEvent, cx| {
+                    if let InputEvent::Changed(query) | InputEvent::Submitted(query) = event {
+                        this.search(query, cx);
+                    }
+                },
+            )
+            .detach();
+
+            // pass everything the child views emit on to the root
+            cx.subscribe(&tracks, |_, _, event: &Arc<UiEvent>, cx| {
+                cx.emit(Arc::clone(event))
+            })
+            .detach();
+            cx.subscribe(&stats, |_, _, event: &Arc<UiEvent>, cx| {
+                cx.emit(Arc::clone(event))
+            })
+            .detach();
+            cx.subscribe(&playlists, |_, _, event: &Arc<UiEvent>, cx| {
+                cx.emit(Arc::clone(event))
+            })
+            .detach();
+            cx.subscribe(&results, |_, _, event: &Arc<UiEvent>, cx| {
+                cx.emit(Arc::clone(event))
+            })
+            .detach();
+
+            Self {
+                section: Section::Tracks,
+                library: model,
+                database,
+                tracks,
+                playlists,
+                history,
+                stats,
+                search_input,
+                hidden,
+                search: None,
+                results,
+            }
+        })
+    }
+
+    fn select(&mut self, section: Section, cx: &mut ViewContext<Self>) {
+        self.section = section;
+        self.refresh_stats(cx);
+        cx.notify();
+    }
+
+    /// Highlights the playing track in every track list.
+    pub fn set_current(&mut self, file: Option<String>, cx: &mut ViewContext<Self>) {
+        for view in [&self.tracks, &self.stats, &self.results] {
+            view.update(cx, |this, cx| this.set_current(file.clone(), cx));
+        }
+    }
+
+    fn search(&mut self, query: &str, cx: &mut ViewContext<Self>) {
+        let results = self
+            .library
+            .search(query, self.database.read(cx), self.search.as_ref(), cx);
+        self.search = (!results.query.is_empty()).then_some(results);
+
+        let tracks = self
+            .search
+            .as_ref()
+            .map(|results| results.tracks.clone())
+            .unwrap_or_default();
+        self.results.update(cx, |this, cx| {
+            this.set_tracks(tracks, cx);
+        });
+        cx.notify();
+    }
+
+    /// Replaces the query, e.g. with an artist picked from the results.
+    fn set_query(&mut self, query: String, cx: &mut ViewContext<Self>) {
+        self.search_input
+            .update(cx, |input, cx| input.set_text(query.clone(), cx));
+        self.search(&query, cx);
+    }
+
+    fn render_search(&self, results: &SearchResults, cx: &mut ViewContext<Self>) -> AnyElement {
+        let theme = cx.global::<Theme>();
+        let (heading, text, invalid) = (theme.subtext0, theme.text, theme.red);
+
+        if let Some(error) = &results.error {
+            // show the query with the part that could not be parsed marked
+            let query = &results.query;
+            let (start, end) = (
+                error.span.start.min(query.len()),
+                error.span.end.min(query.len()),
+            );
+            let marked = match &query[start..end] {
+                "" => "_".to_string(),
+                marked => marked.to_string(),
+            };
+
+            return div()
+                .flex()
+                .flex_col()
+                .px_3()
+                .child(
+                    div()
+                        .flex()
+                        .text_color(text)
+                        .child(query[..start].to_string())
+                        .child(
+                            div()
+                                .text_color(invalid)
+                                .text_decoration_1()
+                                .text_decoration_wavy()
+                                .text_decoration_color(invalid)
+                                .child(marked),
+                        )
+                        .child(query[end..].to_string()),
+                )
+                .child(
+                    div()
+                        .text_sm()
+                        .text_color(invalid)
+                        .child(error.message.clone()),
+                )
+                .into_any_element();
+        }
+
+        let group = |label: &'static str| {
+            div()
+                .px_3()
+                .pt_2()
+                .text_sm()
+                .text_color(heading)
+                .child(label)
+        };
+        let pick = |id: String, label: String, query: String, cx: &mut ViewContext<Self>| {
+            div()
+                .id(ElementId::Name(id.into()))
+                .px_3()
+                .text_color(text)
+                .child(label)
+                .on_click(cx.listener(move |this, _, cx| this.set_query(query.clone(), cx)))
+        };
+
+        let artists = results
+            .artists
+            .iter()
+            .map(|artist| {
+                pick(
+                    format!("artist-{}", artist),
+                    artist.clone(),
+                    artist.clone(),
+                    cx,
+                )
+            })
+            .collect::<Vec<_>>();
+        let albums = results
+            .albums
+            .iter()
+            .map(|(album, artist)| {
+                pick(
+                    format!("album-{}-{}", album, artist),
+                    format!("{} - {}", album, artist),
+                    format!("{} {}", album, artist),
+                    cx,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        div()
+            .flex()
+            .flex_col()
+            .size_full()
+            .child(
+                div()
+                    .id("search-groups")
+                    .flex()
+                    .flex_col()
+                    .flex_none()
+                    .max_h(px(240.))
+                    .overflow_y_scroll()
+                    .when(!artists.is_empty(), |e| {
+                        e.child(group("Artists")).children(artists)
+                    })
+                    .when(!albums.is_empty(), |e| {
+                        e.child(group("Albums")).children(albums)
+                    }),
+            )
+            .child(group("Tracks"))
+            .child(
+                div()
+                    .px_3()
+                    .text_sm()
+                    .text_color(heading)
+                    .child(format!("{} found", results.matches.len())),
+            )
+            .child(self.results.clone())
+            .into_any_element()
+    }
+
+    /// Lists the library again after tracks in it changed.
+    fn refresh_library(&mut self, cx: &mut ViewContext<Self>) {
+        let all = self.library.read(cx).visible(self.database.read(cx));
+        self.tracks.update(cx, |this, cx| this.set_tracks(all, cx));
+        if let Some(results) = self.search.take() {
+            self.search(&results.query, cx);
+        }
+        self.refresh_stats(cx);
+    }
+
+    fn refresh_stats(&mut self, cx: &mut ViewContext<Self>) {
+        let database = self.database.read(cx);
+        let all = &self.library.read(cx).visible(database);
+        let tracks = match self.section {
+            Section::MostPlayed => database.most_played(all),
+            Section::RecentlyPlayed => database.recently_played(all),
+            Section::NeverPlayed => database.never_played(all),
+            Section::Tracks | Section::Playlists | Section::History => return,
+        };
+
+        self.stats.update(cx, |this, cx| {
+            this.set_tracks(tracks, cx);
+        });
     }
 }
 
 impl Render for Browse {
     fn render(&mut self, cx: &mut gpui::ViewContext<Self>) -> impl gpui::IntoElement {
+        let theme = cx.global::<Theme>();
+        let (active, inactive) = (theme.text, theme.subtext0);
+
+        // track lists scroll themselves, only laying out the rows in view
+        let scrolling = |content: AnyElement| {
+            div()
+                .id("browse-view")
+                .size_full()
+                .overflow_scroll()
+                .child(content)
+                .into_any_element()
+        };
+        let content = match (&self.search, self.section) {
+            (Some(results), _) => self.render_search(results, cx),
+            (None, Section::Tracks) => self.tracks.clone().into_any_element(),
+            (None, Section::Playlists) => scrolling(self.playlists.clone().into_any_element()),
+            (None, Section::History) => scrolling(self.history.clone().into_any_element()),
+            _ => self.stats.clone().into_any_element(),
+        };
+
         div()
-            .id("browse-view")
             .flex_grow()
-            .overflow_scroll()
-            .rounded_b_sm()
-            .p(px(1.))
-            .child(self.tracks.clone())
+            .flex()
+            .flex_col()
+            .min_h_0()
+            .child(div().flex().px_3().pt_1().child(self.search_input.clone()))
+            .child(
+                div()
+                    .flex()
+                    .gap_3()
+                    .px_3()
+                    .py_1()
+                    .children(Section::ALL.map(|section| {
+                        div()
+                            .id(ElementId::Name(section.label().into()))
+                            .text_color(if section == self.section {
+                                active
+                            } else {
+                                inactive
+                            })
+                            .child(section.label())
+                            .on_click(cx.listener(move |this, _, cx| this.select(section, cx)))
+                    }))
+                    .child(
+                        div()
+                            .id("organize")
+                            .ml_auto()
+                            .text_color(inactive)
+                            .child("Organize")
+                            .on_click(
+                                cx.listener(|_, _, cx| cx.emit(Arc::new(UiEvent::OrganizeClicked))),
+                            ),
+                    )
+                    .child(
+                        div()
+                            .id("duplicates")
+                            .text_color(inactive)
+                            .child("Duplicates")
+                            .on_click(cx.listener(|_, _, cx| {
+                                cx.emit(Arc::new(UiEvent::DuplicatesClicked))
+                            })),
+                    ),
+            )
+            .child(
+                div()
+                    .flex()
+                    .flex_col()
+                    .flex_grow()
+                    .min_h_0()
+                    .rounded_b_sm()
+                    .p(px(1.))
+                    .child(content),
+            )
     }
 }
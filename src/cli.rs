@@ -0,0 +1,250 @@
+use std::process;
+#[cfg(unix)]
+use std::{fs, path::Path};
+
+#[cfg(unix)]
+use crate::remote::{self, Request};
+use crate::{
+    metadata::{
+        database::Database, library::Library, organize::Plan, pattern::Pattern, query::Query,
+    },
+    settings::Settings,
+};
+
+#[cfg(unix)]
+const CTL_USAGE: &str = "ctl commands:
+  play [<file>...]     resume, or play the files in place of the queue
+  pause | toggle | stop | next | previous
+  seek [+|-]<seconds>  go to a time, or forward or back from where it is
+  enqueue <file>...    add the files to the end of the queue
+  status | queue       print what is playing, or the queue in play order";
+
+/// Runs `ggmusic <command>` without opening a window. Returns `false` when
+/// there is no command and the app should start as usual.
+pub fn run(args: &[String]) -> bool {
+    match args.first().map(String::as_str) {
+        // ggmusic list 'artist:"Miles Davis" year:1955..1960'
+        Some("list") => list(&args[1..].join(" ")),
+        // ggmusic organize '%artist%/%album%/%track% - %title%'
+        Some("organize") => organize(args.get(1).map(String::as_str)),
+        // ggmusic ctl seek +30
+        #[cfg(unix)]
+        Some("ctl") => ctl(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!(
+                "usage: ggmusic [list <query> | organize [<pattern>] | ctl [--json] <command>]"
+            );
+            #[cfg(unix)]
+            println!("{}", CTL_USAGE);
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Prints every track matching the query in `text`, one tab-separated line each.
+fn list(text: &str) {
+    let query = match Query::parse(text) {
+        Ok(query) => query,
+        Err(err) => {
+            // point at the part that could not be parsed
+            let before = text[..err.span.start].chars().count();
+            let width = text[err.span.clone()].chars().count().max(1);
+            eprintln!("{}", text);
+            eprintln!("{}{}", " ".repeat(before), "^".repeat(width));
+            eprintln!("error: {}", err.message);
+            process::exit(2);
+        }
+    };
+
+    // covers are never shown here, so no sidecar images are looked for
+    let library = Library::load_tracks(&[]);
+    let database = Database::load();
+    for track in library.find(&query, &database) {
+        println!(
+            "{}\t{}\t{}\t{}",
+            track.artist,
+            track.album,
+            track.title,
+            track.file.as_deref().unwrap_or_default()
+        );
+    }
+}
+
+/// Prints where the organizer would move every file, without moving any.
+fn organize(pattern: Option<&str>) {
+    let settings = Settings::load();
+    let text = pattern.unwrap_or(&settings.organize_pattern);
+    let pattern = match Pattern::parse(text) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    };
+
+    let library = Library::load_tracks(&[]);
+    let plan = Plan::new(
+        &library.paths(),
+        &pattern,
+        &Library::root(),
+        &settings.cover_names,
+    );
+    print!("{}", plan.report());
+}
+
+/// Absolute paths, as the player runs elsewhere. Paths that do not exist are
+/// passed on as they are, for the player to complain about.
+#[cfg(unix)]
+fn absolute(paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::canonicalize(Path::new(path))
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| path.clone())
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn ctl_request(args: &[String]) -> Option<Request> {
+    let request = match args.first()?.as_str() {
+        "play" => Request::Play {
+            paths: absolute(&args[1..]),
+        },
+        "pause" => Request::Pause,
+        "toggle" => Request::Toggle,
+        "stop" => Request::Stop,
+        "next" => Request::Next,
+        "previous" | "prev" => Request::Previous,
+        "seek" => {
+            let text = args.get(1)?;
+            Request::Seek {
+                seconds: text.parse().ok()?,
+                relative: text.starts_with(['+', '-']),
+            }
+        }
+        "enqueue" if args.len() > 1 => Request::Enqueue {
+            paths: absolute(&args[1..]),
+        },
+        "status" => Request::Status,
+        "queue" => Request::Queue,
+        _ => return None,
+    };
+    Some(request)
+}
+
+/// Sends a command to the running player and prints its answer, as JSON
+/// with `--json` or else as tab-separated lines.
+#[cfg(unix)]
+fn ctl(args: &[String]) {
+    let json = args.first().is_some_and(|arg| arg == "--json");
+    let args = if json { &args[1..] } else { args };
+    let Some(request) = ctl_request(args) else {
+        eprintln!("{}", CTL_USAGE);
+        process::exit(2);
+    };
+
+    let response = match remote::send(&request) {
+        Ok(response) => response,
+        Err(err) => {
+            eprintln!("error: cannot reach ggmusic, is it running? ({})", err);
+            process::exit(1);
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string(&response).unwrap_or_default());
+    }
+    if let Some(error) = &response.error {
+        if !json {
+            eprintln!("error: {}", error);
+        }
+        process::exit(1);
+    }
+    if json {
+        return;
+    }
+
+    if let Some(status) = response.status {
+        let state = match (&status.track, status.playing) {
+            (None, _) => "stopped",
+            (Some(_), true) => "playing",
+            (Some(_), false) => "paused",
+        };
+        match status.track {
+            Some(track) => println!(
+                "{}\t{:.0}/{:.0}\t{}\t{}\t{}\t{}",
+                state,
+                status.position,
+                track.duration,
+                track.artist,
+                track.album,
+                track.title,
+                track.file.as_deref().unwrap_or_default()
+            ),
+            None => println!("{}", state),
+        }
+    }
+    if let Some(queue) = response.queue {
+        for (ix, track) in queue.into_iter().enumerate() {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                ix + 1,
+                track.artist,
+                track.album,
+                track.title,
+                track.file.as_deref().unwrap_or_default()
+            );
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn request(args: &[&str]) -> Option<Request> {
+        ctl_request(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_ctl_commands() {
+        assert!(matches!(request(&["play"]), Some(Request::Play { paths }) if paths.is_empty()));
+        assert!(matches!(request(&["toggle"]), Some(Request::Toggle)));
+        assert!(matches!(request(&["prev"]), Some(Request::Previous)));
+        assert!(matches!(request(&["queue"]), Some(Request::Queue)));
+
+        assert!(request(&[]).is_none());
+        assert!(request(&["rewind"]).is_none());
+        assert!(request(&["enqueue"]).is_none());
+    }
+
+    #[test]
+    fn seeks_from_where_playback_is_only_with_a_sign() {
+        let seek = |text| match request(&["seek", text]) {
+            Some(Request::Seek { seconds, relative }) => Some((seconds, relative)),
+            _ => None,
+        };
+        assert_eq!(seek("30"), Some((30.0, false)));
+        assert_eq!(seek("+30"), Some((30.0, true)));
+        assert_eq!(seek("-7.5"), Some((-7.5, true)));
+        assert_eq!(seek("half"), None);
+        assert!(request(&["seek"]).is_none());
+    }
+
+    #[test]
+    fn sends_files_as_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.flac");
+        fs::write(&file, "").unwrap();
+        let file = fs::canonicalize(file).unwrap();
+        let dotted = dir.path().join(".").join("a.flac");
+
+        let paths = match request(&["enqueue", dotted.to_str().unwrap(), "/missing/b.flac"]) {
+            Some(Request::Enqueue { paths }) => paths,
+            _ => panic!("not an enqueue request"),
+        };
+        assert_eq!(paths, [file.to_str().unwrap(), "/missing/b.flac"]);
+    }
+}
@@ -0,0 +1,394 @@
+use std::{fs, ops::Range, path::Path, sync::Arc};
+
+use gpui::{
+    div, prelude::FluentBuilder, px, uniform_list, AnyElement, ElementId, InteractiveElement,
+    IntoElement, ParentElement, PromptLevel, Render, SharedString, StatefulInteractiveElement,
+    Styled, View, ViewContext, VisualContext, WindowContext,
+};
+
+use crate::{
+    events::UiEvent,
+    metadata::{
+        columns::Column,
+        database::DatabaseModel,
+        duplicates::{self, AudioHashes, Group},
+        fingerprint::FingerprintsModel,
+        library::{Library, LibraryModel},
+        track::Track,
+    },
+    theme::Theme,
+};
+
+#[derive(Clone, Copy)]
+enum Row {
+    Group(usize),
+    // group and track in it
+    Track(usize, usize),
+}
+
+/// Lists tracks that are likely copies of each other, in groups, to keep
+/// one of each group and delete or hide the rest.
+pub struct Duplicates {
+    // `None` while the library is being scanned
+    groups: Option<Vec<Group>>,
+    // the track to keep of each group
+    keepers: Vec<usize>,
+    rows: Vec<Row>,
+    // tracks without a fingerprint when the scan started
+    unprinted: usize,
+    status: Option<String>,
+    error: Option<String>,
+}
+
+/// The file type, from the extension.
+fn format(track: &Track) -> String {
+    track
+        .path()
+        .and_then(|path| Path::new(path).extension())
+        .map(|ext| ext.to_string_lossy().to_uppercase())
+        .unwrap_or_default()
+}
+
+impl Duplicates {
+    pub fn new(cx: &mut WindowContext) -> View<Self> {
+        let tracks = cx.global::<LibraryModel>().read(cx).tracks().to_vec();
+        let fingerprints = cx.global::<FingerprintsModel>().read(cx);
+        // fingerprints still being worked out are left out of this scan
+        let prints = fingerprints.of(&tracks);
+        let unprinted = tracks.len().saturating_sub(prints.len());
+
+        cx.new_view(|cx| {
+            cx.spawn(|this, mut cx| async move {
+                // decodes the files that could be copies and were not seen before,
+                // which takes a while
+                let groups = cx
+                    .background_executor()
+                    .spawn(async move {
+                        let mut hashes = AudioHashes::load();
+                        let groups = duplicates::find(&tracks, &prints, &mut hashes);
+                        hashes.save();
+                        groups
+                    })
+                    .await;
+
+                this.update(&mut cx, |this, cx| {
+                    this.show(groups);
+                    cx.notify();
+                })
+                .ok();
+            })
+            .detach();
+
+            Self {
+                groups: None,
+                keepers: Vec::new(),
+                rows: Vec::new(),
+                unprinted,
+                status: None,
+                error: None,
+            }
+        })
+    }
+
+    fn show(&mut self, groups: Vec<Group>) {
+        // the best encoded copy is kept unless another is picked
+        self.keepers = groups
+            .iter()
+            .map(|group| {
+                (0..group.tracks.len())
+                    .max_by_key(|ix| group.tracks[*ix].bitrate)
+                    .unwrap_or(0)
+            })
+            .collect();
+        self.rows = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(ix, group)| {
+                std::iter::once(Row::Group(ix))
+                    .chain((0..group.tracks.len()).map(move |track| Row::Track(ix, track)))
+            })
+            .collect();
+        self.groups = Some(groups);
+    }
+
+    /// The files of a group other than its keeper.
+    fn others(&self, group: usize) -> Vec<&Track> {
+        let Some(tracks) = self.groups.as_ref().map(|groups| &groups[group].tracks) else {
+            return Vec::new();
+        };
+        tracks
+            .iter()
+            .enumerate()
+            .filter(|(ix, _)| *ix != self.keepers[group])
+            .map(|(_, track)| &**track)
+            .collect()
+    }
+
+    fn set_hidden(&mut self, group: usize, hidden: bool, cx: &mut ViewContext<Self>) {
+        let files = self
+            .others(group)
+            .into_iter()
+            .filter_map(|track| track.file.clone())
+            .collect::<Vec<_>>();
+        cx.global::<DatabaseModel>()
+            .clone()
+            .set_hidden(&files, hidden, cx);
+        cx.notify();
+    }
+
+    /// Deletes the files of a group other than its keeper, once confirmed.
+    fn delete_others(&mut self, group: usize, cx: &mut ViewContext<Self>) {
+        // a cue sheet track shares its file with the rest of the album
+        let (files, virtual_tracks): (Vec<_>, Vec<_>) = self
+            .others(group)
+            .into_iter()
+            .partition(|track| track.segment.is_none());
+        let files = files
+            .into_iter()
+            .filter_map(|track| track.file.clone())
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            self.error = Some("Tracks split from a cue sheet can only be hidden".to_string());
+            cx.notify();
+            return;
+        }
+
+        let detail = files.join("\n");
+        let answer = cx.prompt(
+            PromptLevel::Warning,
+            &format!("Delete {} files?", files.len()),
+            Some(&detail),
+            &["Delete", "Cancel"],
+        );
+        let skipped = virtual_tracks.len();
+
+        cx.spawn(|this, mut cx| async move {
+            if answer.await != Ok(0) {
+                return;
+            }
+            let mut deleted = Vec::new();
+            let mut failed = None;
+            for file in files {
+                match fs::remove_file(&file) {
+                    Ok(()) => deleted.push(file),
+                    Err(err) => failed = Some(format!("Failed to delete {}: {}", file, err)),
+                }
+            }
+
+            this.update(&mut cx, |this, cx| {
+                cx.global::<LibraryModel>().clone().remove(&deleted, cx);
+                this.forget(group, &deleted);
+                this.status = Some(match skipped {
+                    0 => format!("Deleted {} files", deleted.len()),
+                    _ => format!(
+                        "Deleted {} files, left {} cue sheet tracks",
+                        deleted.len(),
+                        skipped
+                    ),
+                });
+                this.error = failed;
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Takes deleted files out of a group.
+    fn forget(&mut self, group: usize, deleted: &[String]) {
+        let Some(mut groups) = self.groups.take() else {
+            return;
+        };
+        let keeper = groups[group].tracks[self.keepers[group]].file.clone();
+        groups[group].tracks.retain(|track| {
+            !track
+                .file
+                .as_ref()
+                .is_some_and(|file| deleted.contains(file))
+        });
+        self.keepers[group] = groups[group]
+            .tracks
+            .iter()
+            .position(|track| track.file == keeper)
+            .unwrap_or(0);
+
+        // a group of one is no longer a duplicate
+        let keepers = std::mem::take(&mut self.keepers);
+        let (groups, keepers): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .zip(keepers)
+            .filter(|(group, _)| group.tracks.len() > 1)
+            .unzip();
+        self.show(groups);
+        self.keepers = keepers;
+    }
+
+    fn render_rows(&mut self, rows: Range<usize>, cx: &mut ViewContext<Self>) -> Vec<AnyElement> {
+        let theme = cx.global::<Theme>();
+        let (text, label, accent) = (theme.text, theme.subtext0, theme.blue);
+        let Some(groups) = &self.groups else {
+            return Vec::new();
+        };
+        // whether each row is hidden, read before the rows borrow `cx`
+        let database = cx.global::<DatabaseModel>().read(cx);
+        let hidden = rows
+            .clone()
+            .map(|ix| match self.rows.get(ix) {
+                Some(Row::Group(group)) => self
+                    .others(*group)
+                    .iter()
+                    .all(|track| database.is_hidden(track)),
+                Some(Row::Track(group, track)) => {
+                    database.is_hidden(&groups[*group].tracks[*track])
+                }
+                None => false,
+            })
+            .collect::<Vec<_>>();
+        let start = rows.start;
+
+        rows.filter_map(|ix| {
+            let hidden = hidden[ix - start];
+            let e = match *self.rows.get(ix)? {
+                Row::Group(group) => div()
+                    .id(ElementId::Integer(ix))
+                    .flex()
+                    .gap_2()
+                    .h_6()
+                    .pt_1()
+                    .text_sm()
+                    .text_color(label)
+                    .child(groups[group].kind.label())
+                    .child(div().flex_grow())
+                    .child(if hidden {
+                        self.button("unhide", "Unhide", cx, move |this, cx| {
+                            this.set_hidden(group, false, cx)
+                        })
+                        .into_any_element()
+                    } else {
+                        self.button("hide", "Hide others", cx, move |this, cx| {
+                            this.set_hidden(group, true, cx)
+                        })
+                        .into_any_element()
+                    })
+                    .child(self.button("delete", "Delete others", cx, move |this, cx| {
+                        this.delete_others(group, cx)
+                    })),
+                Row::Track(group, track_ix) => {
+                    let track = &groups[group].tracks[track_ix];
+                    let keeper = self.keepers[group] == track_ix;
+                    let path = track.path().unwrap_or_default();
+                    let path = Path::new(path)
+                        .strip_prefix(Library::root())
+                        .unwrap_or(Path::new(path))
+                        .display()
+                        .to_string();
+                    div()
+                        .id(ElementId::Integer(ix))
+                        .flex()
+                        .gap_2()
+                        .h_6()
+                        .text_sm()
+                        .whitespace_nowrap()
+                        .text_color(if keeper { accent } else { text })
+                        .when(hidden, |e| e.text_color(label))
+                        .child(
+                            div()
+                                .flex_none()
+                                .w(px(40.))
+                                .child(if keeper { "Keep" } else { "" }),
+                        )
+                        .child(
+                            div()
+                                .flex_none()
+                                .w(px(260.))
+                                .overflow_hidden()
+                                .child(format!("{} - {}", track.artist, track.title)),
+                        )
+                        .child(
+                            div()
+                                .flex_none()
+                                .w(px(50.))
+                                .child(Column::Duration.cell(track, None)),
+                        )
+                        .child(div().flex_none().w(px(50.)).child(format(track)))
+                        .child(
+                            div()
+                                .flex_none()
+                                .w(px(70.))
+                                .child(Column::Bitrate.cell(track, None)),
+                        )
+                        .child(div().overflow_hidden().text_color(label).child(path))
+                        .on_click(cx.listener(move |this, _, cx| {
+                            this.keepers[group] = track_ix;
+                            cx.notify();
+                        }))
+                }
+            };
+            Some(e.into_any_element())
+        })
+        .collect()
+    }
+
+    fn button(
+        &self,
+        id: &'static str,
+        label: impl Into<SharedString>,
+        cx: &mut ViewContext<Self>,
+        on_click: impl Fn(&mut Self, &mut ViewContext<Self>) + 'static,
+    ) -> impl IntoElement {
+        div()
+            .id(id)
+            .px_2()
+            .child(label.into())
+            .on_click(cx.listener(move |this, _, cx| on_click(this, cx)))
+    }
+}
+
+impl Render for Duplicates {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (background, text, label, invalid) =
+            (theme.base, theme.text, theme.subtext0, theme.red);
+
+        let summary = match &self.groups {
+            None => "Comparing tracks…".to_string(),
+            Some(groups) if groups.is_empty() => "No duplicates found".to_string(),
+            Some(groups) => format!("{} groups, click a track to keep it instead", groups.len()),
+        };
+
+        let view = cx.view().clone();
+        let rows =
+            uniform_list(view, "duplicate-rows", self.rows.len(), Self::render_rows).h(px(400.));
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .w(px(900.))
+            .p_3()
+            .rounded_md()
+            .bg(background)
+            .text_color(text)
+            .child("Duplicates")
+            .child(div().text_sm().text_color(label).child(summary))
+            .when(self.unprinted > 0, |e| {
+                e.child(div().text_sm().text_color(label).child(format!(
+                    "{} tracks are not fingerprinted yet, other recordings of them are not found",
+                    self.unprinted
+                )))
+            })
+            .child(rows)
+            .when_some(self.status.clone(), |e, status| {
+                e.child(div().text_sm().text_color(label).child(status))
+            })
+            .when_some(self.error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .child(div().flex().justify_end().child(self.button(
+                "close-duplicates",
+                "Close",
+                cx,
+                |_, cx| cx.emit(Arc::new(UiEvent::DialogClosed)),
+            )))
+    }
+}
@@ -1,18 +1,48 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use crate::{
     browse::Browse,
+    duplicates::Duplicates,
+    input::TextInput,
+    lyrics::LyricsView,
     metadata::{
         album::Albums,
         track::{Track, Tracks},
     },
+    naming::NamingTool,
+    organizer::Organizer,
     playback::Playback,
+    playing::Playing,
+    playlists::{NewPlaylist, PlaylistsView},
+    tag_editor::TagEditor,
 };
 
 #[derive(Clone)]
 pub enum UiEvent {
     PlayClicked(PlayClickedEvent),
     PauseClicked,
+    SpeedChanged(f32),
+    PitchChanged(f32),
+    OutputDeviceSelected(Option<String>),
+    ShuffleToggled,
+    RepeatToggled,
+    VolumeChanged(f32),
+    PositionChanged(Duration),
+    AddToPlaylistClicked(Arc<Track>),
+    /// 0 to 5 stars.
+    RatingChanged(Arc<Track>, u8),
+    LovedToggled(Arc<Track>),
+    PlayTracks(Vec<Arc<Track>>),
+    EnqueueTracks(Vec<Arc<Track>>),
+    ExportQueueClicked,
+    EditTagsClicked(Vec<Arc<Track>>),
+    RenameClicked(Vec<Arc<Track>>),
+    OrganizeClicked,
+    DuplicatesClicked,
+    /// Files were moved from the first path to the second, and the library
+    /// already follows them.
+    FilesMoved(Vec<(String, String)>),
+    DialogClosed,
 }
 
 impl UiEvent {
@@ -21,9 +51,21 @@ impl UiEvent {
             track: Arc::clone(track),
         }))
     }
+
+    pub fn add_to_playlist(track: &Arc<Track>) -> Arc<UiEvent> {
+        Arc::new(UiEvent::AddToPlaylistClicked(Arc::clone(track)))
+    }
 }
 impl gpui::EventEmitter<Arc<UiEvent>> for Albums {}
 impl gpui::EventEmitter<Arc<UiEvent>> for Browse {}
+impl gpui::EventEmitter<Arc<UiEvent>> for Playing {}
+impl gpui::EventEmitter<Arc<UiEvent>> for LyricsView {}
+impl gpui::EventEmitter<Arc<UiEvent>> for TagEditor {}
+impl gpui::EventEmitter<Arc<UiEvent>> for NamingTool {}
+impl gpui::EventEmitter<Arc<UiEvent>> for Organizer {}
+impl gpui::EventEmitter<Arc<UiEvent>> for Duplicates {}
+impl gpui::EventEmitter<Arc<UiEvent>> for PlaylistsView {}
+impl gpui::EventEmitter<Arc<UiEvent>> for NewPlaylist {}
 impl gpui::EventEmitter<Arc<UiEvent>> for Tracks {}
 
 #[derive(Clone)]
@@ -34,7 +76,7 @@ pub struct PlayClickedEvent {
 #[derive(Clone)]
 pub enum PlaybackEvent {
     TrackStarted(Arc<Track>),
-    TrackEnded,
+    TrackEnded(TrackEndedEvent),
     Paused,
     Resumed,
 }
@@ -43,6 +85,31 @@ impl PlaybackEvent {
     pub fn start(track: &Arc<Track>) -> Arc<PlaybackEvent> {
         Arc::new(PlaybackEvent::TrackStarted(Arc::clone(track)))
     }
+
+    pub fn end(track: &Arc<Track>, position: Duration, completed: bool) -> Arc<PlaybackEvent> {
+        Arc::new(PlaybackEvent::TrackEnded(TrackEndedEvent {
+            track: Arc::clone(track),
+            position,
+            completed,
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct TrackEndedEvent {
+    pub track: Arc<Track>,
+    /// How far into the track playback got.
+    pub position: Duration,
+    /// Whether the track played to its end rather than being skipped or replaced.
+    pub completed: bool,
 }
 
 impl gpui::EventEmitter<Arc<PlaybackEvent>> for Playback {}
+
+#[derive(Clone)]
+pub enum InputEvent {
+    Changed(String),
+    Submitted(String),
+}
+
+impl gpui::EventEmitter<InputEvent> for TextInput {}
@@ -0,0 +1,108 @@
+use std::{collections::HashMap, sync::Arc};
+
+use gpui::{
+    div, px, IntoElement, ParentElement, Render, Styled, View, ViewContext, VisualContext,
+    WindowContext,
+};
+
+use crate::{
+    metadata::{
+        cover::CoversModel,
+        database::{now, DatabaseModel},
+        library::LibraryModel,
+        track::Track,
+    },
+    theme::Theme,
+};
+
+const HISTORY_VIEW_LIMIT: usize = 500;
+
+pub struct History {
+    entries: Vec<(u64, Arc<Track>)>,
+    library: LibraryModel,
+    database: DatabaseModel,
+}
+
+impl History {
+    pub fn new(
+        cx: &mut WindowContext,
+        library: &LibraryModel,
+        database: &DatabaseModel,
+    ) -> View<Self> {
+        cx.new_view(|cx| {
+            cx.observe(database.model(), |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+            cx.observe(library.model(), |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+            let covers = cx.global::<CoversModel>().clone();
+            cx.observe(covers.model(), |_, _, cx| cx.notify()).detach();
+
+            let mut this = Self {
+                entries: Vec::new(),
+                library: library.clone(),
+                database: database.clone(),
+            };
+            this.refresh(cx);
+            this
+        })
+    }
+
+    fn refresh(&mut self, cx: &mut ViewContext<Self>) {
+        let tracks = self.library.read(cx).tracks();
+        let by_file = tracks
+            .iter()
+            .filter_map(|track| track.file.as_deref().map(|file| (file, track)))
+            .collect::<HashMap<_, _>>();
+
+        self.entries = self
+            .database
+            .read(cx)
+            .history
+            .iter()
+            .rev()
+            .filter_map(|play| {
+                by_file
+                    .get(play.file.as_str())
+                    .map(|&track| (play.timestamp, Arc::clone(track)))
+            })
+            .take(HISTORY_VIEW_LIMIT)
+            .collect();
+        cx.notify();
+    }
+}
+
+fn format_ago(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", secs / 60),
+        3600..=86399 => format!("{} h ago", secs / 3600),
+        _ => format!("{} d ago", secs / 86400),
+    }
+}
+
+impl Render for History {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+
+        div()
+            .flex()
+            .flex_col()
+            .gap(px(1.))
+            .children(self.entries.iter().map(|(timestamp, track)| {
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_3()
+                    .child(
+                        div()
+                            .flex_none()
+                            .w_24()
+                            .text_sm()
+                            .text_color(theme.subtext0)
+                            .child(format_ago(*timestamp)),
+                    )
+                    .child((**track).clone())
+            }))
+    }
+}
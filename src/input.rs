@@ -0,0 +1,98 @@
+use gpui::{
+    div, AppContext, FocusHandle, FocusableView, InteractiveElement, IntoElement, KeyDownEvent,
+    ParentElement, Render, SharedString, Styled, View, ViewContext, VisualContext, WindowContext,
+};
+
+use crate::{events::InputEvent, theme::Theme};
+
+/// A minimal single-line text field.
+pub struct TextInput {
+    focus_handle: FocusHandle,
+    text: String,
+    placeholder: SharedString,
+}
+
+impl TextInput {
+    pub fn new(cx: &mut WindowContext, placeholder: impl Into<SharedString>) -> View<Self> {
+        let placeholder = placeholder.into();
+        cx.new_view(|cx| Self {
+            focus_handle: cx.focus_handle(),
+            text: String::new(),
+            placeholder,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>, cx: &mut ViewContext<Self>) {
+        self.text = text.into();
+        cx.notify();
+    }
+
+    fn on_key_down(&mut self, event: &KeyDownEvent, cx: &mut ViewContext<Self>) {
+        let keystroke = &event.keystroke;
+        // leave shortcuts to whoever handles them
+        if keystroke.modifiers.control || keystroke.modifiers.platform {
+            return;
+        }
+
+        match keystroke.key.as_str() {
+            "backspace" => {
+                self.text.pop();
+            }
+            "enter" => {
+                cx.emit(InputEvent::Submitted(self.text.clone()));
+                return;
+            }
+            "escape" => self.text.clear(),
+            "space" => self.text.push(' '),
+            key => match &keystroke.ime_key {
+                Some(text) => self.text.push_str(text),
+                None if key.chars().count() == 1 => {
+                    if keystroke.modifiers.shift {
+                        self.text.push_str(&key.to_uppercase());
+                    } else {
+                        self.text.push_str(key);
+                    }
+                }
+                None => return,
+            },
+        }
+
+        cx.emit(InputEvent::Changed(self.text.clone()));
+        cx.notify();
+    }
+}
+
+impl FocusableView for TextInput {
+    fn focus_handle(&self, _cx: &AppContext) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Render for TextInput {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let focused = self.focus_handle.is_focused(cx);
+
+        let (text, color) = if self.text.is_empty() && !focused {
+            (self.placeholder.to_string(), theme.overlay0)
+        } else if focused {
+            (format!("{}|", self.text), theme.text)
+        } else {
+            (self.text.clone(), theme.text)
+        };
+
+        div()
+            .track_focus(&self.focus_handle)
+            .on_key_down(cx.listener(Self::on_key_down))
+            .flex_grow()
+            .px_2()
+            .rounded_sm()
+            .bg(theme.surface0)
+            .text_color(color)
+            .child(text)
+    }
+}
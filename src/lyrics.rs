@@ -0,0 +1,180 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use gpui::{
+    div, prelude::FluentBuilder, ElementId, InteractiveElement, IntoElement, Model, ParentElement,
+    Render, ScrollHandle, StatefulInteractiveElement, Styled, Task, View, ViewContext,
+    VisualContext, WindowContext,
+};
+
+use crate::{events::UiEvent, metadata::lyrics::Lyrics, playback::Playback, theme::Theme};
+
+// woken up this much after a line or word is due, so that playback is
+// surely past it
+const WAKE_MARGIN: Duration = Duration::from_millis(10);
+
+/// Lyrics of the playing track, following along when they are synchronized.
+pub struct LyricsView {
+    playback: Model<Playback>,
+    // file the lyrics are for
+    file: Option<String>,
+    lyrics: Option<Lyrics>,
+    line: Option<usize>,
+    position: Duration,
+    scroll: ScrollHandle,
+    // wakes the view when the next line or word is due
+    wake: Option<Task<()>>,
+}
+
+impl LyricsView {
+    pub fn new(cx: &mut WindowContext, playback: Model<Playback>) -> View<Self> {
+        cx.new_view(|cx| {
+            cx.observe(&playback, |this: &mut Self, _, cx| this.follow(cx))
+                .detach();
+
+            let mut this = Self {
+                playback,
+                file: None,
+                lyrics: None,
+                line: None,
+                position: Duration::ZERO,
+                scroll: ScrollHandle::new(),
+                wake: None,
+            };
+            this.follow(cx);
+            this
+        })
+    }
+
+    /// Catches up with the playing track and position.
+    fn follow(&mut self, cx: &mut ViewContext<Self>) {
+        let playback = self.playback.read(cx);
+        let file = playback.current().and_then(|track| track.file.clone());
+        let position = playback.position();
+        let (playing, speed) = (playback.is_playing(), playback.speed());
+
+        if file != self.file {
+            self.file = file.clone();
+            self.lyrics = None;
+            self.line = None;
+            if let Some(file) = file {
+                self.load(file, cx);
+            }
+            cx.notify();
+        }
+
+        let Some(lyrics) = &self.lyrics else {
+            self.wake = None;
+            return;
+        };
+        let line = lyrics.line_at(position);
+        if line != self.line {
+            self.line = line;
+            if let Some(line) = line {
+                self.scroll.scroll_to_item(line);
+            }
+            cx.notify();
+        } else if position != self.position
+            && line.is_some_and(|line| !lyrics.lines[line].words.is_empty())
+        {
+            // words light up within the line
+            cx.notify();
+        }
+        self.position = position;
+
+        // playback does not notify as the position moves
+        let next = lyrics.next_start(position).filter(|_| playing);
+        self.wake = next.map(|next| {
+            let wait = (next - position).div_f32(speed) + WAKE_MARGIN;
+            cx.spawn(|this, mut cx| async move {
+                cx.background_executor().timer(wait).await;
+                this.update(&mut cx, |this, cx| this.follow(cx)).ok();
+            })
+        });
+    }
+
+    fn load(&mut self, file: String, cx: &mut ViewContext<Self>) {
+        cx.spawn(|this, mut cx| async move {
+            let lyrics = cx
+                .background_executor()
+                .spawn({
+                    let file = file.clone();
+                    async move { Lyrics::load(Path::new(&file)) }
+                })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                // another track may have started in the meantime
+                if this.file.as_ref() == Some(&file) {
+                    this.lyrics = lyrics;
+                    this.follow(cx);
+                    cx.notify();
+                }
+            })
+            .ok();
+        })
+        .detach();
+    }
+}
+
+impl Render for LyricsView {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (current, other) = (theme.text, theme.overlay1);
+
+        let e = div()
+            .id("lyrics")
+            .flex()
+            .flex_col()
+            .flex_grow()
+            .min_h_0()
+            .py_1()
+            .px_3()
+            .overflow_y_scroll()
+            .track_scroll(&self.scroll);
+
+        let Some(lyrics) = &self.lyrics else {
+            return e.when(self.file.is_some(), |e| {
+                e.text_sm().text_color(other).child("No lyrics")
+            });
+        };
+
+        let position = self.position;
+        let lines = lyrics
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(ix, line)| {
+                let is_current = self.line == Some(ix);
+                let e = div()
+                    .id(ElementId::Integer(ix))
+                    .flex()
+                    .flex_wrap()
+                    .min_h_4()
+                    .text_color(if is_current { current } else { other });
+
+                let e = if is_current && !line.words.is_empty() {
+                    e.children(line.words.iter().map(|word| {
+                        div()
+                            .text_color(if word.start <= position {
+                                current
+                            } else {
+                                other
+                            })
+                            .child(word.text.clone())
+                    }))
+                } else {
+                    e.child(line.text.clone())
+                };
+
+                match line.start {
+                    Some(start) => e.on_click(cx.listener(move |_, _, cx| {
+                        cx.emit(Arc::new(UiEvent::PositionChanged(start)))
+                    })),
+                    None => e,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        e.children(lines)
+    }
+}
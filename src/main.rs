@@ -1,11 +1,26 @@
 mod app;
 mod asserts;
 mod browse;
+mod cli;
+mod duplicates;
 mod events;
+mod history;
+mod input;
+mod lyrics;
 mod menu;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod naming;
+mod organizer;
 mod playback;
 mod playing;
+mod playlists;
+#[cfg(unix)]
+mod remote;
 mod root;
+mod settings;
+mod storage;
+mod tag_editor;
 mod theme;
 mod window;
 
@@ -14,5 +29,10 @@ mod metadata;
 fn main() {
     env_logger::init();
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if cli::run(&args) {
+        return;
+    }
+
     app::run_app();
 }
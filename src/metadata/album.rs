@@ -1,16 +1,17 @@
 use std::{hash::Hash, sync::Arc};
 
-use gpui::{div, ElementId, InteractiveElement, IntoElement, ParentElement, Render, Styled};
-use lofty::picture::Picture;
+use gpui::{div, img, ElementId, InteractiveElement, IntoElement, ParentElement, Render, Styled};
 
-use super::track::Track;
+use super::{
+    cover::{self, CoverSize},
+    track::Track,
+};
 
 pub struct Album {
     title: String,
     artist: String,
     year: u32,
     tracks: Vec<Arc<Track>>,
-    picture: Option<Picture>,
 }
 
 impl Hash for Album {
@@ -30,11 +31,26 @@ impl Eq for Album {}
 
 impl Render for Album {
     fn render(&mut self, cx: &mut gpui::ViewContext<Self>) -> impl gpui::IntoElement {
-        div()
+        // the cover of the first track that has one stands for the album
+        let track = self
+            .tracks
+            .iter()
+            .find(|track| track.cover.is_some())
+            .or(self.tracks.first())
+            .cloned();
+        let cover = track
+            .as_ref()
+            .and_then(|track| cover::cover(track, CoverSize::Grid, cx));
+
+        let e = div()
             .id(ElementId::Name(self.title.clone().into_element()))
-            .size_64()
-            .child(div().child("Cover"))
-            .child(div().child(self.title.clone()))
+            .size_64();
+        let e = match (cover, &track) {
+            (Some(cover), _) => e.child(img(cover).size_64()),
+            (None, Some(track)) => e.child(cover::placeholder(track, cx).size_64()),
+            (None, None) => e,
+        };
+        e.child(div().child(self.title.clone()))
             .child(div().child(self.artist.clone()))
     }
 }
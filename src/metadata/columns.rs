@@ -0,0 +1,429 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use super::{database::Database, search::fold, track::Track};
+
+const MIN_WIDTH: f32 = 32.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    Title,
+    Artist,
+    Album,
+    TrackNumber,
+    Duration,
+    Year,
+    Genre,
+    Bitrate,
+    PlayCount,
+    DateAdded,
+    Rating,
+    Loved,
+}
+
+impl Column {
+    pub const ALL: [Column; 12] = [
+        Column::Title,
+        Column::Artist,
+        Column::Album,
+        Column::TrackNumber,
+        Column::Duration,
+        Column::Year,
+        Column::Genre,
+        Column::Bitrate,
+        Column::PlayCount,
+        Column::DateAdded,
+        Column::Rating,
+        Column::Loved,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Column::Title => "Title",
+            Column::Artist => "Artist",
+            Column::Album => "Album",
+            Column::TrackNumber => "#",
+            Column::Duration => "Time",
+            Column::Year => "Year",
+            Column::Genre => "Genre",
+            Column::Bitrate => "Bitrate",
+            Column::PlayCount => "Plays",
+            Column::DateAdded => "Added",
+            Column::Rating => "Rating",
+            Column::Loved => "Loved",
+        }
+    }
+
+    fn default_width(self) -> f32 {
+        match self {
+            Column::Title | Column::Artist | Column::Album => 200.,
+            Column::Genre => 120.,
+            Column::DateAdded | Column::Rating => 100.,
+            Column::TrackNumber => 40.,
+            _ => 64.,
+        }
+    }
+
+    fn shown_by_default(self) -> bool {
+        !matches!(
+            self,
+            Column::Genre
+                | Column::Bitrate
+                | Column::DateAdded
+                | Column::TrackNumber
+                | Column::Loved
+        )
+    }
+
+    pub fn cell(self, track: &Track, database: Option<&Database>) -> String {
+        let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+        match self {
+            Column::Title => track.title.clone(),
+            Column::Artist => track.artist.clone(),
+            Column::Album => track.album.clone(),
+            Column::TrackNumber => number(track.track_number),
+            Column::Duration => {
+                let secs = track.duration().as_secs();
+                format!("{}:{:02}", secs / 60, secs % 60)
+            }
+            Column::Year => number(track.year),
+            Column::Genre => track.genre.clone().unwrap_or_default(),
+            Column::Bitrate => track
+                .bitrate
+                .map(|kbps| format!("{} kbps", kbps))
+                .unwrap_or_default(),
+            Column::PlayCount => database
+                .map(|database| database.play_count(track).to_string())
+                .unwrap_or_default(),
+            Column::DateAdded => database
+                .and_then(|database| database.added(track))
+                .map(|added| {
+                    let days = added / (24 * 60 * 60);
+                    format_date(days)
+                })
+                .unwrap_or_default(),
+            Column::Rating => database
+                .map(|database| stars(database.rating(track)))
+                .unwrap_or_default(),
+            Column::Loved => match database {
+                Some(database) if database.is_loved(track) => "♥".to_string(),
+                _ => String::new(),
+            },
+        }
+    }
+
+    /// What `track` is sorted by in this column. Text columns use their
+    /// sort tags where present.
+    fn sort_value(self, track: &Track, database: Option<&Database>) -> SortValue {
+        let text = |tag: &Option<String>, fallback: &str| {
+            SortValue::Text(fold(tag.as_deref().unwrap_or(fallback)))
+        };
+        let number = |n: Option<u32>| SortValue::Number(n.map(u64::from));
+        match self {
+            Column::Title => text(&track.title_sort, &track.title),
+            Column::Artist => text(&track.artist_sort, &track.artist),
+            // keep albums in disc order
+            Column::Album => SortValue::Album(
+                fold(track.album_sort.as_deref().unwrap_or(&track.album)),
+                track.disc_number,
+                track.track_number,
+            ),
+            Column::TrackNumber => number(track.track_number),
+            Column::Duration => SortValue::Number(Some(track.duration().as_millis() as u64)),
+            Column::Year => number(track.year),
+            Column::Genre => text(&track.genre, ""),
+            Column::Bitrate => number(track.bitrate),
+            Column::PlayCount => number(Some(
+                database.map_or(0, |database| database.play_count(track)),
+            )),
+            Column::DateAdded => {
+                SortValue::Number(database.and_then(|database| database.added(track)))
+            }
+            Column::Rating => number(Some(
+                database.map_or(0, |database| database.rating(track).into()),
+            )),
+            Column::Loved => number(Some(
+                database
+                    .is_some_and(|database| database.is_loved(track))
+                    .into(),
+            )),
+        }
+    }
+}
+
+/// A track's value in one column, worked out once per sort rather than
+/// on every comparison. A column always gives the same variant.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Text(String),
+    Number(Option<u64>),
+    /// Album, disc and track number.
+    Album(String, Option<u32>, Option<u32>),
+}
+
+/// `★★★☆☆` for 3 out of 5 stars.
+pub fn stars(rating: u8) -> String {
+    let rating = usize::from(rating.min(5));
+    format!("{}{}", "★".repeat(rating), "☆".repeat(5 - rating))
+}
+
+/// `YYYY-MM-DD` from days since the unix epoch.
+fn format_date(days: u64) -> String {
+    // civil from days, Howard Hinnant's algorithm
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnState {
+    pub column: Column,
+    pub width: f32,
+    pub visible: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub column: Column,
+    pub ascending: bool,
+}
+
+/// How track lists are shown, kept in the settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackLayout {
+    /// Columns instead of the plain list.
+    pub table: bool,
+    /// In display order.
+    pub columns: Vec<ColumnState>,
+    /// Most significant first.
+    pub sort: Vec<SortKey>,
+}
+
+impl Default for TrackLayout {
+    fn default() -> Self {
+        Self {
+            table: false,
+            columns: Column::ALL
+                .into_iter()
+                .map(|column| ColumnState {
+                    column,
+                    width: column.default_width(),
+                    visible: column.shown_by_default(),
+                })
+                .collect(),
+            sort: Vec::new(),
+        }
+    }
+}
+
+impl TrackLayout {
+    /// Appends columns added since the layout was saved.
+    pub fn add_missing_columns(&mut self) {
+        for column in Column::ALL {
+            if !self.columns.iter().any(|state| state.column == column) {
+                self.columns.push(ColumnState {
+                    column,
+                    width: column.default_width(),
+                    visible: false,
+                });
+            }
+        }
+    }
+
+    /// Sorts by `column`, or flips it if it already is the main key. With
+    /// `add`, `column` becomes an extra key after the existing ones instead.
+    pub fn sort_by(&mut self, column: Column, add: bool) {
+        let existing = self.sort.iter().position(|key| key.column == column);
+        match (existing, add) {
+            (Some(0), false) | (Some(_), true) => {
+                let key = &mut self.sort[existing.unwrap()];
+                key.ascending = !key.ascending;
+            }
+            (_, false) => {
+                self.sort = vec![SortKey {
+                    column,
+                    ascending: true,
+                }]
+            }
+            (None, true) => self.sort.push(SortKey {
+                column,
+                ascending: true,
+            }),
+        }
+    }
+
+    pub fn resize(&mut self, index: usize, width: f32) {
+        if let Some(state) = self.columns.get_mut(index) {
+            state.width = width.max(MIN_WIDTH);
+        }
+    }
+
+    pub fn move_column(&mut self, from: usize, to: usize) {
+        if from < self.columns.len() && to < self.columns.len() && from != to {
+            let state = self.columns.remove(from);
+            self.columns.insert(to, state);
+        }
+    }
+
+    pub fn toggle(&mut self, column: Column) {
+        if let Some(state) = self.columns.iter_mut().find(|s| s.column == column) {
+            state.visible = !state.visible;
+        }
+    }
+
+    /// Indices into `tracks` in sort order, `None` when unsorted.
+    pub fn order(&self, tracks: &[Arc<Track>], database: Option<&Database>) -> Option<Vec<usize>> {
+        if self.sort.is_empty() {
+            return None;
+        }
+
+        let values = tracks
+            .iter()
+            .map(|track| {
+                self.sort
+                    .iter()
+                    .map(|key| key.column.sort_value(track, database))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut order = (0..tracks.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| {
+            let keys = self.sort.iter().zip(values[a].iter().zip(&values[b]));
+            keys.fold(Ordering::Equal, |ordering, (key, (a, b))| {
+                ordering.then_with(|| {
+                    let ordering = a.cmp(b);
+                    if key.ascending {
+                        ordering
+                    } else {
+                        ordering.reverse()
+                    }
+                })
+            })
+        });
+        Some(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    fn wav(dir: &Path, name: &str) -> Track {
+        let path = dir.join(name);
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        writer.write_sample(0i16).unwrap();
+        writer.finalize().unwrap();
+        Track::read(&path).unwrap()
+    }
+
+    fn key(column: Column, ascending: bool) -> SortKey {
+        SortKey { column, ascending }
+    }
+
+    fn layout(sort: Vec<SortKey>) -> TrackLayout {
+        TrackLayout {
+            sort,
+            ..TrackLayout::default()
+        }
+    }
+
+    #[test]
+    fn flips_the_main_key_and_adds_extra_ones() {
+        let mut layout = layout(Vec::new());
+
+        layout.sort_by(Column::Artist, false);
+        assert_eq!(layout.sort, [key(Column::Artist, true)]);
+        layout.sort_by(Column::Artist, false);
+        assert_eq!(layout.sort, [key(Column::Artist, false)]);
+
+        layout.sort_by(Column::Year, true);
+        assert_eq!(
+            layout.sort,
+            [key(Column::Artist, false), key(Column::Year, true)]
+        );
+        // an extra key flips in place
+        layout.sort_by(Column::Year, true);
+        assert_eq!(
+            layout.sort,
+            [key(Column::Artist, false), key(Column::Year, false)]
+        );
+
+        // without add, an extra key becomes the only one
+        layout.sort_by(Column::Year, false);
+        assert_eq!(layout.sort, [key(Column::Year, true)]);
+    }
+
+    #[test]
+    fn moves_columns_within_bounds() {
+        let columns = |layout: &TrackLayout| {
+            layout
+                .columns
+                .iter()
+                .map(|state| state.column)
+                .collect::<Vec<_>>()
+        };
+        let mut layout = TrackLayout::default();
+
+        layout.move_column(0, 2);
+        assert_eq!(
+            columns(&layout)[..3],
+            [Column::Artist, Column::Album, Column::Title]
+        );
+        layout.move_column(2, 0);
+        assert_eq!(columns(&layout), Column::ALL);
+
+        layout.move_column(0, Column::ALL.len());
+        layout.move_column(Column::ALL.len(), 0);
+        assert_eq!(columns(&layout), Column::ALL);
+    }
+
+    #[test]
+    fn orders_by_every_key_in_turn() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = |name: &str, album: &str, disc, number, year| {
+            let mut track = wav(dir.path(), name);
+            track.album = album.to_string();
+            track.disc_number = disc;
+            track.track_number = Some(number);
+            track.year = Some(year);
+            Arc::new(track)
+        };
+        let tracks = [
+            track("a.wav", "Beta", Some(2), 1, 2001),
+            track("b.wav", "alpha", None, 2, 1999),
+            track("c.wav", "Beta", Some(1), 2, 2001),
+            track("d.wav", "Beta", Some(1), 1, 2000),
+            track("e.wav", "Álpha", None, 1, 1999),
+        ];
+
+        assert_eq!(layout(Vec::new()).order(&tracks, None), None);
+
+        // case and accents are folded, and albums keep disc then track order
+        let by_album = layout(vec![key(Column::Album, true)]);
+        assert_eq!(by_album.order(&tracks, None), Some(vec![4, 1, 3, 2, 0]));
+
+        let by_year = layout(vec![key(Column::Year, false), key(Column::Album, true)]);
+        assert_eq!(by_year.order(&tracks, None), Some(vec![2, 0, 3, 4, 1]));
+    }
+}
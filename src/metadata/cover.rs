@@ -0,0 +1,479 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    hash::Hasher,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{anyhow, Result};
+use gpui::{
+    div, AppContext, Context, Div, Global, ImageData, Model, ParentElement, Styled, WindowContext,
+};
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat};
+use lofty::{
+    file::TaggedFileExt,
+    picture::{MimeType, Picture, PictureType},
+    probe::Probe,
+    tag::Tag,
+};
+use log::error;
+use lru::LruCache;
+
+use crate::{storage, theme::Theme};
+
+use super::{tags, track::Track};
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+const JPEG_QUALITY: u8 = 90;
+const THUMBNAIL_SIZES: [CoverSize; 2] = [CoverSize::List, CoverSize::Grid];
+// decoded thumbnails kept in memory, the least recently shown go first
+const THUMBNAIL_LIMIT: usize = 1024;
+
+/// FNV-1a, which unlike `DefaultHasher` hashes the same on every run and
+/// with every Rust release, for hashes that are kept.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Identifies a cover by its content, so tracks of an album sharing the same
+/// picture share one thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CoverId(u64);
+
+impl CoverId {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hasher = Fnv1a::default();
+        hasher.write(bytes);
+        Self(hasher.finish())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverSource {
+    /// In the tags of this file.
+    Embedded(Arc<Path>),
+    /// An image next to the tracks, like `cover.jpg`.
+    Sidecar(Arc<Path>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cover {
+    pub id: CoverId,
+    pub source: CoverSource,
+}
+
+impl Cover {
+    pub fn embedded(file: &Path, bytes: &[u8]) -> Self {
+        Self {
+            id: CoverId::of(bytes),
+            source: CoverSource::Embedded(file.into()),
+        }
+    }
+
+    /// The image in `dir` named after the earliest of `names`, ignoring case
+    /// and extension.
+    pub fn sidecar(dir: &Path, names: &[String]) -> Option<Self> {
+        if names.is_empty() {
+            return None;
+        }
+
+        let images = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+                    && path.is_file()
+            })
+            .collect::<Vec<_>>();
+        let path = names.iter().find_map(|name| {
+            images.iter().find(|path| {
+                path.file_stem()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|stem| stem.eq_ignore_ascii_case(name))
+            })
+        })?;
+
+        // keyed by path and modification time, so a replaced image is picked up
+        let modified = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let key = format!("{}:{}", path.display(), modified);
+        Some(Self {
+            id: CoverId::of(key.as_bytes()),
+            source: CoverSource::Sidecar(path.as_path().into()),
+        })
+    }
+
+    fn bytes(&self) -> Result<Vec<u8>> {
+        match &self.source {
+            CoverSource::Embedded(file) => embedded_picture(file),
+            CoverSource::Sidecar(path) => Ok(fs::read(path)?),
+        }
+    }
+}
+
+/// Gives tracks without embedded art the sidecar image of their directory,
+/// looking into each directory once.
+pub fn add_sidecar_covers(tracks: &mut [Track], names: &[String]) {
+    let mut found = HashMap::<PathBuf, Option<Cover>>::new();
+    for track in tracks.iter_mut().filter(|track| track.cover.is_none()) {
+        let Some(dir) = track
+            .file
+            .as_deref()
+            .and_then(|file| Path::new(file).parent())
+        else {
+            continue;
+        };
+        track.cover = found
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| Cover::sidecar(dir, names))
+            .clone();
+    }
+}
+
+/// Stands in for a missing or loading cover: the album's initial on a colour
+/// picked from its name. Sized by the caller.
+pub fn placeholder(track: &Track, cx: &WindowContext) -> Div {
+    let theme = cx.global::<Theme>();
+    let colors = [
+        theme.flamingo,
+        theme.pink,
+        theme.mauve,
+        theme.red,
+        theme.maroon,
+        theme.peach,
+        theme.yellow,
+        theme.green,
+        theme.teal,
+        theme.sky,
+        theme.sapphire,
+        theme.blue,
+        theme.lavender,
+    ];
+
+    let name = match track.album.as_str() {
+        "" | "UNKNOWN" => &track.title,
+        album => album,
+    };
+    let color = colors[(CoverId::of(name.as_bytes()).0 % colors.len() as u64) as usize];
+    let initial = name
+        .chars()
+        .find(|c| c.is_alphanumeric())
+        .map(|c| c.to_uppercase().to_string())
+        .unwrap_or_default();
+
+    div()
+        .flex()
+        .flex_none()
+        .items_center()
+        .justify_center()
+        .bg(color)
+        .text_color(theme.base)
+        .child(initial)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoverSize {
+    List,
+    Grid,
+    /// As embedded, only kept for the playing track.
+    Full,
+}
+
+impl CoverSize {
+    /// Edge of the thumbnail in pixels, `None` for full size.
+    fn pixels(self) -> Option<u32> {
+        match self {
+            CoverSize::List => Some(64),
+            CoverSize::Grid => Some(256),
+            CoverSize::Full => None,
+        }
+    }
+}
+
+/// The front cover. Other pictures are left alone, as [`remove_embedded`]
+/// only removes this one.
+pub fn front_picture(tag: &Tag) -> Option<&Picture> {
+    tag.pictures()
+        .iter()
+        .find(|pic| pic.pic_type() == PictureType::CoverFront)
+}
+
+fn embedded_picture(file: &Path) -> Result<Vec<u8>> {
+    let tagged_file = Probe::open(file)?.read()?;
+    tagged_file
+        .primary_tag()
+        .and_then(front_picture)
+        .map(|pic| pic.data().to_vec())
+        .ok_or_else(|| anyhow!("no cover in {}", file.display()))
+}
+
+/// Reads `image` and makes a front cover of it, shrunk to fit in `max_size`
+/// pixels. JPEG and PNG images that already fit are embedded as they are.
+pub fn prepare_picture(image: &Path, max_size: u32) -> Result<Picture> {
+    let bytes = fs::read(image)?;
+    let format = image::guess_format(&bytes)?;
+    let decoded = image::load_from_memory_with_format(&bytes, format)?;
+    let fits = decoded.width() <= max_size && decoded.height() <= max_size;
+
+    let (mime_type, data) = match format {
+        ImageFormat::Jpeg if fits => (MimeType::Jpeg, bytes),
+        ImageFormat::Png if fits => (MimeType::Png, bytes),
+        _ => {
+            let resized = if fits {
+                decoded
+            } else {
+                decoded.thumbnail(max_size, max_size)
+            };
+            let mut data = Vec::new();
+            DynamicImage::ImageRgb8(resized.to_rgb8())
+                .write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+            (MimeType::Jpeg, data)
+        }
+    };
+    Ok(Picture::new_unchecked(
+        PictureType::CoverFront,
+        Some(mime_type),
+        None,
+        data,
+    ))
+}
+
+/// Makes `picture` the front cover of `file`, replacing any there is.
+pub fn embed(file: &Path, picture: &Picture) -> Result<()> {
+    tags::update(file, |tag| {
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(picture.clone());
+        Ok(())
+    })
+}
+
+/// Removes the front cover embedded in `file`, keeping any other pictures.
+pub fn remove_embedded(file: &Path) -> Result<()> {
+    tags::update(file, |tag| {
+        tag.remove_picture_type(PictureType::CoverFront);
+        Ok(())
+    })
+}
+
+/// Saves the picture embedded in `file` as `destination`.
+pub fn export_embedded(file: &Path, destination: &Path) -> Result<()> {
+    fs::write(destination, embedded_picture(file)?)?;
+    Ok(())
+}
+
+/// A file holding the picture of `cover` for other programs to show: the
+/// image itself for sidecars, a copy in the cache for embedded pictures.
+pub fn art_file(cover: &Cover) -> Result<PathBuf> {
+    let file = match &cover.source {
+        CoverSource::Sidecar(path) => return Ok(path.to_path_buf()),
+        CoverSource::Embedded(file) => file,
+    };
+    let path = storage::cache_dir()
+        .join("covers")
+        .join(format!("{:016x}-art", cover.id.0));
+    if !path.is_file() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, embedded_picture(file)?)?;
+    }
+    Ok(path)
+}
+
+fn thumbnail_path(id: CoverId, pixels: u32) -> PathBuf {
+    storage::cache_dir()
+        .join("covers")
+        .join(format!("{:016x}-{}.png", id.0, pixels))
+}
+
+fn save_thumbnail(image: &DynamicImage, path: &PathBuf) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    image.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Decodes `cover`, going through the thumbnail cache on disk for anything
+/// smaller than full size. Every thumbnail missing from the cache is made
+/// from the one decode, so other sizes do not read the file again.
+fn load(cover: &Cover, size: CoverSize) -> Result<ImageData> {
+    if let Some(pixels) = size.pixels() {
+        if let Ok(image) = image::open(thumbnail_path(cover.id, pixels)) {
+            return Ok(ImageData::new(image.into_bgra8()));
+        }
+    }
+
+    let image = image::load_from_memory(&cover.bytes()?)?;
+    let mut requested = None;
+    for thumbnail_size in THUMBNAIL_SIZES {
+        let Some(pixels) = thumbnail_size.pixels() else {
+            continue;
+        };
+        let path = thumbnail_path(cover.id, pixels);
+        if thumbnail_size != size && path.is_file() {
+            continue;
+        }
+        let thumbnail = image.thumbnail(pixels, pixels);
+        if let Err(err) = save_thumbnail(&thumbnail, &path) {
+            error!("Failed to cache cover {}: {}", path.display(), err);
+        }
+        if thumbnail_size == size {
+            requested = Some(thumbnail);
+        }
+    }
+    Ok(ImageData::new(requested.unwrap_or(image).into_bgra8()))
+}
+
+/// Covers decoded so far, loaded in the background the first time they are asked for.
+pub struct Covers {
+    thumbnails: LruCache<(CoverId, CoverSize), Arc<ImageData>>,
+    full: Option<(CoverId, Arc<ImageData>)>,
+    // loaded, being loaded or failed, so each is only tried once
+    requested: HashSet<(CoverId, CoverSize)>,
+}
+
+impl Default for Covers {
+    fn default() -> Self {
+        Self {
+            thumbnails: LruCache::new(
+                NonZeroUsize::new(THUMBNAIL_LIMIT).expect("the limit is not zero"),
+            ),
+            full: None,
+            requested: HashSet::new(),
+        }
+    }
+}
+
+impl Covers {
+    fn get(&mut self, id: CoverId, size: CoverSize) -> Option<Arc<ImageData>> {
+        match size {
+            CoverSize::Full => self
+                .full
+                .as_ref()
+                .filter(|(full, _)| *full == id)
+                .map(|(_, image)| Arc::clone(image)),
+            _ => self.thumbnails.get(&(id, size)).cloned(),
+        }
+    }
+
+    fn insert(&mut self, id: CoverId, size: CoverSize, image: ImageData) {
+        let image = Arc::new(image);
+        match size {
+            CoverSize::Full => {
+                if let Some((previous, _)) = self.full.replace((id, image)) {
+                    self.requested.remove(&(previous, CoverSize::Full));
+                }
+            }
+            _ => {
+                // a thumbnail pushed out is loaded again if it is shown again
+                if let Some((evicted, _)) = self.thumbnails.push((id, size), image) {
+                    if evicted != (id, size) {
+                        self.requested.remove(&evicted);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CoversModel(Model<Covers>);
+
+impl CoversModel {
+    pub fn init(cx: &mut WindowContext) -> Self {
+        let this = Self(cx.new_model(|_| Covers::default()));
+        cx.set_global(this.clone());
+        this
+    }
+
+    pub fn model(&self) -> &Model<Covers> {
+        &self.0
+    }
+
+    pub fn read<'a>(&self, cx: &'a AppContext) -> &'a Covers {
+        self.0.read(cx)
+    }
+
+    /// The cover of `track` at `size`, if it has one and it is loaded. Starts
+    /// loading it otherwise; observers of the model are notified once it is.
+    pub fn cover(
+        &self,
+        track: &Track,
+        size: CoverSize,
+        cx: &mut WindowContext,
+    ) -> Option<Arc<ImageData>> {
+        let cover = track.cover.as_ref()?;
+        let image = self.0.update(cx, |covers, _| covers.get(cover.id, size));
+        if image.is_none() {
+            self.load(cover.clone(), size, cx);
+        }
+        image
+    }
+
+    fn load(&self, cover: Cover, size: CoverSize, cx: &mut WindowContext) {
+        let id = cover.id;
+        let first = self
+            .0
+            .update(cx, |covers, _| covers.requested.insert((id, size)));
+        if !first {
+            return;
+        }
+
+        let model = self.0.clone();
+        cx.spawn(|mut cx| async move {
+            let image = cx
+                .background_executor()
+                .spawn({
+                    let cover = cover.clone();
+                    async move { load(&cover, size) }
+                })
+                .await;
+
+            match image {
+                Ok(image) => {
+                    let _ = model.update(&mut cx, |covers, cx| {
+                        covers.insert(id, size, image);
+                        cx.notify();
+                    });
+                }
+                Err(err) => error!("Failed to load cover {:?}: {}", cover.source, err),
+            }
+        })
+        .detach();
+    }
+}
+
+impl Global for CoversModel {}
+
+/// Shorthand for asking the global covers model.
+pub fn cover(track: &Track, size: CoverSize, cx: &mut WindowContext) -> Option<Arc<ImageData>> {
+    let covers = cx.try_global::<CoversModel>()?.clone();
+    covers.cover(track, size, cx)
+}
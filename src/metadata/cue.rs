@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use super::track::{Segment, Track};
+
+// cue sheet times count frames of 1/75 s
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// `INDEX 01`, where the track starts in its file.
+    pub start: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueFile {
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub files: Vec<CueFile>,
+}
+
+/// `mm:ss:ff`.
+fn parse_time(text: &str) -> Option<Duration> {
+    let mut parts = text.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    let frames = (minutes * 60 + seconds) * FRAMES_PER_SECOND + frames;
+    Some(Duration::from_micros(
+        frames * 1_000_000 / FRAMES_PER_SECOND,
+    ))
+}
+
+/// A quoted value, or the first word of an unquoted one.
+fn value(text: &str) -> String {
+    let text = text.trim();
+    match text.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => text
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// The value of a `TITLE` or `PERFORMER` line, quoted or not.
+fn text_value(text: &str) -> String {
+    let text = text.trim();
+    match text.strip_prefix('"') {
+        Some(quoted) => quoted.rsplit_once('"').map_or(quoted, |(inside, _)| inside),
+        None => text,
+    }
+    .to_string()
+}
+
+impl CueSheet {
+    pub fn parse(text: &str) -> Self {
+        let mut sheet = Self::default();
+        // lines apply to the sheet until the first `TRACK`
+        let mut in_track = false;
+        let mut is_audio = true;
+
+        for line in text.lines() {
+            let line = line.trim().trim_start_matches('\u{feff}');
+            let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+            match command.to_ascii_uppercase().as_str() {
+                "REM" => {
+                    let (key, rest) = rest.trim().split_once(' ').unwrap_or((rest, ""));
+                    match key.to_ascii_uppercase().as_str() {
+                        "GENRE" => sheet.genre = Some(text_value(rest)),
+                        "DATE" => sheet.year = rest.trim().get(..4).and_then(|y| y.parse().ok()),
+                        _ => {}
+                    }
+                }
+                "FILE" => {
+                    sheet.files.push(CueFile {
+                        name: value(rest),
+                        tracks: Vec::new(),
+                    });
+                    in_track = false;
+                }
+                "TRACK" => {
+                    let mut words = rest.split_whitespace();
+                    let number = words.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+                    is_audio = words
+                        .next()
+                        .is_some_and(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                    in_track = true;
+                    if !is_audio {
+                        continue;
+                    }
+                    if sheet.files.is_empty() {
+                        sheet.files.push(CueFile::default());
+                    }
+                    if let Some(file) = sheet.files.last_mut() {
+                        file.tracks.push(CueTrack {
+                            number,
+                            ..CueTrack::default()
+                        });
+                    }
+                }
+                "INDEX" if in_track && is_audio => {
+                    let mut words = rest.split_whitespace();
+                    if words.next().and_then(|n| n.parse::<u32>().ok()) == Some(1) {
+                        let start = words.next().and_then(parse_time);
+                        if let Some(track) = sheet.last_track() {
+                            track.start = start;
+                        }
+                    }
+                }
+                "TITLE" | "PERFORMER" => {
+                    let text = Some(text_value(rest));
+                    let title = command.eq_ignore_ascii_case("TITLE");
+                    match (in_track, title) {
+                        (false, true) => sheet.title = text,
+                        (false, false) => sheet.performer = text,
+                        (true, _) if !is_audio => {}
+                        (true, true) => {
+                            if let Some(track) = sheet.last_track() {
+                                track.title = text;
+                            }
+                        }
+                        (true, false) => {
+                            if let Some(track) = sheet.last_track() {
+                                track.performer = text;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        sheet
+    }
+
+    fn last_track(&mut self) -> Option<&mut CueTrack> {
+        self.files.last_mut()?.tracks.last_mut()
+    }
+
+    /// The entry for `path`, matched by name, then by name without
+    /// extension since sheets often still name the `.wav` a rip came from.
+    /// A sheet with a single file is taken to be about `path` either way.
+    fn file_for(&self, path: &Path) -> Option<&CueFile> {
+        let name = path.file_name()?.to_string_lossy();
+        let stem = path.file_stem()?.to_string_lossy();
+        let stem_of = |name: &str| {
+            Path::new(name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        };
+
+        self.files
+            .iter()
+            .find(|file| file.name == name)
+            .or_else(|| {
+                self.files
+                    .iter()
+                    .find(|file| stem_of(&file.name).as_deref() == Some(&*stem))
+            })
+            .or_else(|| match self.files.as_slice() {
+                [file] => Some(file),
+                _ => None,
+            })
+    }
+}
+
+/// `image.cue` or `image.flac.cue` next to the image.
+fn external(path: &Path) -> Option<String> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".cue");
+    [path.with_extension("cue"), PathBuf::from(appended)]
+        .iter()
+        .find_map(|cue| fs::read(cue).ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads the track at `path`, split into the tracks of its cue sheet if it
+/// has one, embedded or next to it. Files with a single track stay whole.
+pub fn read_tracks(path: &Path) -> Result<Vec<Track>> {
+    let (track, embedded) = Track::read_with_cue(path)?;
+    if !track.is_song() {
+        return Ok(vec![track]);
+    }
+    let Some(text) = embedded.or_else(|| external(path)) else {
+        return Ok(vec![track]);
+    };
+
+    let sheet = CueSheet::parse(&text);
+    let Some(file) = sheet.file_for(path) else {
+        return Ok(vec![track]);
+    };
+    let starts = file
+        .tracks
+        .iter()
+        .filter_map(|cue| cue.start.map(|start| (cue, start)))
+        .collect::<Vec<_>>();
+    if starts.len() < 2 {
+        return Ok(vec![track]);
+    }
+
+    let tracks = starts
+        .iter()
+        .enumerate()
+        .map(|(ix, (cue, start))| {
+            let segment = Segment {
+                start: *start,
+                end: starts.get(ix + 1).map(|(_, next)| *next),
+            };
+            let mut cut = track.cut(cue.number, segment);
+            cut.title = cue
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {}", cue.number));
+            if let Some(artist) = cue.performer.clone().or_else(|| sheet.performer.clone()) {
+                cut.artist = artist;
+            }
+            if let Some(album) = sheet.title.clone() {
+                cut.album = album;
+            }
+            cut.album_artist = sheet.performer.clone().or(cut.album_artist);
+            cut.genre = sheet.genre.clone().or(cut.genre);
+            cut.year = sheet.year.or(cut.year);
+            cut
+        })
+        .collect();
+    Ok(tracks)
+}
+
+/// Where a library key points after the files in `moved` were moved,
+/// following cue sheet tracks along with their image.
+pub fn relocated(file: &str, moved: &HashMap<String, String>) -> Option<String> {
+    if let Some(new) = moved.get(file) {
+        return Some(new.clone());
+    }
+    let (path, number) = file.rsplit_once('#')?;
+    moved.get(path).map(|new| format!("{}#{}", new, number))
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    const SHEET: &str = r#"REM GENRE "Jazz"
+REM DATE 1959
+PERFORMER "Miles Davis"
+TITLE "Kind of Blue"
+FILE "image.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "So What"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Freddie Freeloader"
+    PERFORMER "Miles Davis Sextet"
+    INDEX 00 00:00:50
+    INDEX 01 00:01:00
+  TRACK 03 AUDIO
+    TITLE "Blue in Green"
+    INDEX 01 00:01:37
+"#;
+
+    #[test]
+    fn parses_times_in_frames() {
+        assert_eq!(parse_time("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(parse_time("01:02:00"), Some(Duration::from_secs(62)));
+        // 37 of 75 frames
+        assert_eq!(
+            parse_time("00:01:37"),
+            Some(Duration::from_micros(1_493_333))
+        );
+        assert_eq!(parse_time("00:01"), None);
+        assert_eq!(parse_time("aa:00:00"), None);
+    }
+
+    #[test]
+    fn parses_sheet_and_tracks() {
+        let sheet = CueSheet::parse(SHEET);
+        assert_eq!(sheet.title.as_deref(), Some("Kind of Blue"));
+        assert_eq!(sheet.performer.as_deref(), Some("Miles Davis"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.year, Some(1959));
+
+        let [file] = sheet.files.as_slice() else {
+            panic!("expected one file, got {:?}", sheet.files);
+        };
+        assert_eq!(file.name, "image.wav");
+        let titles = file
+            .tracks
+            .iter()
+            .map(|track| track.title.as_deref().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["So What", "Freddie Freeloader", "Blue in Green"]);
+        assert_eq!(
+            file.tracks[1].performer.as_deref(),
+            Some("Miles Davis Sextet")
+        );
+    }
+
+    #[test]
+    fn starts_tracks_at_index_01() {
+        let sheet = CueSheet::parse(SHEET);
+        let starts = sheet.files[0]
+            .tracks
+            .iter()
+            .map(|track| track.start)
+            .collect::<Vec<_>>();
+        // the pregap in `INDEX 00` is left to the track before
+        assert_eq!(
+            starts,
+            [
+                Some(Duration::ZERO),
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_micros(1_493_333)),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_tracks_with_their_files() {
+        let sheet = CueSheet::parse(
+            "FILE \"one.flac\" WAVE\n\
+             TRACK 01 AUDIO\n\
+             INDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\n\
+             INDEX 01 03:00:00\n\
+             FILE \"two.flac\" WAVE\n\
+             TRACK 03 AUDIO\n\
+             INDEX 01 00:00:00\n\
+             TRACK 04 MODE1/2352\n\
+             INDEX 01 01:00:00\n",
+        );
+        let files = sheet
+            .files
+            .iter()
+            .map(|file| {
+                let numbers = file.tracks.iter().map(|track| track.number).collect();
+                (file.name.as_str(), numbers)
+            })
+            .collect::<Vec<(_, Vec<_>)>>();
+        // data tracks are left out
+        assert_eq!(files, [("one.flac", vec![1, 2]), ("two.flac", vec![3])]);
+
+        let two = sheet.file_for(Path::new("/music/two.wav")).unwrap();
+        assert_eq!(two.name, "two.flac");
+        assert!(sheet.file_for(Path::new("/music/three.flac")).is_none());
+    }
+
+    #[test]
+    fn last_track_runs_to_the_end_of_the_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&image, spec).unwrap();
+        for _ in 0..8000 * 3 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        fs::write(dir.path().join("image.cue"), SHEET).unwrap();
+
+        let tracks = read_tracks(&image).unwrap();
+        let cut = tracks
+            .iter()
+            .map(|track| (track.title.as_str(), track.duration(), track.segment))
+            .collect::<Vec<_>>();
+        let ms = Duration::from_millis;
+        let start = Duration::from_micros(1_493_333);
+        assert_eq!(cut[0].1, ms(1000));
+        assert_eq!(cut[1].1, start - ms(1000));
+        assert_eq!(cut[2].0, "Blue in Green");
+        assert_eq!(cut[2].1, ms(3000) - start);
+        assert_eq!(cut[2].2.and_then(|segment| segment.end), None);
+        assert_eq!(tracks[1].artist, "Miles Davis Sextet");
+        assert_eq!(tracks[2].album, "Kind of Blue");
+        assert!(tracks[2].file.as_ref().unwrap().ends_with("image.wav#3"));
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use gpui::{AppContext, Context, Global, Model, ModelContext, Task, WindowContext};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{events::PlaybackEvent, playback::Playback, storage};
+
+use super::{cue, library::LibraryModel, track::Track};
+
+const DATABASE_FILE: &str = "library.json";
+// a play counts once this much of a track was heard, even if it was skipped afterwards
+const MIN_PLAYED: Duration = Duration::from_secs(4 * 60);
+const HISTORY_LIMIT: usize = 10_000;
+const LIST_LIMIT: usize = 100;
+// plays, ratings and the like are written out this long after the last
+// change, not one by one
+const SAVE_DELAY: Duration = Duration::from_secs(30);
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackStats {
+    pub play_count: u32,
+    pub skip_count: u32,
+    /// Seconds since the unix epoch.
+    pub last_played: Option<u64>,
+    /// 0 to 5 stars, `None` to go by the rating in the tags.
+    pub rating: Option<u8>,
+    /// `None` to go by the tags.
+    pub loved: Option<bool>,
+    /// When the file was first seen in the library, in seconds since the
+    /// unix epoch.
+    pub added: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Play {
+    pub file: String,
+    /// When the track started, in seconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+/// Everything ggmusic knows about the library beyond the tags, keyed by file path.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Database {
+    pub stats: HashMap<String, TrackStats>,
+    /// Recorded plays, oldest first.
+    pub history: Vec<Play>,
+    /// Tracks kept out of the library views, like duplicates not worth deleting.
+    pub hidden: HashSet<String>,
+    #[serde(skip)]
+    started: Option<Play>,
+    #[serde(skip)]
+    pending_save: Option<Task<()>>,
+}
+
+impl Database {
+    pub fn load() -> Self {
+        storage::load(DATABASE_FILE)
+    }
+
+    pub fn stats(&self, track: &Track) -> Option<&TrackStats> {
+        track.file.as_ref().and_then(|file| self.stats.get(file))
+    }
+
+    pub fn is_hidden(&self, track: &Track) -> bool {
+        track
+            .file
+            .as_ref()
+            .is_some_and(|file| self.hidden.contains(file))
+    }
+
+    /// When `track` was first seen in the library.
+    pub fn added(&self, track: &Track) -> Option<u64> {
+        self.stats(track).and_then(|stats| stats.added)
+    }
+
+    /// Notes the files of `tracks` not seen before as added now, returning
+    /// whether there were any.
+    fn saw(&mut self, tracks: &[Arc<Track>]) -> bool {
+        let now = now();
+        let mut new = false;
+        for file in tracks.iter().filter_map(|track| track.file.as_ref()) {
+            match self.stats.get_mut(file) {
+                Some(stats) if stats.added.is_some() => continue,
+                Some(stats) => stats.added = Some(now),
+                None => {
+                    let stats = TrackStats {
+                        added: Some(now),
+                        ..Default::default()
+                    };
+                    self.stats.insert(file.clone(), stats);
+                }
+            }
+            new = true;
+        }
+        new
+    }
+
+    pub fn play_count(&self, track: &Track) -> u32 {
+        self.stats(track).map_or(0, |stats| stats.play_count)
+    }
+
+    /// 0 to 5 stars, as rated here or else in the tags.
+    pub fn rating(&self, track: &Track) -> u8 {
+        self.stats(track)
+            .and_then(|stats| stats.rating)
+            .or(track.rating)
+            .unwrap_or(0)
+    }
+
+    /// Loved here, or else in the tags.
+    pub fn is_loved(&self, track: &Track) -> bool {
+        self.stats(track)
+            .and_then(|stats| stats.loved)
+            .unwrap_or(track.loved)
+    }
+
+    pub fn most_played(&self, tracks: &[Arc<Track>]) -> Vec<Arc<Track>> {
+        let mut tracks = tracks
+            .iter()
+            .filter(|track| self.play_count(track) > 0)
+            .cloned()
+            .collect::<Vec<_>>();
+        tracks.sort_by_key(|track| std::cmp::Reverse(self.play_count(track)));
+        tracks.truncate(LIST_LIMIT);
+        tracks
+    }
+
+    pub fn recently_played(&self, tracks: &[Arc<Track>]) -> Vec<Arc<Track>> {
+        let last_played = |track: &Track| self.stats(track).and_then(|stats| stats.last_played);
+
+        let mut tracks = tracks
+            .iter()
+            .filter(|track| last_played(track).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        tracks.sort_by_key(|track| std::cmp::Reverse(last_played(track)));
+        tracks.truncate(LIST_LIMIT);
+        tracks
+    }
+
+    pub fn never_played(&self, tracks: &[Arc<Track>]) -> Vec<Arc<Track>> {
+        tracks
+            .iter()
+            .filter(|track| self.play_count(track) == 0)
+            .cloned()
+            .collect()
+    }
+
+    /// Starts the play of a track that was playing without having started,
+    /// as a session restored at startup does, `position` into it.
+    fn resumed(&mut self, track: Option<Arc<Track>>, position: Duration) {
+        if self.started.is_some() {
+            return;
+        }
+        self.started = track.and_then(|track| track.file.clone()).map(|file| Play {
+            file,
+            timestamp: now().saturating_sub(position.as_secs()),
+        });
+    }
+
+    /// Updates play statistics, returns whether anything changed.
+    fn record(&mut self, event: &PlaybackEvent) -> bool {
+        match event {
+            PlaybackEvent::TrackStarted(track) => {
+                self.started = track.file.clone().map(|file| Play {
+                    file,
+                    timestamp: now(),
+                });
+                false
+            }
+            PlaybackEvent::TrackEnded(event) => {
+                let Some(play) = self
+                    .started
+                    .take()
+                    .filter(|play| event.track.file.as_ref() == Some(&play.file))
+                else {
+                    return false;
+                };
+
+                // a track without a length is only played once something was heard
+                let duration = event.track.duration();
+                let played = !event.position.is_zero()
+                    && (event.completed
+                        || event.position >= MIN_PLAYED
+                        || (!duration.is_zero() && event.position >= duration / 2));
+
+                let stats = self.stats.entry(play.file.clone()).or_default();
+                if played {
+                    stats.play_count += 1;
+                    stats.last_played = Some(play.timestamp);
+
+                    self.history.push(play);
+                    if self.history.len() > HISTORY_LIMIT {
+                        self.history.drain(..self.history.len() - HISTORY_LIMIT);
+                    }
+                } else {
+                    stats.skip_count += 1;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Follows files moved from the first path to the second.
+    pub fn relocate(&mut self, moved: &[(String, String)]) {
+        let moved = moved.iter().cloned().collect::<HashMap<_, _>>();
+        self.stats = self
+            .stats
+            .drain()
+            .map(|(file, stats)| match cue::relocated(&file, &moved) {
+                Some(new) => (new, stats),
+                None => (file, stats),
+            })
+            .collect();
+        self.hidden = self
+            .hidden
+            .drain()
+            .map(|file| cue::relocated(&file, &moved).unwrap_or(file))
+            .collect();
+        for play in self.history.iter_mut().chain(&mut self.started) {
+            if let Some(new) = cue::relocated(&play.file, &moved) {
+                play.file = new;
+            }
+        }
+    }
+
+    fn save(&self) {
+        if let Err(err) = storage::save(DATABASE_FILE, self) {
+            error!("Failed to save library database: {}", err);
+        }
+    }
+
+    /// Saves a while from now, so that a run of plays rewrites the file once.
+    fn save_later(&mut self, cx: &mut ModelContext<Self>) {
+        self.pending_save = Some(cx.spawn(|this, mut cx| async move {
+            cx.background_executor().timer(SAVE_DELAY).await;
+            this.update(&mut cx, |database, _| database.save()).ok();
+        }));
+    }
+}
+
+#[derive(Clone)]
+pub struct DatabaseModel(Model<Database>);
+
+impl DatabaseModel {
+    pub fn init(
+        cx: &mut WindowContext,
+        library: &LibraryModel,
+        playback: &Model<Playback>,
+    ) -> Self {
+        let model = cx.new_model(|cx| {
+            cx.observe(library.model(), |database: &mut Database, library, cx| {
+                if database.saw(library.read(cx).tracks()) {
+                    database.save_later(cx);
+                    cx.notify();
+                }
+            })
+            .detach();
+
+            cx.subscribe(
+                playback,
+                |database, playback, event: &Arc<PlaybackEvent>, cx| {
+                    if let PlaybackEvent::Resumed = **event {
+                        let playback = playback.read(cx);
+                        database.resumed(playback.current(), playback.position());
+                    }
+                    if database.record(event) {
+                        database.save_later(cx);
+                        cx.notify();
+                    }
+                },
+            )
+            .detach();
+
+            cx.on_app_quit(|database, _| {
+                if database.pending_save.take().is_some() {
+                    database.save();
+                }
+                async {}
+            })
+            .detach();
+
+            let mut database = Database::load();
+            if database.saw(library.read(cx).tracks()) {
+                database.save_later(cx);
+            }
+            database
+        });
+
+        let this = Self(model);
+        cx.set_global(this.clone());
+        this
+    }
+
+    pub fn model(&self) -> &Model<Database> {
+        &self.0
+    }
+
+    pub fn read<'a>(&self, cx: &'a AppContext) -> &'a Database {
+        self.0.read(cx)
+    }
+
+    /// Moves the statistics of renamed files to their new paths.
+    pub fn relocate(&self, moved: &[(String, String)], cx: &mut AppContext) {
+        self.0.update(cx, |database, cx| {
+            database.relocate(moved);
+            database.save();
+            cx.notify();
+        });
+    }
+
+    /// Rates `track` 0 to 5 stars.
+    pub fn set_rating(&self, track: &Track, rating: u8, cx: &mut AppContext) {
+        let Some(file) = track.file.clone() else {
+            return;
+        };
+        self.0.update(cx, |database, cx| {
+            database.stats.entry(file).or_default().rating = Some(rating.min(5));
+            database.save_later(cx);
+            cx.notify();
+        });
+    }
+
+    /// Loves `track` or stops loving it, returning whether it is loved now.
+    pub fn toggle_loved(&self, track: &Track, cx: &mut AppContext) -> bool {
+        let Some(file) = track.file.clone() else {
+            return false;
+        };
+        self.0.update(cx, |database, cx| {
+            let loved = !database.is_loved(track);
+            database.stats.entry(file).or_default().loved = Some(loved);
+            database.save_later(cx);
+            cx.notify();
+            loved
+        })
+    }
+
+    /// Hides or shows again the tracks of `files`.
+    pub fn set_hidden(&self, files: &[String], hidden: bool, cx: &mut AppContext) {
+        self.0.update(cx, |database, cx| {
+            for file in files {
+                if hidden {
+                    database.hidden.insert(file.clone());
+                } else {
+                    database.hidden.remove(file);
+                }
+            }
+            database.save_later(cx);
+            cx.notify();
+        });
+    }
+}
+
+impl Global for DatabaseModel {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// A silent mono track `seconds` long.
+    fn silence(dir: &Path, name: &str, seconds: u32) -> Arc<Track> {
+        let path = dir.join(name);
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for _ in 0..seconds * spec.sample_rate {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        Arc::new(Track::read(&path).unwrap())
+    }
+
+    #[test]
+    fn keeps_the_date_a_file_was_first_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (
+            silence(dir.path(), "old.wav", 1),
+            silence(dir.path(), "new.wav", 1),
+        );
+        let mut database = Database::default();
+        database.stats.insert(
+            old.file.clone().unwrap(),
+            TrackStats {
+                added: Some(1),
+                ..Default::default()
+            },
+        );
+
+        assert!(database.saw(&[Arc::clone(&old), Arc::clone(&new)]));
+        assert_eq!(database.added(&old), Some(1));
+        let added = database.added(&new).unwrap();
+        assert!(added > 1);
+        assert!(!database.saw(&[old, Arc::clone(&new)]));
+        assert_eq!(database.added(&new), Some(added));
+    }
+
+    #[test]
+    fn counts_plays_and_skips() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = silence(dir.path(), "track.wav", 10);
+        let mut database = Database::default();
+
+        database.record(&PlaybackEvent::start(&track));
+        database.record(&PlaybackEvent::end(&track, Duration::from_secs(2), false));
+        database.record(&PlaybackEvent::start(&track));
+        database.record(&PlaybackEvent::end(&track, Duration::from_secs(6), false));
+
+        let stats = database.stats(&track).unwrap();
+        assert_eq!((stats.play_count, stats.skip_count), (1, 1));
+        assert_eq!(database.history.len(), 1);
+    }
+
+    #[test]
+    fn tracks_without_a_length_need_to_be_heard() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = silence(dir.path(), "empty.wav", 0);
+        assert!(track.duration().is_zero());
+        let mut database = Database::default();
+
+        database.record(&PlaybackEvent::start(&track));
+        database.record(&PlaybackEvent::end(&track, Duration::ZERO, true));
+        assert_eq!(database.play_count(&track), 0);
+
+        database.record(&PlaybackEvent::start(&track));
+        database.record(&PlaybackEvent::end(&track, Duration::from_secs(1), true));
+        assert_eq!(database.play_count(&track), 1);
+    }
+
+    #[test]
+    fn a_restored_track_counts_once_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = silence(dir.path(), "track.wav", 10);
+        let mut database = Database::default();
+
+        // a session restored at startup emits no start
+        database.resumed(Some(Arc::clone(&track)), Duration::from_secs(3));
+        database.record(&PlaybackEvent::end(&track, Duration::from_secs(10), true));
+        assert_eq!(database.play_count(&track), 1);
+        assert!(database.history[0].timestamp <= now() - 3);
+
+        // resuming a track that did start keeps its start
+        database.record(&PlaybackEvent::start(&track));
+        let started = database.started.as_ref().unwrap().timestamp;
+        database.resumed(Some(Arc::clone(&track)), Duration::from_secs(60));
+        assert_eq!(database.started.as_ref().unwrap().timestamp, started);
+    }
+}
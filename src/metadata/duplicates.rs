@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    hash::Hasher,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use log::error;
+use rayon::prelude::*;
+use rodio::Decoder;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::{
+    cover::Fnv1a,
+    fingerprint::{self, modified},
+    search::fold,
+    track::Track,
+};
+
+// named anew whenever the hash changes, so old hashes are not compared
+// with new ones
+const AUDIO_HASHES_FILE: &str = "audio_hashes-fnv1a.json";
+/// How far apart the lengths of copies of one recording may be, in seconds.
+pub const DURATION_TOLERANCE_SECS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The same decoded audio, whatever the tags say.
+    SameAudio,
+    /// The same recording by its acoustic fingerprint, in another encoding
+    /// or bitrate.
+    SameRecording,
+    /// The same recording by its fingerprint, but tagged as something else,
+    /// so one of them is likely tagged wrong.
+    TagsDisagree,
+    /// The same artist and title, give or take punctuation and remaster
+    /// notes, at about the same length.
+    Likely,
+}
+
+impl Kind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Kind::SameAudio => "Same audio",
+            Kind::SameRecording => "Same recording",
+            Kind::TagsDisagree => "Same recording, tags disagree",
+            Kind::Likely => "Likely the same",
+        }
+    }
+}
+
+pub struct Group {
+    pub kind: Kind,
+    pub tracks: Vec<Arc<Track>>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct AudioHash {
+    /// When the file was last changed, in seconds since the unix epoch.
+    modified: u64,
+    hash: u64,
+}
+
+/// Hashes of decoded audio from earlier scans, keyed by file path, so only
+/// new and changed files are decoded again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct AudioHashes(HashMap<String, AudioHash>);
+
+impl AudioHashes {
+    pub fn load() -> Self {
+        storage::load_from(&storage::cache_dir(), AUDIO_HASHES_FILE)
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = storage::save_to(&storage::cache_dir(), AUDIO_HASHES_FILE, self) {
+            error!("Failed to save audio hashes: {}", err);
+        }
+    }
+
+    /// The hash of `track`, decoding its file unless it is known and has
+    /// not changed since.
+    fn get(&self, track: &Track) -> Option<AudioHash> {
+        let file = track.file.as_ref()?;
+        let modified = modified(track);
+        if let Some(known) = self.0.get(file).filter(|known| known.modified == modified) {
+            return Some(*known);
+        }
+        let hash = audio_hash(Path::new(track.path()?)).ok()?;
+        Some(AudioHash { modified, hash })
+    }
+}
+
+/// Hashes the decoded samples of `path`, so that copies with different
+/// tags hash the same.
+fn audio_hash(path: &Path) -> Result<u64> {
+    let decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    let mut hasher = Fnv1a::default();
+    for sample in decoder {
+        hasher.write(&sample.to_le_bytes());
+    }
+    Ok(hasher.finish())
+}
+
+/// Folded text without bracketed notes like `(2011 Remaster)` or
+/// punctuation, for comparing titles and artists.
+fn normalize(text: &str) -> String {
+    let mut depth = 0usize;
+    let text = fold(text)
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth = depth.saturating_sub(1),
+                _ => return depth == 0 && (c.is_alphanumeric() || c.is_whitespace()),
+            }
+            false
+        })
+        .collect::<String>();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether every track in `tracks` is already in the same one of the
+/// `listed` groups.
+fn listed_together(tracks: &[&Arc<Track>], listed: &HashMap<String, usize>) -> bool {
+    let groups = tracks
+        .iter()
+        .map(|track| track.file.as_ref().and_then(|file| listed.get(file)))
+        .collect::<Vec<_>>();
+    groups[0].is_some() && groups.iter().all(|group| *group == groups[0])
+}
+
+/// Groups of tracks that look like copies of each other. Only files whose
+/// lengths match to the millisecond are compared by their decoded audio,
+/// using and updating `hashes`, `prints` holds the acoustic fingerprints
+/// known so far.
+pub fn find(
+    tracks: &[Arc<Track>],
+    prints: &HashMap<String, Vec<u32>>,
+    hashes: &mut AudioHashes,
+) -> Vec<Group> {
+    let mut groups = Vec::new();
+
+    // cue sheet tracks share a file with others, the file is compared
+    // instead; the same samples make for the same length, to the millisecond
+    let mut by_length = HashMap::<u128, Vec<&Arc<Track>>>::new();
+    for track in tracks.iter().filter(|track| track.segment.is_none()) {
+        by_length
+            .entry(track.duration().as_millis())
+            .or_default()
+            .push(track);
+    }
+    let candidates = by_length
+        .into_values()
+        .filter(|tracks| tracks.len() > 1)
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let known = &*hashes;
+    let hashed = candidates
+        .par_iter()
+        .filter_map(|track| Some((known.get(track)?, *track)))
+        .collect::<Vec<_>>();
+
+    // files that left the library are forgotten
+    let files = tracks
+        .iter()
+        .filter_map(|track| track.file.as_deref())
+        .collect::<HashSet<_>>();
+    hashes.0.retain(|file, _| files.contains(file.as_str()));
+    let mut by_hash = HashMap::<u64, Vec<Arc<Track>>>::new();
+    for (hash, track) in hashed {
+        if let Some(file) = track.file.clone() {
+            hashes.0.insert(file, hash);
+        }
+        by_hash
+            .entry(hash.hash)
+            .or_default()
+            .push(Arc::clone(track));
+    }
+    // which group a track is listed in
+    let mut listed = HashMap::<String, usize>::new();
+    for tracks in by_hash.into_values().filter(|tracks| tracks.len() > 1) {
+        for file in tracks.iter().filter_map(|track| track.file.clone()) {
+            listed.insert(file, groups.len());
+        }
+        groups.push(Group {
+            kind: Kind::SameAudio,
+            tracks,
+        });
+    }
+
+    for tracks in fingerprint::matches(tracks, prints) {
+        if listed_together(&tracks.iter().collect::<Vec<_>>(), &listed) {
+            continue;
+        }
+        let name = |track: &Track| (normalize(&track.artist), normalize(&track.title));
+        let kind = if tracks.iter().all(|track| name(track) == name(&tracks[0])) {
+            Kind::SameRecording
+        } else {
+            Kind::TagsDisagree
+        };
+        for file in tracks.iter().filter_map(|track| track.file.clone()) {
+            listed.entry(file).or_insert(groups.len());
+        }
+        groups.push(Group { kind, tracks });
+    }
+
+    let mut by_name = HashMap::<(String, String), Vec<&Arc<Track>>>::new();
+    for track in tracks {
+        let title = normalize(&track.title);
+        if !title.is_empty() {
+            by_name
+                .entry((normalize(&track.artist), title))
+                .or_default()
+                .push(track);
+        }
+    }
+    for mut named in by_name.into_values().filter(|tracks| tracks.len() > 1) {
+        named.sort_by_key(|track| track.duration());
+
+        // runs of tracks each close in length to the one before
+        let mut runs: Vec<Vec<&Arc<Track>>> = Vec::new();
+        for track in named {
+            match runs.last_mut() {
+                Some(run)
+                    if run.last().is_some_and(|last| {
+                        track.duration().as_secs() - last.duration().as_secs()
+                            <= DURATION_TOLERANCE_SECS
+                    }) =>
+                {
+                    run.push(track)
+                }
+                _ => runs.push(vec![track]),
+            }
+        }
+
+        for run in runs.into_iter().filter(|run| run.len() > 1) {
+            if listed_together(&run, &listed) {
+                continue;
+            }
+            groups.push(Group {
+                kind: Kind::Likely,
+                tracks: run.into_iter().cloned().collect(),
+            });
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// A mono WAV of `seconds` holding `sample` throughout, titled and by
+    /// the given artist.
+    fn wav(dir: &Path, name: &str, seconds: u32, sample: i16, title: &str) -> Arc<Track> {
+        let path = dir.join(name);
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for _ in 0..seconds * spec.sample_rate {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut track = Track::read(&path).unwrap();
+        track.artist = "Artist".to_string();
+        track.title = title.to_string();
+        Arc::new(track)
+    }
+
+    fn names(group: &Group) -> Vec<&str> {
+        group
+            .tracks
+            .iter()
+            .map(|track| track.title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_titles_for_comparison() {
+        for (text, normalized) in [
+            ("Song (2011 Remaster)", "song"),
+            ("Don't Stop!", "dont stop"),
+            ("Beyoncé – Halo [Live]", "beyonce halo"),
+            ("  Many   spaces ", "many spaces"),
+            ("Nested (a (b) c) end", "nested end"),
+            ("AC/DC", "acdc"),
+            ("(Intro)", ""),
+        ] {
+            assert_eq!(normalize(text), normalized, "{}", text);
+        }
+    }
+
+    #[test]
+    fn groups_same_audio_and_likely_copies_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracks = [
+            // the same samples, and by name the same song too
+            wav(dir.path(), "a.wav", 1, 0, "Song"),
+            wav(dir.path(), "b.wav", 1, 0, "Song (2011 Remaster)"),
+            // other audio of about the same length and name
+            wav(dir.path(), "c.wav", 1, 1000, "Other"),
+            wav(dir.path(), "d.wav", 2, 2000, "Other!"),
+            // the same name, but far too long to be a copy
+            wav(dir.path(), "e.wav", 10, 3000, "other"),
+        ];
+        let mut hashes = AudioHashes::default();
+
+        let groups = find(&tracks, &HashMap::new(), &mut hashes);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].kind, Kind::SameAudio);
+        let mut same = names(&groups[0]);
+        same.sort_unstable();
+        assert_eq!(same, ["Song", "Song (2011 Remaster)"]);
+        assert_eq!(groups[1].kind, Kind::Likely);
+        assert_eq!(names(&groups[1]), ["Other", "Other!"]);
+
+        // only files of equal length were decoded, and are known now
+        let mut hashed = hashes.0.keys().cloned().collect::<Vec<_>>();
+        hashed.sort_unstable();
+        let file = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        assert_eq!(hashed, [file("a.wav"), file("b.wav"), file("c.wav")]);
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use gpui::{AppContext, Context, Global, Model, WindowContext};
+use log::{error, warn};
+use rodio::Source;
+use rusty_chromaprint::{Configuration, Fingerprinter};
+
+use crate::{playback, storage};
+
+use super::{duplicates::DURATION_TOLERANCE_SECS, library::LibraryModel, track::Track};
+
+const FINGERPRINTS_FILE: &str = "fingerprints.bin";
+// like Chromaprint itself, only the start of a track is listened to
+const LENGTH: Duration = Duration::from_secs(120);
+// how many items one fingerprint may be shifted against another, items
+// cover about an eighth of a second each
+const MAX_OFFSET: isize = 24;
+// the share of equal bits above which two fingerprints are the same recording
+const MATCH_THRESHOLD: f64 = 0.85;
+// fingerprints worked out between saves
+const SAVE_EVERY: usize = 50;
+// items two fingerprints need in common before they are compared bit by bit
+const MIN_SHARED: usize = 8;
+// items found in more fingerprints than this, like silence, say nothing
+// about which recording a track is and are not indexed
+const MAX_POSTINGS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    /// When the file was last changed, in seconds since the unix epoch, to
+    /// notice when the fingerprint is out of date.
+    modified: u64,
+    pub data: Vec<u32>,
+}
+
+/// Acoustic fingerprints of the library tracks, keyed by file path. They
+/// stay the same across encodings and bitrates of a recording.
+///
+/// They are kept in a binary log that new fingerprints are appended to,
+/// later records of a file replacing earlier ones, and that is only
+/// rewritten once most of it is out of date.
+#[derive(Default)]
+pub struct Fingerprints {
+    prints: HashMap<String, Fingerprint>,
+    // records in the log, including replaced ones
+    records: usize,
+    running: bool,
+    // the library changed while fingerprints were being worked out
+    stale: bool,
+}
+
+pub fn modified(track: &Track) -> u64 {
+    track
+        .path()
+        .and_then(|path| fs::metadata(path).ok())
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Listens to the start of `track`, decoded the way playback does.
+pub fn compute(track: &Track) -> Result<Fingerprint> {
+    let modified = modified(track);
+    let source = playback::decode(track)?;
+    let (sample_rate, channels) = (source.sample_rate(), source.channels());
+
+    // a cue sheet track is only part of its file
+    let start = track.segment.map_or(Duration::ZERO, |s| s.start);
+    let length = track
+        .segment
+        .and_then(|s| s.end)
+        .map_or(LENGTH, |end| end.saturating_sub(start).min(LENGTH));
+    let mut samples = source.skip_duration(start).take_duration(length);
+
+    let mut printer = Fingerprinter::new(&Configuration::preset_test2());
+    printer
+        .start(sample_rate, channels as u32)
+        .map_err(|err| anyhow!("cannot fingerprint the audio format: {:?}", err))?;
+    let mut buffer = Vec::with_capacity(4096);
+    loop {
+        buffer.clear();
+        buffer.extend(samples.by_ref().take(4096));
+        if buffer.is_empty() {
+            break;
+        }
+        printer.consume(&buffer);
+    }
+    printer.finish();
+
+    Ok(Fingerprint {
+        modified,
+        data: printer.fingerprint().to_vec(),
+    })
+}
+
+/// The share of equal bits of `a` and `b` at the offset where they line up
+/// best, from 0 to 1.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    // too little overlap says nothing
+    let min_overlap = a.len().min(b.len()) / 2;
+    if min_overlap == 0 {
+        return 0.0;
+    }
+
+    (-MAX_OFFSET..=MAX_OFFSET)
+        .filter_map(|offset| {
+            let (a, b) = match offset {
+                o if o < 0 => (a.get(o.unsigned_abs()..)?, b),
+                o => (a, b.get(o as usize..)?),
+            };
+            let overlap = a.len().min(b.len());
+            if overlap < min_overlap {
+                return None;
+            }
+            let differing = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum::<u32>();
+            Some(1.0 - differing as f64 / (overlap * 32) as f64)
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Pairs of `prints` that have at least `MIN_SHARED` items in common, found
+/// through an index from items to the fingerprints they are in, so that
+/// only those pairs have to be compared in full.
+fn candidates(prints: &[&[u32]]) -> Vec<(usize, usize)> {
+    let mut index = HashMap::<u32, Vec<usize>>::new();
+    for (ix, print) in prints.iter().enumerate() {
+        for item in print.iter().collect::<HashSet<_>>() {
+            index.entry(*item).or_default().push(ix);
+        }
+    }
+
+    let mut shared = HashMap::<(usize, usize), usize>::new();
+    for postings in index.values() {
+        if postings.len() > MAX_POSTINGS {
+            continue;
+        }
+        for (n, &a) in postings.iter().enumerate() {
+            for &b in &postings[n + 1..] {
+                *shared.entry((a, b)).or_default() += 1;
+            }
+        }
+    }
+    shared
+        .into_iter()
+        .filter(|(_, count)| *count >= MIN_SHARED)
+        .map(|(pair, _)| pair)
+        .collect()
+}
+
+/// Groups of `tracks` whose fingerprints in `prints` match. Only tracks of
+/// about the same length are compared.
+pub fn matches(tracks: &[Arc<Track>], prints: &HashMap<String, Vec<u32>>) -> Vec<Vec<Arc<Track>>> {
+    let printed = tracks
+        .iter()
+        .filter_map(|track| Some((track, prints.get(track.file.as_ref()?)?.as_slice())))
+        .collect::<Vec<_>>();
+
+    // union-find over the matching pairs
+    let mut parent = (0..printed.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut ix: usize) -> usize {
+        while parent[ix] != ix {
+            parent[ix] = parent[parent[ix]];
+            ix = parent[ix];
+        }
+        ix
+    }
+    let items = printed.iter().map(|(_, print)| *print).collect::<Vec<_>>();
+    for (i, j) in candidates(&items) {
+        let (track, other) = (printed[i].0, printed[j].0);
+        if track
+            .duration()
+            .as_secs()
+            .abs_diff(other.duration().as_secs())
+            > DURATION_TOLERANCE_SECS
+        {
+            continue;
+        }
+        if similarity(items[i], items[j]) >= MATCH_THRESHOLD {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+
+    let mut groups = HashMap::<usize, Vec<Arc<Track>>>::new();
+    for (ix, (track, _)) in printed.iter().enumerate() {
+        let group = root(&mut parent, ix);
+        groups.entry(group).or_default().push(Arc::clone(track));
+    }
+    groups
+        .into_values()
+        .filter(|tracks| tracks.len() > 1)
+        .collect()
+}
+
+fn path() -> PathBuf {
+    storage::data_dir().join(FINGERPRINTS_FILE)
+}
+
+/// Appends one record to `out`: the file path, when it was modified and
+/// the fingerprint items, with lengths and numbers in little endian.
+fn encode(file: &str, print: &Fingerprint, out: &mut Vec<u8>) {
+    out.extend((file.len() as u32).to_le_bytes());
+    out.extend(file.as_bytes());
+    out.extend(print.modified.to_le_bytes());
+    out.extend((print.data.len() as u32).to_le_bytes());
+    for item in &print.data {
+        out.extend(item.to_le_bytes());
+    }
+}
+
+/// Reads the records of `bytes` in order, up to a record cut short by a
+/// crash while appending.
+fn decode(mut bytes: &[u8]) -> Vec<(String, Fingerprint)> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = (bytes.get(..n)?, bytes.get(n..)?);
+        *bytes = rest;
+        Some(head)
+    }
+    fn read_u32(bytes: &mut &[u8]) -> Option<u32> {
+        Some(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?))
+    }
+
+    let mut records = Vec::new();
+    while !bytes.is_empty() {
+        let record = (|| {
+            let len = read_u32(&mut bytes)? as usize;
+            let file = String::from_utf8(take(&mut bytes, len)?.to_vec()).ok()?;
+            let modified = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().ok()?);
+            let count = read_u32(&mut bytes)? as usize;
+            let data = take(&mut bytes, count * 4)?
+                .chunks_exact(4)
+                .map(|item| u32::from_le_bytes(item.try_into().unwrap()))
+                .collect();
+            Some((file, Fingerprint { modified, data }))
+        })();
+        match record {
+            Some(record) => records.push(record),
+            None => {
+                warn!("Ignoring a truncated fingerprint record");
+                break;
+            }
+        }
+    }
+    records
+}
+
+impl Fingerprints {
+    pub fn load() -> Self {
+        let Ok(bytes) = fs::read(path()) else {
+            return Self::default();
+        };
+        let records = decode(&bytes);
+        Self {
+            records: records.len(),
+            prints: records.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// The fingerprints of `tracks` that are known.
+    pub fn of(&self, tracks: &[Arc<Track>]) -> HashMap<String, Vec<u32>> {
+        tracks
+            .iter()
+            .filter_map(|track| {
+                let file = track.file.as_ref()?;
+                Some((file.clone(), self.prints.get(file)?.data.clone()))
+            })
+            .collect()
+    }
+
+    /// When the file of each fingerprint was last changed, for [`missing`]
+    /// and [`gone`] to compare with off the foreground.
+    fn known(&self) -> HashMap<String, u64> {
+        self.prints
+            .iter()
+            .map(|(file, print)| (file.clone(), print.modified))
+            .collect()
+    }
+
+    /// Forgets the fingerprints of `files`.
+    fn forget(&mut self, files: &[String]) {
+        for file in files {
+            self.prints.remove(file);
+        }
+    }
+
+    /// Adds `prints`, appending them to the log, or rewriting it when most
+    /// of it would be out of date.
+    fn add(&mut self, prints: Vec<(String, Fingerprint)>) {
+        let mut bytes = Vec::new();
+        for (file, print) in &prints {
+            encode(file, print, &mut bytes);
+        }
+        self.records += prints.len();
+        self.prints.extend(prints);
+
+        if self.records > self.prints.len() * 2 {
+            self.compact();
+            return;
+        }
+        let appended = fs::create_dir_all(storage::data_dir()).and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path())?
+                .write_all(&bytes)
+        });
+        if let Err(err) = appended {
+            error!("Failed to save fingerprints: {}", err);
+        }
+    }
+
+    /// Rewrites the log with only the current fingerprints.
+    fn compact(&mut self) {
+        let mut bytes = Vec::new();
+        for (file, print) in &self.prints {
+            encode(file, print, &mut bytes);
+        }
+        self.records = self.prints.len();
+
+        // written to a temporary file first so a crash never leaves a truncated log behind
+        let tmp = path().with_extension("bin.tmp");
+        let written = fs::create_dir_all(storage::data_dir())
+            .and_then(|_| fs::write(&tmp, &bytes))
+            .and_then(|_| fs::rename(&tmp, path()));
+        if let Err(err) = written {
+            error!("Failed to save fingerprints: {}", err);
+        }
+    }
+}
+
+/// Tracks without a fingerprint in `known`, or whose file changed since.
+/// Looks at every file, so it is not for the foreground.
+fn missing(known: &HashMap<String, u64>, tracks: &[Arc<Track>]) -> Vec<Arc<Track>> {
+    tracks
+        .iter()
+        .filter(|track| {
+            track.file.as_ref().is_some_and(|file| {
+                known
+                    .get(file)
+                    .map_or(true, |&known| known != modified(track))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Files in `known` that are no longer in the library.
+fn gone(known: &HashMap<String, u64>, tracks: &[Arc<Track>]) -> Vec<String> {
+    let files = tracks
+        .iter()
+        .filter_map(|track| track.file.as_deref())
+        .collect::<HashSet<_>>();
+    known
+        .keys()
+        .filter(|file| !files.contains(file.as_str()))
+        .cloned()
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct FingerprintsModel(Model<Fingerprints>);
+
+impl FingerprintsModel {
+    /// Loads the stored fingerprints and works out the missing ones in the
+    /// background, again whenever the library changes.
+    pub fn init(cx: &mut WindowContext, library: &LibraryModel) -> Self {
+        let model = cx.new_model(|_| Fingerprints::load());
+        let this = Self(model);
+        cx.set_global(this.clone());
+
+        this.update(library, cx);
+        cx.observe(library.model(), {
+            let this = this.clone();
+            let library = library.clone();
+            move |_, cx| this.update(&library, cx)
+        })
+        .detach();
+        this
+    }
+
+    pub fn read<'a>(&self, cx: &'a AppContext) -> &'a Fingerprints {
+        self.0.read(cx)
+    }
+
+    /// Fingerprints the library tracks that have none, one at a time so
+    /// playback is not starved.
+    fn update(&self, library: &LibraryModel, cx: &mut WindowContext) {
+        if self.read(cx).running {
+            // picked up once the current run is done
+            self.0.update(cx, |prints, _| prints.stale = true);
+            return;
+        }
+        let tracks = library.read(cx).tracks().to_vec();
+        let known = self.0.update(cx, |prints, _| {
+            prints.running = true;
+            prints.known()
+        });
+
+        let this = self.clone();
+        let library = library.clone();
+        let model = self.0.clone();
+        cx.spawn(|mut cx| async move {
+            let (unprinted, left) = cx
+                .background_executor()
+                .spawn(async move { (missing(&known, &tracks), gone(&known, &tracks)) })
+                .await;
+            let forgotten = model.update(&mut cx, |fingerprints, _| fingerprints.forget(&left));
+            if forgotten.is_err() {
+                return;
+            }
+
+            for batch in unprinted.chunks(SAVE_EVERY) {
+                let batch = batch.to_vec();
+                let prints = cx
+                    .background_executor()
+                    .spawn(async move {
+                        batch
+                            .iter()
+                            .filter_map(|track| {
+                                let print = compute(track).unwrap_or_else(|err| {
+                                    warn!("Failed to fingerprint {:?}: {}", track.file, err);
+                                    // kept empty so the file is not tried again until it changes
+                                    Fingerprint {
+                                        modified: modified(track),
+                                        data: Vec::new(),
+                                    }
+                                });
+                                Some((track.file.clone()?, print))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .await;
+
+                let updated = model.update(&mut cx, |fingerprints, cx| {
+                    fingerprints.add(prints);
+                    cx.notify();
+                });
+                if updated.is_err() {
+                    return;
+                }
+            }
+
+            let stale = model.update(&mut cx, |fingerprints, _| {
+                fingerprints.running = false;
+                std::mem::take(&mut fingerprints.stale)
+            });
+            if stale.unwrap_or(false) {
+                let _ = cx.update(|cx| this.update(&library, cx));
+            }
+        })
+        .detach();
+    }
+}
+
+impl Global for FingerprintsModel {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(seed: u32) -> Vec<u32> {
+        (0..200u32)
+            .map(|i| (seed * 1000 + i).wrapping_mul(2_654_435_761))
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_appended_records_up_to_a_truncated_one() {
+        let mut bytes = Vec::new();
+        let first = Fingerprint {
+            modified: 1,
+            data: vec![1, 2, 3],
+        };
+        let second = Fingerprint {
+            modified: 2,
+            data: vec![u32::MAX],
+        };
+        encode("a.flac", &first, &mut bytes);
+        encode("b.flac", &second, &mut bytes);
+        encode("a.flac", &second, &mut bytes);
+        let complete = bytes.len();
+        encode("c.flac", &first, &mut bytes);
+        bytes.truncate(complete + 6);
+
+        let records = decode(&bytes);
+        let files = records
+            .iter()
+            .map(|(file, _)| file.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["a.flac", "b.flac", "a.flac"]);
+        assert_eq!(records[0].1.data, [1, 2, 3]);
+
+        // later records replace earlier ones
+        let prints = records.into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(prints["a.flac"].modified, 2);
+        assert_eq!(prints["a.flac"].data, [u32::MAX]);
+    }
+
+    #[test]
+    fn only_pairs_sharing_items_are_candidates() {
+        let a = print(1);
+        // the same recording with some items off by a bit
+        let mut b = a.clone();
+        for item in b.iter_mut().step_by(3) {
+            *item ^= 1;
+        }
+        let c = print(2);
+
+        let prints = [a.as_slice(), c.as_slice(), b.as_slice()];
+        assert_eq!(candidates(&prints), [(0, 2)]);
+        assert!(similarity(&a, &b) >= MATCH_THRESHOLD);
+    }
+}
@@ -1,45 +1,193 @@
 use rayon::prelude::*;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use gpui::{Context, Global, Model, WindowContext};
+use gpui::{AppContext, Context, Global, Model, WindowContext};
+use log::error;
 
-use super::track::{Track, Tracks};
+use crate::settings::Settings;
+
+use super::{
+    cover::add_sidecar_covers,
+    cue,
+    database::Database,
+    query::Query,
+    search::{SearchIndex, SearchResults},
+    track::Track,
+};
 
 const LIBRARY_PATH: &str = "/home/gallon/Music";
 
-#[derive(Debug, Clone)]
-pub struct Library(Vec<Track>);
+/// Where the first track of each file is in `tracks`.
+fn by_file(tracks: &[Arc<Track>]) -> HashMap<String, usize> {
+    let mut by_file = HashMap::new();
+    for (ix, track) in tracks.iter().enumerate() {
+        if let Some(file) = &track.file {
+            by_file.entry(file.clone()).or_insert(ix);
+        }
+    }
+    by_file
+}
+
+pub struct Library {
+    // shared with the views listing them, which need not copy them
+    tracks: Vec<Arc<Track>>,
+    index: SearchIndex,
+    // where the first track of each file is in `tracks`
+    by_file: HashMap<String, usize>,
+    // counts the changes to `tracks`, so that search results from before
+    // one are not narrowed down
+    generation: u64,
+}
 
 impl Library {
-    pub fn load_tracks() -> Self {
-        let dir = PathBuf::from(LIBRARY_PATH.to_string());
+    /// Reads every track in the library directory, splitting images with cue
+    /// sheets into their tracks and taking covers from images named after
+    /// `cover_names` where a track has none embedded.
+    pub fn load_tracks(cover_names: &[String]) -> Self {
         let mut entries = vec![];
-        for entry in dir.read_dir().unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            entries.push(path);
-        }
+        files_in(&Self::root(), &mut entries, &mut HashSet::new());
 
         // TODO: more elegant way to do this?
-        let tracks = entries
+        let mut tracks = entries
             .par_iter()
-            .map(|entry| {
-                if entry.is_file() {
-                    let track = Track::read(entry).unwrap();
-                    if track.is_song() {
-                        Some(track)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+            .flat_map(|entry| {
+                if !entry.is_file() {
+                    return Vec::new();
                 }
+                cue::read_tracks(entry).unwrap_or_else(|err| {
+                    error!("Failed to read {}: {}", entry.display(), err);
+                    Vec::new()
+                })
             })
-            .filter(|track| track.is_some())
-            .map(|track| track.unwrap())
+            .filter(Track::is_song)
             .collect::<Vec<Track>>();
+        add_sidecar_covers(&mut tracks, cover_names);
+        let tracks = tracks.into_iter().map(Arc::new).collect::<Vec<_>>();
+
+        let index = SearchIndex::new(&tracks);
+        Library {
+            by_file: by_file(&tracks),
+            tracks,
+            index,
+            generation: 0,
+        }
+    }
+
+    /// The directory the library is read from.
+    pub fn root() -> PathBuf {
+        PathBuf::from(LIBRARY_PATH)
+    }
+
+    pub fn tracks(&self) -> &[Arc<Track>] {
+        &self.tracks
+    }
+
+    /// Puts `tracks` in place of the library tracks with the same file.
+    pub fn replace(&mut self, tracks: Vec<Track>) {
+        self.generation += 1;
+        for track in tracks {
+            if let Some(&ix) = track.file.as_ref().and_then(|file| self.by_file.get(file)) {
+                self.index.update(ix, &track);
+                self.tracks[ix] = Arc::new(track);
+            }
+        }
+    }
+
+    /// Puts tracks read from their new paths in place of the tracks of the
+    /// files at the old ones.
+    pub fn relocate(&mut self, moved: Vec<(String, Vec<Track>)>) {
+        self.generation += 1;
+        let mut moved = moved
+            .into_iter()
+            .map(|(old, tracks)| (old, Some(tracks)))
+            .collect::<HashMap<_, _>>();
+        let mut tracks = Vec::with_capacity(self.tracks.len());
+        for track in self.tracks.drain(..) {
+            let Some(new) = track.path().and_then(|path| moved.get_mut(path)) else {
+                tracks.push(track);
+                continue;
+            };
+            // the new tracks go where the first of the old ones was
+            if let Some(new) = new.take() {
+                tracks.extend(new.into_iter().map(Arc::new));
+            }
+        }
+        self.tracks = tracks;
+        // a cue sheet may have changed how many tracks a file holds
+        self.index = SearchIndex::new(&self.tracks);
+        self.by_file = by_file(&self.tracks);
+    }
+
+    /// Drops the tracks read from `paths`, after the files were deleted.
+    pub fn remove(&mut self, paths: &[String]) {
+        self.generation += 1;
+        let paths = paths.iter().map(String::as_str).collect::<HashSet<_>>();
+        self.tracks
+            .retain(|track| track.path().map_or(true, |path| !paths.contains(path)));
+        self.index = SearchIndex::new(&self.tracks);
+        self.by_file = by_file(&self.tracks);
+    }
+
+    /// The library track of `file`.
+    fn track_of(&self, file: &str) -> Option<&Arc<Track>> {
+        self.by_file.get(file).map(|&ix| &self.tracks[ix])
+    }
+
+    /// The tracks not hidden in `database`.
+    pub fn visible(&self, database: &Database) -> Vec<Arc<Track>> {
+        self.tracks
+            .iter()
+            .filter(|track| !database.is_hidden(track))
+            .cloned()
+            .collect()
+    }
+
+    /// Every file the library reads, once each even where a cue sheet
+    /// splits it into several tracks.
+    pub fn paths(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.tracks
+            .iter()
+            .filter_map(Track::path)
+            .filter(|path| seen.insert(*path))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Every track matching `query`, in library order.
+    pub fn find(&self, query: &Query, database: &Database) -> Vec<Arc<Track>> {
+        self.index
+            .search(&self.tracks, query, database, None)
+            .into_iter()
+            .map(|ix| Arc::clone(&self.tracks[ix]))
+            .collect()
+    }
+}
 
-        Library(tracks)
+/// Every file under `dir`, looking into subdirectories. Symlinks are
+/// followed, but every directory is entered only once, so a link back up
+/// the tree cannot loop forever.
+fn files_in(dir: &Path, files: &mut Vec<PathBuf>, visited: &mut HashSet<PathBuf>) {
+    let Ok(real) = dir.canonicalize() else {
+        return;
+    };
+    if !visited.insert(real) {
+        return;
+    }
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files_in(&path, files, visited);
+        } else {
+            files.push(path);
+        }
     }
 }
 
@@ -48,16 +196,124 @@ pub struct LibraryModel(Model<Library>);
 
 impl LibraryModel {
     pub fn init(cx: &mut WindowContext) -> Self {
-        let library = Library::load_tracks();
+        let library = Library::load_tracks(&cx.global::<Settings>().cover_names);
         let this = Self(cx.new_model(|_| library));
         cx.set_global(this.clone());
         this
     }
 
-    pub fn get_tracks(&self, cx: &mut WindowContext) -> Tracks {
-        let tracks = self.0.read(cx).0.clone();
-        Tracks { tracks }
+    pub fn model(&self) -> &Model<Library> {
+        &self.0
+    }
+
+    pub fn read<'a>(&self, cx: &'a AppContext) -> &'a Library {
+        self.0.read(cx)
+    }
+
+    /// Swaps in tracks read again after their files changed.
+    pub fn replace(&self, tracks: Vec<Track>, cx: &mut AppContext) {
+        self.0.update(cx, |library, cx| {
+            library.replace(tracks);
+            cx.notify();
+        });
+    }
+
+    /// Follows files that were moved, given as the old path and the tracks
+    /// read from the new one.
+    pub fn relocate(&self, moved: Vec<(String, Vec<Track>)>, cx: &mut AppContext) {
+        self.0.update(cx, |library, cx| {
+            library.relocate(moved);
+            cx.notify();
+        });
+    }
+
+    /// Forgets the tracks of files that were deleted.
+    pub fn remove(&self, paths: &[String], cx: &mut AppContext) {
+        self.0.update(cx, |library, cx| {
+            library.remove(paths);
+            cx.notify();
+        });
+    }
+
+    /// File paths of every track in the library.
+    pub fn files(&self, cx: &AppContext) -> Vec<String> {
+        self.0
+            .read(cx)
+            .tracks
+            .iter()
+            .filter_map(|track| track.file.clone())
+            .collect()
+    }
+
+    /// Every file the library reads, see [`Library::paths`].
+    pub fn paths(&self, cx: &AppContext) -> Vec<String> {
+        self.0.read(cx).paths()
+    }
+
+    /// Tracks, artists and albums matching `query`, reusing `previous`
+    /// results while the query is being typed and the library stays the same.
+    pub fn search(
+        &self,
+        query: &str,
+        database: &Database,
+        previous: Option<&SearchResults>,
+        cx: &AppContext,
+    ) -> SearchResults {
+        let library = self.0.read(cx);
+        let previous = previous.filter(|previous| previous.generation == library.generation);
+        let mut results =
+            SearchResults::new(&library.index, &library.tracks, query, database, previous);
+        results.generation = library.generation;
+        results
+    }
+
+    /// Looks a track up by path, reading it from disk if it is not part of
+    /// the library. Returns `None` if the file is gone or not a song.
+    pub fn find_track(&self, path: &str, cx: &AppContext) -> Option<Arc<Track>> {
+        match self.0.read(cx).track_of(path) {
+            Some(track) => Some(Arc::clone(track)),
+            None => Self::read_track(path, cx),
+        }
+    }
+
+    /// Looks up every one of `paths` like [`Self::find_track`].
+    pub fn find_tracks(&self, paths: &[String], cx: &AppContext) -> Vec<Option<Arc<Track>>> {
+        paths.iter().map(|path| self.find_track(path, cx)).collect()
+    }
+
+    /// Reads a track outside the library from disk.
+    fn read_track(path: &str, cx: &AppContext) -> Option<Arc<Track>> {
+        let mut track = Path::new(path)
+            .is_file()
+            .then(|| Track::read(path).ok())
+            .flatten()
+            .filter(Track::is_song)?;
+        add_sidecar_covers(
+            std::slice::from_mut(&mut track),
+            &cx.global::<Settings>().cover_names,
+        );
+        Some(Arc::new(track))
     }
 }
 
 impl Global for LibraryModel {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::*;
+
+    #[test]
+    fn files_in_survives_symlink_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        let album = dir.path().join("album");
+        fs::create_dir(&album).unwrap();
+        fs::write(album.join("01.flac"), b"").unwrap();
+        symlink(dir.path(), album.join("loop")).unwrap();
+
+        let mut files = Vec::new();
+        files_in(dir.path(), &mut files, &mut HashSet::new());
+        assert_eq!(files, [album.join("01.flac")]);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{fs, path::Path, time::Duration};
+
+use id3::frame::TimestampFormat;
+use lofty::{file::TaggedFileExt, probe::Probe, tag::ItemKey};
+
+/// A word of an enhanced LRC line, `<mm:ss.xx>word`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub start: Duration,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// `None` for lyrics without timestamps.
+    pub start: Option<Duration>,
+    pub text: String,
+    /// Empty unless the line has word timestamps.
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lyrics {
+    /// Sorted by start when synchronized.
+    pub lines: Vec<Line>,
+}
+
+/// `mm:ss.xx`, `mm:ss:xx` or `mm:ss`.
+fn parse_time(text: &str) -> Option<Duration> {
+    let (minutes, seconds) = text.trim().split_once(':')?;
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = seconds.replacen(':', ".", 1).parse::<f64>().ok()?;
+    (seconds.is_finite() && seconds >= 0.0)
+        .then(|| Duration::from_secs(minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Splits `<mm:ss.xx>` word timestamps out of `text`.
+fn parse_words(text: &str) -> (String, Vec<Word>) {
+    let mut pieces = text.split('<');
+    let mut plain = pieces.next().unwrap_or_default().to_string();
+    let mut words = Vec::<Word>::new();
+
+    for piece in pieces {
+        let timed = piece
+            .split_once('>')
+            .and_then(|(time, word)| parse_time(time).map(|start| (start, word)));
+        match timed {
+            Some((start, word)) => {
+                plain.push_str(word);
+                words.push(Word {
+                    start,
+                    text: word.to_string(),
+                });
+            }
+            None => {
+                // a literal `<`
+                plain.push('<');
+                plain.push_str(piece);
+                if let Some(word) = words.last_mut() {
+                    word.text.push('<');
+                    word.text.push_str(piece);
+                }
+            }
+        }
+    }
+
+    (plain.trim().to_string(), words)
+}
+
+impl Lyrics {
+    /// Parses LRC, enhanced LRC or plain text, one line per line.
+    pub fn parse(text: &str) -> Self {
+        let mut offset = 0i64;
+        let mut timed = Vec::new();
+        let mut plain = Vec::new();
+
+        'lines: for line in text.lines() {
+            let mut rest = line.trim();
+            let mut starts = Vec::new();
+
+            while let Some(tag) = rest.strip_prefix('[') {
+                let Some((inside, after)) = tag.split_once(']') else {
+                    break;
+                };
+                match parse_time(inside) {
+                    Some(start) => starts.push(start),
+                    None => {
+                        // an ID tag like `[ar:Artist]`, only the offset matters
+                        if let Some(("offset", value)) = inside.split_once(':') {
+                            offset = value.trim().parse().unwrap_or(0);
+                        }
+                        continue 'lines;
+                    }
+                }
+                rest = after;
+            }
+
+            let (text, words) = parse_words(rest);
+            if starts.is_empty() {
+                plain.push(Line {
+                    start: None,
+                    text,
+                    words: Vec::new(),
+                });
+            } else {
+                timed.extend(starts.into_iter().map(|start| Line {
+                    start: Some(start),
+                    text: text.clone(),
+                    words: words.clone(),
+                }));
+            }
+        }
+
+        if timed.is_empty() {
+            // drop the blank lines around plain lyrics
+            let first = plain.iter().position(|line| !line.text.is_empty());
+            let last = plain.iter().rposition(|line| !line.text.is_empty());
+            let lines = match (first, last) {
+                (Some(first), Some(last)) => plain.drain(first..=last).collect(),
+                _ => Vec::new(),
+            };
+            return Self { lines };
+        }
+
+        // a positive offset shows the lyrics earlier
+        let shift = |time: Duration| {
+            if offset >= 0 {
+                time.saturating_sub(Duration::from_millis(offset as u64))
+            } else {
+                time + Duration::from_millis(offset.unsigned_abs())
+            }
+        };
+        for line in &mut timed {
+            line.start = line.start.map(shift);
+            for word in &mut line.words {
+                word.start = shift(word.start);
+            }
+        }
+        timed.sort_by_key(|line| line.start);
+        Self { lines: timed }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.first().is_some_and(|line| line.start.is_some())
+    }
+
+    /// Index of the line being sung at `position`.
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        if !self.is_synced() {
+            return None;
+        }
+        self.lines
+            .partition_point(|line| line.start.is_some_and(|start| start <= position))
+            .checked_sub(1)
+    }
+
+    /// When the next line or the next word of the line sung at `position`
+    /// starts, `None` once nothing more changes.
+    pub fn next_start(&self, position: Duration) -> Option<Duration> {
+        let line = self.line_at(position);
+        let next_line = self.lines[line.map_or(0, |line| line + 1)..]
+            .iter()
+            .find_map(|line| line.start.filter(|&start| start > position));
+        let next_word = line.and_then(|line| {
+            self.lines[line]
+                .words
+                .iter()
+                .map(|word| word.start)
+                .find(|&start| start > position)
+        });
+        next_line.into_iter().chain(next_word).min()
+    }
+
+    /// Synchronized lyrics of `file` if there are any, plain ones otherwise.
+    /// Looks at SYLT frames, then a `.lrc` file next to it, then USLT/LYRICS tags.
+    pub fn load(file: &Path) -> Option<Self> {
+        if let Some(lyrics) = synchronised_frame(file) {
+            return Some(lyrics);
+        }
+
+        let sidecar = fs::read_to_string(file.with_extension("lrc")).ok();
+        let embedded = Probe::open(file)
+            .ok()
+            .and_then(|probe| probe.read().ok())
+            .and_then(|tagged_file| {
+                let tag = tagged_file.primary_tag()?;
+                tag.get_string(&ItemKey::Lyrics).map(String::from)
+            });
+
+        let found = [sidecar, embedded]
+            .into_iter()
+            .flatten()
+            .map(|text| Self::parse(&text))
+            .filter(|lyrics| !lyrics.lines.is_empty())
+            .collect::<Vec<_>>();
+        let synced = found.iter().position(Lyrics::is_synced).unwrap_or(0);
+        found.into_iter().nth(synced)
+    }
+}
+
+/// The first ID3v2 SYLT frame with millisecond timestamps.
+fn synchronised_frame(file: &Path) -> Option<Lyrics> {
+    let tag = id3::Tag::read_from_path(file).ok()?;
+    let frame = tag
+        .synchronised_lyrics()
+        .find(|lyrics| lyrics.timestamp_format == TimestampFormat::Ms)?;
+
+    let lines = frame
+        .content
+        .iter()
+        .map(|(ms, text)| Line {
+            start: Some(Duration::from_millis(u64::from(*ms))),
+            text: text.trim().to_string(),
+            words: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    (!lines.is_empty()).then_some(Lyrics { lines })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        assert_eq!(parse_time("01:02.50"), Some(ms(62_500)));
+        assert_eq!(parse_time("01:02:50"), Some(ms(62_500)));
+        assert_eq!(parse_time("01:02"), Some(ms(62_000)));
+        assert_eq!(parse_time(" 00:05.1 "), Some(ms(5_100)));
+        assert_eq!(parse_time("ar:Artist"), None);
+        assert_eq!(parse_time("01"), None);
+        assert_eq!(parse_time("01:-2"), None);
+        assert_eq!(parse_time("01:inf"), None);
+    }
+
+    #[test]
+    fn parse_words_splits_word_stamps() {
+        let (text, words) = parse_words("<00:01.00>Hello <00:01.50>world");
+        assert_eq!(text, "Hello world");
+        assert_eq!(
+            words,
+            [
+                Word {
+                    start: ms(1_000),
+                    text: "Hello ".into()
+                },
+                Word {
+                    start: ms(1_500),
+                    text: "world".into()
+                },
+            ]
+        );
+
+        let (text, words) = parse_words("a < b");
+        assert_eq!(text, "a < b");
+        assert!(words.is_empty());
+
+        let (text, words) = parse_words("<00:01.00>1 <2");
+        assert_eq!(text, "1 <2");
+        assert_eq!(words[0].text, "1 <2");
+    }
+
+    #[test]
+    fn plain_lyrics_drop_surrounding_blank_lines() {
+        let lyrics = Lyrics::parse("\n\nfirst\n\nsecond\n\n");
+        let lines = lyrics
+            .lines
+            .iter()
+            .map(|l| l.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["first", "", "second"]);
+        assert!(!lyrics.is_synced());
+        assert_eq!(lyrics.line_at(ms(10_000)), None);
+        assert_eq!(lyrics.next_start(Duration::ZERO), None);
+        assert!(Lyrics::parse("\n  \n").lines.is_empty());
+    }
+
+    #[test]
+    fn synced_lyrics_are_sorted_and_skip_id_tags() {
+        let lyrics =
+            Lyrics::parse("[ar:Artist]\n[ti:Title]\n[00:10.00][00:30.00]chorus\n[00:20.00]verse\n");
+        let lines = lyrics
+            .lines
+            .iter()
+            .map(|l| (l.start, l.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                (Some(ms(10_000)), "chorus"),
+                (Some(ms(20_000)), "verse"),
+                (Some(ms(30_000)), "chorus"),
+            ]
+        );
+        assert!(lyrics.is_synced());
+        assert_eq!(lyrics.line_at(ms(5_000)), None);
+        assert_eq!(lyrics.line_at(ms(10_000)), Some(0));
+        assert_eq!(lyrics.line_at(ms(25_000)), Some(1));
+        assert_eq!(lyrics.line_at(ms(99_000)), Some(2));
+    }
+
+    #[test]
+    fn offset_shifts_lines_and_words() {
+        let lyrics =
+            Lyrics::parse("[offset:+500]\n[00:00.20]early\n[00:02.00]<00:02.00>a <00:03.00>b");
+        assert_eq!(lyrics.lines[0].start, Some(Duration::ZERO));
+        assert_eq!(lyrics.lines[1].start, Some(ms(1_500)));
+        assert_eq!(lyrics.lines[1].words[0].start, ms(1_500));
+        assert_eq!(lyrics.lines[1].words[1].start, ms(2_500));
+
+        let lyrics = Lyrics::parse("[offset:-250]\n[00:01.00]late");
+        assert_eq!(lyrics.lines[0].start, Some(ms(1_250)));
+
+        let lyrics = Lyrics::parse("[offset:nonsense]\n[00:01.00]same");
+        assert_eq!(lyrics.lines[0].start, Some(ms(1_000)));
+    }
+
+    #[test]
+    fn next_start_covers_lines_and_words() {
+        let lyrics = Lyrics::parse("[00:01.00]<00:01.00>one <00:02.00>two\n[00:05.00]three");
+        assert_eq!(lyrics.next_start(Duration::ZERO), Some(ms(1_000)));
+        assert_eq!(lyrics.next_start(ms(1_000)), Some(ms(2_000)));
+        assert_eq!(lyrics.next_start(ms(2_500)), Some(ms(5_000)));
+        assert_eq!(lyrics.next_start(ms(5_000)), None);
+    }
+}
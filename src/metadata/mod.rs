@@ -1,3 +1,18 @@
 pub mod album;
+pub mod columns;
+pub mod cover;
+pub mod cue;
+pub mod database;
+pub mod duplicates;
+pub mod fingerprint;
 pub mod library;
+pub mod lyrics;
+pub mod organize;
+pub mod pattern;
+pub mod playlist;
+pub mod playlist_file;
+pub mod query;
+pub mod search;
+pub mod smart_playlist;
+pub mod tags;
 pub mod track;
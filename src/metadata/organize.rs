@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::error;
+
+use super::{
+    pattern::{self, check_conflicts, Pattern, Rename},
+    tags::{self, TagField},
+};
+
+const VARIOUS_ARTISTS: &str = "Various Artists";
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+// named after a track, and renamed along with it
+const TRACK_SIDECARS: [&str; 2] = ["lrc", "cue"];
+
+/// What the tracks of one album have in common. Albums are told apart by
+/// directory as well as name, so two "Greatest Hits" stay apart.
+#[derive(Default)]
+struct Album {
+    artists: HashSet<String>,
+    discs: HashSet<String>,
+    disc_total: u32,
+}
+
+type AlbumKey = (Option<PathBuf>, Option<String>);
+
+fn album_key(file: &Path, values: &HashMap<TagField, String>) -> AlbumKey {
+    (
+        file.parent().map(Path::to_path_buf),
+        values.get(&TagField::Album).cloned(),
+    )
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Every move needed to lay the library out after one pattern.
+pub struct Plan {
+    root: PathBuf,
+    /// One per track, in library order.
+    pub tracks: Vec<Rename>,
+    /// Lyrics, cue sheets and covers that go along with the tracks.
+    pub sidecars: Vec<Rename>,
+}
+
+impl Plan {
+    /// Works out where `files` go under `root`. Albums without an album
+    /// artist but with several artists go under "Various Artists", and disc
+    /// numbers are only filled in for albums with more than one disc.
+    pub fn new(files: &[String], pattern: &Pattern, root: &Path, cover_names: &[String]) -> Self {
+        let read = files
+            .iter()
+            .map(|file| {
+                tags::read(Path::new(file))
+                    .map(|values| values.into_iter().collect::<HashMap<_, _>>())
+            })
+            .collect::<Vec<_>>();
+
+        let mut albums = HashMap::<AlbumKey, Album>::new();
+        for (file, values) in files.iter().zip(&read) {
+            let Ok(values) = values else {
+                continue;
+            };
+            let album = albums
+                .entry(album_key(Path::new(file), values))
+                .or_default();
+            if let Some(artist) = values.get(&TagField::Artist) {
+                album.artists.insert(artist.clone());
+            }
+            if let Some(disc) = values.get(&TagField::DiscNumber) {
+                album.discs.insert(disc.clone());
+            }
+            if let Some(total) = values
+                .get(&TagField::DiscTotal)
+                .and_then(|t| t.parse().ok())
+            {
+                album.disc_total = album.disc_total.max(total);
+            }
+        }
+
+        let tracks = files
+            .iter()
+            .zip(&read)
+            .map(|(file, values)| {
+                let from = PathBuf::from(file);
+                let values = match values {
+                    Ok(values) => values,
+                    Err(err) => return Rename::stuck(from, format!("tags unreadable: {}", err)),
+                };
+                let album = &albums[&album_key(&from, values)];
+
+                let relative = pattern.format(|field| match field {
+                    TagField::AlbumArtist => {
+                        values.get(&TagField::AlbumArtist).cloned().or_else(|| {
+                            match album.artists.len() {
+                                0 | 1 => values.get(&TagField::Artist).cloned(),
+                                _ => Some(VARIOUS_ARTISTS.to_string()),
+                            }
+                        })
+                    }
+                    TagField::DiscNumber if album.discs.len() <= 1 && album.disc_total <= 1 => None,
+                    field => values.get(&field).cloned(),
+                });
+                Rename::to_layout(from, root, relative)
+            })
+            .collect::<Vec<_>>();
+
+        let sidecars = sidecars(&tracks, files, cover_names);
+
+        let mut renames = tracks;
+        let count = renames.len();
+        renames.extend(sidecars);
+        check_conflicts(&mut renames);
+        let sidecars = renames.split_off(count);
+
+        Self {
+            root: root.to_path_buf(),
+            tracks: renames,
+            sidecars,
+        }
+    }
+
+    fn renames(&self) -> impl Iterator<Item = &Rename> {
+        self.tracks.iter().chain(&self.sidecars)
+    }
+
+    /// Files that would move.
+    pub fn moves(&self) -> usize {
+        self.renames()
+            .filter(|rename| rename.from != rename.to)
+            .count()
+    }
+
+    /// Files that cannot be moved where the pattern puts them.
+    pub fn conflicts(&self) -> usize {
+        self.renames()
+            .filter(|rename| rename.conflict.is_some())
+            .count()
+    }
+
+    fn short<'a>(&self, path: &'a Path) -> std::path::Display<'a> {
+        path.strip_prefix(&self.root).unwrap_or(path).display()
+    }
+
+    /// One line per file that moves or cannot, for checking before anything
+    /// is touched.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} files to move, {} problems\n",
+            self.moves(),
+            self.conflicts()
+        );
+        for rename in self.renames() {
+            let line = match &rename.conflict {
+                Some(conflict) => format!("! {}: {}\n", self.short(&rename.from), conflict),
+                None if rename.from == rename.to => continue,
+                None => format!(
+                    "{} -> {}\n",
+                    self.short(&rename.from),
+                    self.short(&rename.to)
+                ),
+            };
+            report.push_str(&line);
+        }
+        report
+    }
+
+    /// Moves every file or none: after a failure the files moved so far go
+    /// back. Returns how the tracks moved, leaving out the sidecars.
+    pub fn apply(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        if let Some(rename) = self
+            .renames()
+            .find(|rename| rename.conflict.is_some() && rename.from != rename.to)
+        {
+            bail!(
+                "{}: {}",
+                self.short(&rename.from),
+                rename.conflict.as_deref().unwrap_or_default()
+            );
+        }
+
+        // files that stay put, unreadable ones included, are left alone
+        let renames = self
+            .renames()
+            .filter(|rename| rename.conflict.is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        let (moved, failure) = pattern::apply(&renames, &self.root);
+        if let Some(err) = failure {
+            let (_, rollback) = pattern::apply(&pattern::reverse(&moved), &self.root);
+            return Err(match rollback {
+                None => anyhow!("{}; nothing was moved", err),
+                Some(rollback) => anyhow!("{}; moving files back failed too: {}", err, rollback),
+            });
+        }
+
+        let tracks = self
+            .tracks
+            .iter()
+            .map(|rename| &rename.from)
+            .collect::<HashSet<_>>();
+        let (tracks, sidecars): (Vec<_>, Vec<_>) = moved
+            .into_iter()
+            .partition(|(from, _)| tracks.contains(from));
+
+        for (from, to) in sidecars.iter().filter(|(from, _)| extension(from) == "cue") {
+            if let Err(err) = rename_in_cue(to, from.parent(), &tracks) {
+                error!("Failed to update {}: {}", to.display(), err);
+            }
+        }
+        Ok(tracks)
+    }
+}
+
+/// Sidecars named after moved tracks, then the covers and cue sheets of
+/// directories whose tracks all end up in the same new one.
+fn sidecars(tracks: &[Rename], files: &[String], cover_names: &[String]) -> Vec<Rename> {
+    let mut sidecars = Vec::new();
+    for rename in tracks.iter().filter(|rename| rename.from != rename.to) {
+        for extension in TRACK_SIDECARS {
+            let from = rename.from.with_extension(extension);
+            if from.is_file() {
+                sidecars.push(Rename {
+                    to: rename.to.with_extension(extension),
+                    from,
+                    conflict: None,
+                });
+            }
+        }
+    }
+
+    let mut destinations = HashMap::<PathBuf, HashSet<PathBuf>>::new();
+    for rename in tracks {
+        if let (Some(from), Some(to)) = (rename.from.parent(), rename.to.parent()) {
+            destinations
+                .entry(from.to_path_buf())
+                .or_default()
+                .insert(to.to_path_buf());
+        }
+    }
+
+    let taken = files
+        .iter()
+        .map(PathBuf::from)
+        .chain(sidecars.iter().map(|rename| rename.from.clone()))
+        .collect::<HashSet<_>>();
+    for (dir, targets) in destinations {
+        let Some(target) = targets.iter().next().filter(|_| targets.len() == 1) else {
+            continue;
+        };
+        if *target == dir {
+            continue;
+        }
+        let Ok(entries) = dir.read_dir() else {
+            continue;
+        };
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if taken.contains(&path) || !path.is_file() {
+                continue;
+            }
+            let extension = extension(&path);
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let is_cover = IMAGE_EXTENSIONS.contains(&extension.as_str())
+                && cover_names.iter().any(|name| name.to_lowercase() == stem);
+
+            if is_cover || extension == "cue" {
+                if let Some(name) = path.file_name() {
+                    sidecars.push(Rename {
+                        to: target.join(name),
+                        from: path,
+                        conflict: None,
+                    });
+                }
+            }
+        }
+    }
+    sidecars
+}
+
+/// Points the `FILE` lines of a moved cue sheet at the new names of the
+/// tracks that were next to it in `dir`.
+fn rename_in_cue(cue: &Path, dir: Option<&Path>, moved: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let names = moved
+        .iter()
+        .filter(|(from, _)| from.parent() == dir)
+        .filter_map(|(from, to)| {
+            Some((
+                from.file_name()?.to_string_lossy().into_owned(),
+                to.file_name()?.to_string_lossy().into_owned(),
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let text = fs::read_to_string(cue)?;
+    let mut changed = false;
+    let lines = text
+        .lines()
+        .map(|line| {
+            let renamed = line.trim_start().strip_prefix("FILE ").and_then(|rest| {
+                let start = rest.find('"')?;
+                let end = rest.rfind('"').filter(|&end| end > start)?;
+                let new = names.get(&rest[start + 1..end])?;
+                Some(format!("FILE \"{}\"{}", new, &rest[end + 1..]))
+            });
+            match renamed {
+                Some(renamed) => {
+                    changed = true;
+                    renamed
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if changed {
+        fs::write(cue, lines.join("\n") + "\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(root: &Path, tracks: Vec<Rename>, sidecars: Vec<Rename>) -> Plan {
+        Plan {
+            root: root.to_path_buf(),
+            tracks,
+            sidecars,
+        }
+    }
+
+    fn rename(from: &Path, to: &Path) -> Rename {
+        Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            conflict: None,
+        }
+    }
+
+    #[test]
+    fn refuses_to_apply_with_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.mp3"), dir.path().join("b.mp3"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let mut clash = rename(&b, &dir.path().join("x/a.mp3"));
+        clash.conflict = Some("another file would get the same name".into());
+        let plan = plan(
+            dir.path(),
+            vec![rename(&a, &dir.path().join("x/a.mp3")), clash],
+            Vec::new(),
+        );
+        assert_eq!(plan.conflicts(), 1);
+        assert_eq!(
+            plan.apply().unwrap_err().to_string(),
+            "b.mp3: another file would get the same name"
+        );
+        assert!(a.is_file() && b.is_file());
+    }
+
+    #[test]
+    fn moves_everything_back_after_a_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("in/a.mp3"), dir.path().join("in/b.mp3"));
+        let lyrics = dir.path().join("in/a.lrc");
+        fs::create_dir_all(dir.path().join("in")).unwrap();
+        for file in [&a, &b, &lyrics] {
+            fs::write(file, "").unwrap();
+        }
+        let plan = plan(
+            dir.path(),
+            vec![
+                rename(&a, &dir.path().join("out/a.mp3")),
+                rename(&b, &dir.path().join("out/b.mp3")),
+            ],
+            vec![rename(&lyrics, &dir.path().join("out/a.lrc"))],
+        );
+        // taken after the plan was made
+        fs::create_dir_all(dir.path().join("out")).unwrap();
+        fs::write(dir.path().join("out/a.lrc"), "other").unwrap();
+
+        let err = plan.apply().unwrap_err().to_string();
+        assert!(err.ends_with("; nothing was moved"), "{}", err);
+        assert!(a.is_file() && b.is_file() && lyrics.is_file());
+        assert!(!dir.path().join("out/a.mp3").exists());
+        assert!(!dir.path().join("out/b.mp3").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out/a.lrc")).unwrap(),
+            "other"
+        );
+    }
+
+    #[test]
+    fn applies_a_plan_and_returns_the_track_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, lyrics) = (dir.path().join("in/a.mp3"), dir.path().join("in/a.lrc"));
+        let (new_a, new_lyrics) = (dir.path().join("out/a.mp3"), dir.path().join("out/a.lrc"));
+        fs::create_dir_all(dir.path().join("in")).unwrap();
+        fs::write(&a, "").unwrap();
+        fs::write(&lyrics, "").unwrap();
+
+        let plan = plan(
+            dir.path(),
+            vec![rename(&a, &new_a)],
+            vec![rename(&lyrics, &new_lyrics)],
+        );
+        assert_eq!(plan.moves(), 2);
+        assert_eq!(plan.apply().unwrap(), [(a, new_a.clone())]);
+        assert!(new_a.is_file() && new_lyrics.is_file());
+        assert!(!dir.path().join("in").exists());
+    }
+}
@@ -0,0 +1,666 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::tags::{self, TagField};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(TagField),
+    /// Left out when a field in it has no value.
+    Optional(Vec<Part>),
+}
+
+/// A path layout like `%albumartist%/%album%/%track% - %title%`, used both
+/// to read tags out of paths and to name files after their tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    parts: Vec<Part>,
+}
+
+fn field_named(name: &str) -> Option<TagField> {
+    let field = match name.to_ascii_lowercase().as_str() {
+        "title" => TagField::Title,
+        "artist" => TagField::Artist,
+        "album" => TagField::Album,
+        "albumartist" => TagField::AlbumArtist,
+        "genre" => TagField::Genre,
+        "year" => TagField::Year,
+        "track" => TagField::TrackNumber,
+        "tracktotal" => TagField::TrackTotal,
+        "disc" => TagField::DiscNumber,
+        "disctotal" => TagField::DiscTotal,
+        "composer" => TagField::Composer,
+        _ => return None,
+    };
+    Some(field)
+}
+
+/// Makes `text` safe to use as a single path component.
+pub fn sanitize(text: &str) -> String {
+    let text = text
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // no hidden files, and Windows drops trailing dots and spaces
+    let text = text
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .trim();
+    match text {
+        "" => "_".to_string(),
+        text => text.to_string(),
+    }
+}
+
+fn flush(literal: &mut String, parts: &mut Vec<Part>) {
+    if !literal.is_empty() {
+        parts.push(Part::Text(std::mem::take(literal)));
+    }
+}
+
+fn has_field(parts: &[Part]) -> bool {
+    parts.iter().any(|part| match part {
+        Part::Text(_) => false,
+        Part::Field(_) => true,
+        Part::Optional(parts) => has_field(parts),
+    })
+}
+
+/// Keeps paths made from `parts` under the directory they are made in.
+fn check_relative(parts: &[Part]) -> Result<()> {
+    // fields stand in as a nul, which no literal holds
+    let shape = parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            Part::Field(_) | Part::Optional(_) => "\0",
+        })
+        .collect::<String>();
+    if shape.starts_with('/') {
+        bail!("the pattern cannot start with /");
+    }
+    if shape.split('/').any(|name| name == "." || name == "..") {
+        bail!("the pattern cannot hold . or .. directories");
+    }
+    Ok(())
+}
+
+impl Pattern {
+    /// Parses `%field%` placeholders and `[...]` sections, which are left
+    /// out when a field in them has no value. A `\` before `[`, `]`, `%` or
+    /// `\` makes it plain text.
+    pub fn parse(text: &str) -> Result<Self> {
+        // the innermost open section last
+        let mut stack = vec![Vec::new()];
+        let mut literal = String::new();
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            let parts = stack.last_mut().expect("the pattern itself stays open");
+            match c {
+                '%' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('%').ok_or_else(|| anyhow!("a % is not closed"))?;
+                    let name = &rest[..end];
+                    let field =
+                        field_named(name).ok_or_else(|| anyhow!("unknown field %{}%", name))?;
+                    if literal.is_empty() && matches!(parts.last(), Some(Part::Field(_))) {
+                        // nothing would tell where the first one ends
+                        bail!("%{}% needs some text before it", name);
+                    }
+                    flush(&mut literal, parts);
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '[' => {
+                    flush(&mut literal, parts);
+                    stack.push(Vec::new());
+                }
+                ']' => {
+                    flush(&mut literal, parts);
+                    if stack.len() == 1 {
+                        bail!("a ] has no [");
+                    }
+                    let section = stack.pop().unwrap_or_default();
+                    if !has_field(&section) {
+                        bail!("[...] needs a %field% inside");
+                    }
+                    stack
+                        .last_mut()
+                        .expect("the pattern itself stays open")
+                        .push(Part::Optional(section));
+                }
+                '/' if stack.len() > 1 => bail!("[...] cannot hold a /"),
+                '\\' => match chars.next() {
+                    Some(c @ ('[' | ']' | '%' | '\\')) => literal.push(c),
+                    _ => bail!("a \\ can only come before [, ], % or \\"),
+                },
+                c => literal.push(c),
+            }
+        }
+        if stack.len() > 1 {
+            bail!("a [ is not closed");
+        }
+
+        let mut parts = stack.pop().unwrap_or_default();
+        flush(&mut literal, &mut parts);
+        if !has_field(&parts) {
+            bail!("the pattern has no %fields%");
+        }
+        check_relative(&parts)?;
+        Ok(Self { parts })
+    }
+
+    /// How many directories the pattern spans.
+    fn depth(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.matches('/').count(),
+                Part::Field(_) | Part::Optional(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Tags read out of `path`, matched against its end so the pattern only
+    /// has to cover the last few directories. `None` if it does not fit.
+    pub fn extract(&self, path: &Path) -> Option<Vec<(TagField, String)>> {
+        let stem = path.with_extension("");
+        let components = stem
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?;
+        let tail = components[components.len().checked_sub(self.depth() + 1)?..].join("/");
+
+        let mut fields = Vec::new();
+        match_parts(&self.parts, &tail, &mut fields).then_some(fields)
+    }
+
+    /// A path for a file with tags looked up by `value`, without extension.
+    /// Missing tags become `Unknown <field>` outside of `[...]` sections.
+    pub fn format(&self, value: impl Fn(TagField) -> Option<String>) -> PathBuf {
+        let path = format_parts(&self.parts, &value, true).unwrap_or_default();
+        PathBuf::from(path)
+    }
+}
+
+/// `parts` filled in, or `None` if a field is missing and not `required`.
+fn format_parts(
+    parts: &[Part],
+    value: &impl Fn(TagField) -> Option<String>,
+    required: bool,
+) -> Option<String> {
+    let mut path = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => path.push_str(text),
+            Part::Optional(parts) => {
+                if let Some(text) = format_parts(parts, value, false) {
+                    path.push_str(&text);
+                }
+            }
+            Part::Field(field) => {
+                let text = match (field, value(*field)) {
+                    (_, Some(text)) if text.trim().is_empty() => None,
+                    (TagField::TrackNumber, Some(text)) => Some(match text.parse::<u32>() {
+                        Ok(number) => format!("{:02}", number),
+                        Err(_) => text,
+                    }),
+                    (_, text) => text,
+                };
+                let text = match text {
+                    Some(text) => text,
+                    None if required => format!("Unknown {}", field.label().to_lowercase()),
+                    None => return None,
+                };
+                path.push_str(&sanitize(&text));
+            }
+        }
+    }
+    Some(path)
+}
+
+/// Matches `text` against `parts`, trying the shortest value for each field
+/// first and sections before leaving them out. Fields never span directories.
+fn match_parts(parts: &[Part], text: &str, fields: &mut Vec<(TagField, String)>) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((Part::Text(literal), rest)) => text
+            .strip_prefix(literal.as_str())
+            .is_some_and(|text| match_parts(rest, text, fields)),
+        Some((Part::Optional(section), rest)) => {
+            let with = section.iter().chain(rest).cloned().collect::<Vec<_>>();
+            let found = fields.len();
+            if match_parts(&with, text, fields) {
+                return true;
+            }
+            fields.truncate(found);
+            match_parts(rest, text, fields)
+        }
+        Some((Part::Field(field), rest)) => {
+            let limit = text.find('/').unwrap_or(text.len());
+            for end in (1..=limit).filter(|&end| text.is_char_boundary(end)) {
+                let value = text[..end].trim();
+                let value = match (field.is_number(), value.parse::<u32>()) {
+                    (_, _) if value.is_empty() => continue,
+                    (true, Ok(number)) => number.to_string(),
+                    (true, Err(_)) => continue,
+                    (false, _) => value.to_string(),
+                };
+
+                fields.push((*field, value));
+                if match_parts(rest, &text[end..], fields) {
+                    return true;
+                }
+                fields.pop();
+            }
+            false
+        }
+    }
+}
+
+/// A planned move of a file to where its tags say it belongs.
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Why the file cannot be moved there.
+    pub conflict: Option<String>,
+}
+
+impl Rename {
+    /// A move of `from` to `relative` under `root`, keeping its extension.
+    pub fn to_layout(from: PathBuf, root: &Path, relative: PathBuf) -> Self {
+        let mut to = root.join(relative).into_os_string();
+        if let Some(extension) = from.extension() {
+            to.push(".");
+            to.push(extension);
+        }
+        Self {
+            from,
+            to: PathBuf::from(to),
+            conflict: None,
+        }
+    }
+
+    /// A file that cannot be moved at all.
+    pub fn stuck(from: PathBuf, conflict: String) -> Self {
+        Self {
+            to: from.clone(),
+            from,
+            conflict: Some(conflict),
+        }
+    }
+}
+
+/// Flags renames onto files that exist or onto the same path as another.
+pub fn check_conflicts(renames: &mut [Rename]) {
+    let mut targets = HashMap::<PathBuf, usize>::new();
+    for rename in renames.iter() {
+        *targets.entry(rename.to.clone()).or_default() += 1;
+    }
+    for rename in renames
+        .iter_mut()
+        .filter(|rename| rename.conflict.is_none())
+    {
+        if targets[&rename.to] > 1 {
+            rename.conflict = Some("another file would get the same name".to_string());
+        } else if rename.to != rename.from && rename.to.exists() {
+            rename.conflict = Some("a file with that name already exists".to_string());
+        }
+    }
+}
+
+/// Where each of `files` would go under `root`, flagging clashes with
+/// existing files and with each other.
+pub fn plan_renames(files: &[String], pattern: &Pattern, root: &Path) -> Vec<Rename> {
+    let mut renames = files
+        .iter()
+        .map(|file| {
+            let from = PathBuf::from(file);
+            let values = match tags::read(&from) {
+                Ok(values) => values.into_iter().collect::<HashMap<_, _>>(),
+                Err(err) => return Rename::stuck(from, format!("tags unreadable: {}", err)),
+            };
+
+            let relative = pattern.format(|field| {
+                let value = values.get(&field).cloned();
+                match field {
+                    TagField::AlbumArtist => {
+                        value.or_else(|| values.get(&TagField::Artist).cloned())
+                    }
+                    _ => value,
+                }
+            });
+            Rename::to_layout(from, root, relative)
+        })
+        .collect::<Vec<_>>();
+
+    check_conflicts(&mut renames);
+    renames
+}
+
+/// `fs::rename`, falling back to copying across file systems.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Removes `dir` and its parents for as long as they are empty, up to but
+/// not including `root`.
+fn remove_empty_dirs(dir: Option<&Path>, root: &Path) {
+    let mut dir = dir;
+    while let Some(current) = dir.filter(|dir| dir.starts_with(root) && *dir != root) {
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Moves the files as planned, stopping at the first failure, and removes
+/// the directories under `root` they leave empty. Returns the moves that
+/// were made, for [`reverse`], along with the failure.
+pub fn apply(renames: &[Rename], root: &Path) -> (Vec<(PathBuf, PathBuf)>, Option<anyhow::Error>) {
+    let mut moved = Vec::new();
+    for rename in renames.iter().filter(|rename| rename.from != rename.to) {
+        let failure = match &rename.conflict {
+            Some(conflict) => Some(anyhow!("{}", conflict)),
+            // `fs::rename` would replace it
+            None if rename.to.exists() => Some(anyhow!("a file with that name already exists")),
+            None => move_file(&rename.from, &rename.to).err(),
+        };
+        if let Some(err) = failure {
+            return (moved, Some(anyhow!("{}: {}", rename.from.display(), err)));
+        }
+        remove_empty_dirs(rename.from.parent(), root);
+        moved.push((rename.from.clone(), rename.to.clone()));
+    }
+    (moved, None)
+}
+
+/// Renames that put files moved by [`apply`] back where they were.
+pub fn reverse(moved: &[(PathBuf, PathBuf)]) -> Vec<Rename> {
+    moved
+        .iter()
+        .rev()
+        .map(|(from, to)| Rename {
+            from: to.clone(),
+            to: from.clone(),
+            conflict: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        Pattern::parse(text).expect_err(text).to_string()
+    }
+
+    fn extract(pattern: &str, path: &str) -> Option<Vec<(TagField, String)>> {
+        Pattern::parse(pattern).unwrap().extract(Path::new(path))
+    }
+
+    fn tags(tags: &[(TagField, &str)]) -> Option<Vec<(TagField, String)>> {
+        Some(
+            tags.iter()
+                .map(|(field, value)| (*field, value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_fields_text_and_sections() {
+        let pattern = Pattern::parse("%AlbumArtist%/%album%/[%disc%-]%track% - %title%").unwrap();
+        assert_eq!(
+            pattern.parts,
+            [
+                Part::Field(TagField::AlbumArtist),
+                Part::Text("/".into()),
+                Part::Field(TagField::Album),
+                Part::Text("/".into()),
+                Part::Optional(vec![
+                    Part::Field(TagField::DiscNumber),
+                    Part::Text("-".into()),
+                ]),
+                Part::Field(TagField::TrackNumber),
+                Part::Text(" - ".into()),
+                Part::Field(TagField::Title),
+            ]
+        );
+        assert_eq!(pattern.depth(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert_eq!(error("%title"), "a % is not closed");
+        assert_eq!(error("%mood%"), "unknown field %mood%");
+        assert_eq!(
+            error("%artist%%title%"),
+            "%title% needs some text before it"
+        );
+        assert_eq!(error("[%disc%-%track%"), "a [ is not closed");
+        assert_eq!(error("%track%]"), "a ] has no [");
+        assert_eq!(error("[CD %disc%/]%track%"), "[...] cannot hold a /");
+        assert_eq!(error("[live] %title%"), "[...] needs a %field% inside");
+        assert_eq!(error("music"), "the pattern has no %fields%");
+        assert_eq!(error("\\%title%"), "the pattern has no %fields%");
+        assert_eq!(error("/tmp/%title%"), "the pattern cannot start with /");
+        assert_eq!(
+            error("../../%artist%/%title%"),
+            "the pattern cannot hold . or .. directories"
+        );
+        assert_eq!(
+            error("%artist%/./%title%"),
+            "the pattern cannot hold . or .. directories"
+        );
+        assert!(Pattern::parse("%artist%/..%title%").is_ok());
+        assert!(Pattern::parse("%artist%.../%title%").is_ok());
+        assert_eq!(
+            error("%title% \\n"),
+            "a \\ can only come before [, ], % or \\"
+        );
+        assert_eq!(
+            error("%title%\\"),
+            "a \\ can only come before [, ], % or \\"
+        );
+    }
+
+    #[test]
+    fn escapes_brackets_and_percent_signs() {
+        let pattern = Pattern::parse("\\[%year%\\] %album% 100\\% \\\\[%disc%]").unwrap();
+        assert_eq!(
+            pattern.parts,
+            [
+                Part::Text("[".into()),
+                Part::Field(TagField::Year),
+                Part::Text("] ".into()),
+                Part::Field(TagField::Album),
+                Part::Text(" 100% \\".into()),
+                Part::Optional(vec![Part::Field(TagField::DiscNumber)]),
+            ]
+        );
+        assert_eq!(
+            pattern.format(|field| match field {
+                TagField::Year => Some("1959".into()),
+                TagField::Album => Some("Kind of Blue".into()),
+                _ => None,
+            }),
+            PathBuf::from("[1959] Kind of Blue 100% \\")
+        );
+        assert_eq!(
+            extract("\\[%year%\\] %album%", "/music/[1959] Kind of Blue.flac"),
+            tags(&[(TagField::Year, "1959"), (TagField::Album, "Kind of Blue")])
+        );
+    }
+
+    #[test]
+    fn extracts_tags_from_the_end_of_a_path() {
+        assert_eq!(
+            extract(
+                "%artist%/%album%/%track% - %title%",
+                "/music/Miles Davis/Kind of Blue/01 - So What.flac"
+            ),
+            tags(&[
+                (TagField::Artist, "Miles Davis"),
+                (TagField::Album, "Kind of Blue"),
+                (TagField::TrackNumber, "1"),
+                (TagField::Title, "So What"),
+            ])
+        );
+        // not enough directories
+        assert_eq!(extract("%artist%/%album%/%title%", "Song.mp3"), None);
+        // a track number has to be a number
+        assert_eq!(extract("%track% %title%", "/music/Intro Song.mp3"), None);
+        // a field never spans directories
+        let pattern = Pattern::parse("%artist% - %title%").unwrap();
+        assert!(!match_parts(&pattern.parts, "a/b - c", &mut Vec::new()));
+        assert!(match_parts(&pattern.parts, "b - c", &mut Vec::new()));
+    }
+
+    #[test]
+    fn extracts_optional_sections_when_they_fit() {
+        let pattern = "%album%/[%disc%-]%track% %title%";
+        assert_eq!(
+            extract(pattern, "/music/Box/2-03 Song.mp3"),
+            tags(&[
+                (TagField::Album, "Box"),
+                (TagField::DiscNumber, "2"),
+                (TagField::TrackNumber, "3"),
+                (TagField::Title, "Song"),
+            ])
+        );
+        assert_eq!(
+            extract(pattern, "/music/Box/03 Song.mp3"),
+            tags(&[
+                (TagField::Album, "Box"),
+                (TagField::TrackNumber, "3"),
+                (TagField::Title, "Song"),
+            ])
+        );
+    }
+
+    #[test]
+    fn formats_paths_from_tags() {
+        let pattern = Pattern::parse("%albumartist%/%album%/[%disc%-]%track% %title%").unwrap();
+        let path = pattern.format(|field| match field {
+            TagField::Album => Some("Highway to Hell".into()),
+            TagField::TrackNumber => Some("3".into()),
+            TagField::Title => Some("AC/DC: Live?".into()),
+            _ => None,
+        });
+        assert_eq!(
+            path,
+            PathBuf::from("Unknown album artist/Highway to Hell/03 AC_DC_ Live_")
+        );
+
+        let path = pattern.format(|field| match field {
+            TagField::AlbumArtist => Some("  ".into()),
+            TagField::DiscNumber => Some("2".into()),
+            TagField::TrackNumber => Some("A1".into()),
+            _ => Some("x".into()),
+        });
+        assert_eq!(path, PathBuf::from("Unknown album artist/x/2-A1 x"));
+    }
+
+    fn rename(from: &Path, to: &Path) -> Rename {
+        Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            conflict: None,
+        }
+    }
+
+    #[test]
+    fn flags_clashing_and_existing_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c, taken) = ["a", "b", "c", "taken"].map(|name| dir.path().join(name));
+        fs::write(&taken, "").unwrap();
+
+        let mut renames = [
+            rename(&a, &dir.path().join("same")),
+            rename(&b, &dir.path().join("same")),
+            rename(&c, &taken),
+            rename(&taken, &taken),
+            Rename::stuck(a.clone(), "tags unreadable".into()),
+        ];
+        check_conflicts(&mut renames);
+        let conflicts = renames.map(|rename| rename.conflict);
+        assert_eq!(
+            conflicts,
+            [
+                Some("another file would get the same name".into()),
+                Some("another file would get the same name".into()),
+                Some("a file with that name already exists".into()),
+                // staying put is no clash with itself
+                None,
+                Some("tags unreadable".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_renames_until_one_fails_and_reverses_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("in/a"), dir.path().join("in/b"));
+        let (new_a, new_b) = (dir.path().join("out/a"), dir.path().join("out/b"));
+        fs::create_dir_all(a.parent().unwrap()).unwrap();
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        // appeared after the renames were planned
+        fs::create_dir_all(new_b.parent().unwrap()).unwrap();
+        fs::write(&new_b, "other").unwrap();
+
+        let (moved, failure) = apply(&[rename(&a, &new_a), rename(&b, &new_b)], dir.path());
+        assert_eq!(moved, [(a.clone(), new_a.clone())]);
+        assert!(failure
+            .unwrap()
+            .to_string()
+            .ends_with("a file with that name already exists"));
+        assert_eq!(fs::read_to_string(&new_a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&new_b).unwrap(), "other");
+
+        let (back, failure) = apply(&reverse(&moved), dir.path());
+        assert!(failure.is_none());
+        assert_eq!(back, [(new_a.clone(), a.clone())]);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!new_a.exists());
+    }
+
+    #[test]
+    fn removes_directories_left_empty_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("music");
+        let from = root.join("old/album/a");
+        let to = dir.path().join("elsewhere/a");
+        fs::create_dir_all(from.parent().unwrap()).unwrap();
+        fs::write(&from, "a").unwrap();
+
+        let (moved, failure) = apply(&[rename(&from, &to)], &root);
+        assert!(failure.is_none());
+        assert_eq!(moved.len(), 1);
+        assert!(to.is_file());
+        assert!(!root.join("old").exists());
+        // empty now, but not for this to remove
+        assert!(root.is_dir());
+    }
+}
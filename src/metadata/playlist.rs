@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use gpui::{AppContext, Context, Global, Model, WindowContext};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::{cue, database::now, smart_playlist::SmartPlaylist};
+
+const PLAYLISTS_FILE: &str = "playlists.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: u64,
+    pub name: String,
+    /// Track file paths, in play order.
+    pub tracks: Vec<String>,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+    pub modified: u64,
+}
+
+impl Playlist {
+    fn new(id: u64, name: String) -> Self {
+        let created = now();
+        Self {
+            id,
+            name,
+            tracks: Vec::new(),
+            created,
+            modified: created,
+        }
+    }
+
+    fn touch(&mut self) {
+        self.modified = now();
+    }
+
+    pub fn add(&mut self, files: impl IntoIterator<Item = String>) {
+        self.tracks.extend(files);
+        self.touch();
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.tracks.len() {
+            self.tracks.remove(index);
+            self.touch();
+        }
+    }
+
+    /// Moves the entry at `from` so that it ends up at `to`.
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        if from < self.tracks.len() && to < self.tracks.len() && from != to {
+            let file = self.tracks.remove(from);
+            self.tracks.insert(to, file);
+            self.touch();
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Playlists {
+    next_id: u64,
+    pub playlists: Vec<Playlist>,
+    pub smart: Vec<SmartPlaylist>,
+    /// Where tracks added from the browse views go.
+    #[serde(skip)]
+    pub target: Option<u64>,
+}
+
+impl Playlists {
+    pub fn get(&self, id: u64) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Playlist> {
+        self.playlists.iter_mut().find(|p| p.id == id)
+    }
+
+    pub fn create(&mut self, name: impl Into<String>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.playlists.push(Playlist::new(id, name.into()));
+        id
+    }
+
+    pub fn get_smart(&self, id: u64) -> Option<&SmartPlaylist> {
+        self.smart.iter().find(|p| p.id == id)
+    }
+
+    pub fn get_smart_mut(&mut self, id: u64) -> Option<&mut SmartPlaylist> {
+        self.smart.iter_mut().find(|p| p.id == id)
+    }
+
+    /// Smart playlists share ids with normal ones.
+    pub fn create_smart(&mut self, name: impl Into<String>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.smart.push(SmartPlaylist::new(id, name.into()));
+        id
+    }
+
+    pub fn rename(&mut self, id: u64, name: impl Into<String>) {
+        if let Some(playlist) = self.get_mut(id) {
+            playlist.name = name.into();
+            playlist.touch();
+        } else if let Some(playlist) = self.get_smart_mut(id) {
+            playlist.name = name.into();
+            playlist.touch();
+        }
+    }
+
+    pub fn delete(&mut self, id: u64) {
+        self.playlists.retain(|p| p.id != id);
+        self.smart.retain(|p| p.id != id);
+        if self.target == Some(id) {
+            self.target = None;
+        }
+    }
+
+    pub fn duplicate(&mut self, id: u64) -> Option<u64> {
+        if let Some(source) = self.get_smart(id).cloned() {
+            let copy = self.create_smart(format!("{} (copy)", source.name));
+            if let Some(playlist) = self.get_smart_mut(copy) {
+                *playlist = SmartPlaylist {
+                    id: copy,
+                    name: playlist.name.clone(),
+                    ..source
+                };
+                playlist.touch();
+            }
+            return Some(copy);
+        }
+
+        let source = self.get(id)?.clone();
+        let copy = self.create(format!("{} (copy)", source.name));
+        if let Some(playlist) = self.get_mut(copy) {
+            playlist.add(source.tracks);
+        }
+        Some(copy)
+    }
+
+    /// Whether there is a playlist for tracks to be added to.
+    pub fn has_target(&self) -> bool {
+        self.target.is_some_and(|id| self.get(id).is_some())
+    }
+
+    /// Adds tracks to the target playlist, if there is one.
+    pub fn add_to_target(&mut self, files: impl IntoIterator<Item = String>) {
+        if let Some(playlist) = self.target.and_then(|id| self.get_mut(id)) {
+            playlist.add(files);
+        }
+    }
+
+    /// Follows files moved from the first path to the second.
+    pub fn relocate(&mut self, moved: &[(String, String)]) {
+        let moved = moved.iter().cloned().collect::<HashMap<_, _>>();
+        for playlist in &mut self.playlists {
+            for file in &mut playlist.tracks {
+                if let Some(new) = cue::relocated(file, &moved) {
+                    *file = new;
+                }
+            }
+        }
+    }
+
+    fn save(&self) {
+        if let Err(err) = storage::save(PLAYLISTS_FILE, self) {
+            error!("Failed to save playlists: {}", err);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaylistsModel(Model<Playlists>);
+
+impl PlaylistsModel {
+    pub fn init(cx: &mut WindowContext) -> Self {
+        let playlists = storage::load::<Playlists>(PLAYLISTS_FILE);
+        let this = Self(cx.new_model(|_| playlists));
+        cx.set_global(this.clone());
+        this
+    }
+
+    pub fn model(&self) -> &Model<Playlists> {
+        &self.0
+    }
+
+    pub fn read<'a>(&self, cx: &'a AppContext) -> &'a Playlists {
+        self.0.read(cx)
+    }
+
+    /// Picks the playlist tracks are added to, which is not saved.
+    pub fn set_target(&self, id: Option<u64>, cx: &mut AppContext) {
+        self.0.update(cx, |playlists, _| {
+            playlists.target = id.filter(|&id| playlists.get(id).is_some());
+        });
+    }
+
+    /// Applies a change and writes the playlists back to disk.
+    pub fn update<R>(&self, cx: &mut AppContext, f: impl FnOnce(&mut Playlists) -> R) -> R {
+        self.0.update(cx, |playlists, cx| {
+            let result = f(playlists);
+            playlists.save();
+            cx.notify();
+            result
+        })
+    }
+}
+
+impl Global for PlaylistsModel {}
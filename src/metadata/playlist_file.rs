@@ -0,0 +1,570 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use url::Url;
+
+use super::track::Track;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "m3u" => Some(Self::M3u),
+            "m3u8" => Some(Self::M3u8),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// One line of a playlist file, before it is matched against the library.
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub location: String,
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl Entry {
+    /// `None` for tracks without a file, and for tracks of a cue sheet, as
+    /// other players cannot open a `#<n>` location and the whole image
+    /// would play in their place.
+    fn from_track(track: &Track) -> Option<Self> {
+        if track.segment.is_some() {
+            return None;
+        }
+        Some(Self {
+            location: track.file.clone()?,
+            title: Some(format!("{} - {}", track.artist, track.title)),
+            duration: Some(track.duration()).filter(|d| !d.is_zero()),
+        })
+    }
+}
+
+pub fn parse(format: PlaylistFormat, content: &str) -> Result<Vec<Entry>> {
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok(parse_m3u(content)),
+        PlaylistFormat::Pls => Ok(parse_pls(content)),
+        PlaylistFormat::Xspf => parse_xspf(content),
+    }
+}
+
+pub fn write(format: PlaylistFormat, name: &str, entries: &[Entry]) -> String {
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => write_m3u(entries),
+        PlaylistFormat::Pls => write_pls(entries),
+        PlaylistFormat::Xspf => write_xspf(name, entries),
+    }
+}
+
+fn parse_m3u(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut pending = Entry::default();
+
+    for line in content.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>[ attributes],<title>
+            let (length, title) = info.split_once(',').unwrap_or((info, ""));
+            let seconds = length.split_whitespace().next().unwrap_or_default();
+            pending.duration = seconds
+                .parse::<i64>()
+                .ok()
+                .filter(|&s| s > 0)
+                .map(|s| Duration::from_secs(s as u64));
+            pending.title = Some(title.trim().to_string()).filter(|t| !t.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            pending.location = line.to_string();
+            entries.push(std::mem::take(&mut pending));
+        }
+    }
+
+    entries
+}
+
+fn write_m3u(entries: &[Entry]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for entry in entries {
+        let seconds = entry.duration.map_or(-1, |d| d.as_secs() as i64);
+        out.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            seconds,
+            entry.title.as_deref().unwrap_or_default(),
+            entry.location
+        ));
+    }
+    out
+}
+
+fn parse_pls(content: &str) -> Vec<Entry> {
+    let mut entries: HashMap<usize, Entry> = HashMap::new();
+
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+
+        let field = ["file", "title", "length"]
+            .into_iter()
+            .find_map(|field| Some(field).zip(key.strip_prefix(field)?.parse::<usize>().ok()));
+        let Some((field, index)) = field else {
+            continue;
+        };
+
+        let entry = entries.entry(index).or_default();
+        match field {
+            "file" => entry.location = value.to_string(),
+            "title" => entry.title = Some(value.to_string()),
+            _ => {
+                entry.duration = value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|&s| s > 0)
+                    .map(|s| Duration::from_secs(s as u64))
+            }
+        }
+    }
+
+    let mut entries = entries
+        .into_iter()
+        .filter(|(_, entry)| !entry.location.is_empty())
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn write_pls(entries: &[Entry]) -> String {
+    let mut out = String::from("[playlist]\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        out.push_str(&format!("File{}={}\n", n, entry.location));
+        if let Some(title) = &entry.title {
+            out.push_str(&format!("Title{}={}\n", n, title));
+        }
+        let seconds = entry.duration.map_or(-1, |d| d.as_secs() as i64);
+        out.push_str(&format!("Length{}={}\n", n, seconds));
+    }
+    out.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    out
+}
+
+fn parse_xspf(content: &str) -> Result<Vec<Entry>> {
+    let document = roxmltree::Document::parse(content)?;
+
+    let entries = document
+        .descendants()
+        .filter(|node| node.has_tag_name("track"))
+        .filter_map(|track| {
+            let text = |name: &str| {
+                track
+                    .children()
+                    .find(|node| node.has_tag_name(name))
+                    .and_then(|node| node.text())
+                    .map(|text| text.trim().to_string())
+            };
+
+            Some(Entry {
+                location: text("location")?,
+                title: match (text("creator"), text("title")) {
+                    (Some(creator), Some(title)) => Some(format!("{} - {}", creator, title)),
+                    (_, title) => title,
+                },
+                duration: text("duration")
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .map(Duration::from_millis),
+            })
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_xspf(name: &str, entries: &[Entry]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    out.push_str(&format!(
+        "  <title>{}</title>\n  <trackList>\n",
+        escape_xml(name)
+    ));
+
+    for entry in entries {
+        let location = Url::from_file_path(&entry.location)
+            .map(String::from)
+            .unwrap_or_else(|_| entry.location.clone());
+
+        out.push_str("    <track>\n");
+        out.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape_xml(&location)
+        ));
+        if let Some(title) = &entry.title {
+            out.push_str(&format!("      <title>{}</title>\n", escape_xml(title)));
+        }
+        if let Some(duration) = entry.duration {
+            out.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                duration.as_millis()
+            ));
+        }
+        out.push_str("    </track>\n");
+    }
+
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+/// Resolves `.` and `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+/// Turns a playlist location into a path, relative ones taken from `base`.
+fn entry_path(location: &str, base: &Path) -> Option<PathBuf> {
+    if let Ok(url) = Url::parse(location) {
+        // a single letter scheme is a windows drive, not a url
+        if url.scheme().len() > 1 {
+            return url.to_file_path().ok();
+        }
+    }
+
+    let path = PathBuf::from(location.replace('\\', "/"));
+    Some(normalize(&base.join(path)))
+}
+
+pub struct ImportReport {
+    pub name: String,
+    /// Library file paths, in playlist order.
+    pub matched: Vec<String>,
+    /// Locations that could not be found in the library.
+    pub unmatched: Vec<String>,
+}
+
+/// Reads a playlist file and matches its entries against `library`, by
+/// exact path first and then by the trailing `album/file` part of the path,
+/// for playlists written on another machine. A tail shared by several
+/// library files, like `CD1/01.flac`, matches none of them.
+pub fn import<P: AsRef<Path>>(path: P, library: &[String]) -> Result<ImportReport> {
+    let path = path.as_ref();
+    let format = PlaylistFormat::from_path(path)
+        .ok_or_else(|| anyhow!("unsupported playlist format: {}", path.display()))?;
+
+    let bytes = fs::read(path)?;
+    let content = match String::from_utf8(bytes) {
+        Ok(content) => content,
+        // plain .m3u files are traditionally latin-1
+        Err(err) => err.into_bytes().iter().map(|&b| b as char).collect(),
+    };
+    let entries = parse(format, content.trim_start_matches('\u{feff}'))?;
+
+    let base = path.parent().unwrap_or(Path::new("/"));
+    let tail = |path: &Path| {
+        let mut components = path.components().rev().take(2).collect::<Vec<_>>();
+        components.reverse();
+        components.into_iter().collect::<PathBuf>()
+    };
+    let files = library.iter().map(String::as_str).collect::<HashSet<_>>();
+    // `None` for tails that are ambiguous
+    let mut by_tail = HashMap::<PathBuf, Option<&String>>::new();
+    for file in library {
+        by_tail
+            .entry(tail(Path::new(file)))
+            .and_modify(|found| *found = None)
+            .or_insert(Some(file));
+    }
+
+    let mut report = ImportReport {
+        name: path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        matched: Vec::new(),
+        unmatched: Vec::new(),
+    };
+
+    for entry in entries {
+        let found = entry_path(&entry.location, base).and_then(|path| {
+            let file = path.to_string_lossy().to_string();
+            if files.contains(file.as_str()) {
+                return Some(file);
+            }
+            by_tail.get(&tail(&path)).copied().flatten().cloned()
+        });
+
+        match found {
+            Some(file) => report.matched.push(file),
+            None => report.unmatched.push(entry.location),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Writes `tracks` to a playlist file, the format picked from the extension.
+/// Returns the tracks left out, for having no file or being part of a cue
+/// sheet, as `artist - title`.
+pub fn export<P: AsRef<Path>>(path: P, name: &str, tracks: &[Arc<Track>]) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let format = PlaylistFormat::from_path(path).unwrap_or(PlaylistFormat::M3u8);
+    let mut skipped = Vec::new();
+    let entries = tracks
+        .iter()
+        .filter_map(|track| {
+            let entry = Entry::from_track(track);
+            if entry.is_none() {
+                skipped.push(format!("{} - {}", track.artist, track.title));
+            }
+            entry
+        })
+        .collect::<Vec<_>>();
+
+    let content = write(format, name, &entries);
+    match format {
+        PlaylistFormat::M3u => {
+            let latin1 = content
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect::<Vec<_>>();
+            fs::write(path, latin1)?;
+        }
+        _ => fs::write(path, content)?,
+    }
+
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                location: "/music/Miles Davis/Kind of Blue/01 So What.flac".into(),
+                title: Some("Miles Davis - So What".into()),
+                duration: Some(Duration::from_secs(562)),
+            },
+            Entry {
+                location: "/music/Various/A & B <live>.mp3".into(),
+                title: None,
+                duration: None,
+            },
+        ]
+    }
+
+    fn locations(entries: &[Entry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| entry.location.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_m3u() {
+        let entries = parse_m3u(
+            "#EXTM3U\n\
+             #EXTINF:123 tvg-id=\"x\",Artist - Title\n\
+             one.mp3\n\
+             # a comment\n\
+             \n\
+             #EXTINF:-1,\n\
+             two.mp3\n\
+             three.mp3\n",
+        );
+        assert_eq!(locations(&entries), ["one.mp3", "two.mp3", "three.mp3"]);
+        assert_eq!(entries[0].title.as_deref(), Some("Artist - Title"));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(123)));
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[1].duration, None);
+        // the info of one entry does not carry over to the next
+        assert_eq!(entries[2].title, None);
+    }
+
+    #[test]
+    fn parses_pls_in_entry_order() {
+        let entries = parse_pls(
+            "[playlist]\n\
+             File2=two.ogg\n\
+             Title2=Second\n\
+             File1=one.ogg\n\
+             Length1=61\n\
+             Length2=-1\n\
+             Title3=No file\n\
+             NumberOfEntries=2\n",
+        );
+        assert_eq!(locations(&entries), ["one.ogg", "two.ogg"]);
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(61)));
+        assert_eq!(entries[1].title.as_deref(), Some("Second"));
+        assert_eq!(entries[1].duration, None);
+    }
+
+    #[test]
+    fn parses_xspf() {
+        let entries = parse_xspf(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <playlist version="1" xmlns="http://xspf.org/ns/0/">
+              <trackList>
+                <track>
+                  <location>file:///music/one.flac</location>
+                  <creator>Artist</creator>
+                  <title>One</title>
+                  <duration>61500</duration>
+                </track>
+                <track><title>Without a location</title></track>
+                <track><location>two.flac</location><title>Two</title></track>
+              </trackList>
+            </playlist>"#,
+        )
+        .unwrap();
+        assert_eq!(locations(&entries), ["file:///music/one.flac", "two.flac"]);
+        assert_eq!(entries[0].title.as_deref(), Some("Artist - One"));
+        assert_eq!(entries[0].duration, Some(Duration::from_millis(61_500)));
+        assert_eq!(entries[1].title.as_deref(), Some("Two"));
+        assert!(parse_xspf("<playlist>").is_err());
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let base = Path::new("/");
+        for format in [
+            PlaylistFormat::M3u8,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+        ] {
+            let written = write(format, "Mix", &entries());
+            let parsed = parse(format, &written).unwrap();
+            let paths = parsed
+                .iter()
+                .map(|entry| entry_path(&entry.location, base).unwrap())
+                .collect::<Vec<_>>();
+            let expected = entries()
+                .iter()
+                .map(|entry| PathBuf::from(&entry.location))
+                .collect::<Vec<_>>();
+            assert_eq!(paths, expected, "{:?}", format);
+            assert_eq!(parsed[0].title, entries()[0].title, "{:?}", format);
+            assert_eq!(parsed[0].duration, entries()[0].duration, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn resolves_relative_and_url_locations() {
+        let base = Path::new("/music/lists");
+        assert_eq!(
+            entry_path("../Album/01.flac", base),
+            Some(PathBuf::from("/music/Album/01.flac"))
+        );
+        assert_eq!(
+            entry_path("Album\\01.flac", base),
+            Some(PathBuf::from("/music/lists/Album/01.flac"))
+        );
+        assert_eq!(
+            entry_path("file:///music/A%20B.flac", base),
+            Some(PathBuf::from("/music/A B.flac"))
+        );
+    }
+
+    #[test]
+    fn leaves_out_cue_sheet_tracks() {
+        use hound::{SampleFormat, WavSpec, WavWriter};
+
+        use crate::metadata::track::Segment;
+
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&image, spec).unwrap();
+        for _ in 0..2 * spec.sample_rate {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut whole = Track::read(&image).unwrap();
+        whole.artist = "Artist".to_string();
+        let mut cut = whole.cut(
+            2,
+            Segment {
+                start: Duration::from_secs(1),
+                end: None,
+            },
+        );
+        cut.title = "Second".to_string();
+
+        let list = dir.path().join("list.m3u8");
+        let skipped = export(&list, "list", &[Arc::new(whole), Arc::new(cut)]).unwrap();
+        assert_eq!(skipped, ["Artist - Second"]);
+        let content = fs::read_to_string(&list).unwrap();
+        assert!(content.contains(&image.to_string_lossy().to_string()));
+        assert!(!content.contains("image.wav#"));
+    }
+
+    #[test]
+    fn imports_by_path_then_by_unambiguous_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("Road Trip.m3u8");
+        fs::write(
+            &list,
+            "/music/Album/01.flac\n\
+             /old/disk/Other/02.flac\n\
+             /old/disk/CD1/01.flac\n\
+             /music/Missing/03.flac\n",
+        )
+        .unwrap();
+        let library = [
+            "/music/Album/01.flac",
+            "/music/Other/02.flac",
+            "/music/Box A/CD1/01.flac",
+            "/music/Box B/CD1/01.flac",
+        ]
+        .map(String::from);
+
+        let report = import(&list, &library).unwrap();
+        assert_eq!(report.name, "Road Trip");
+        assert_eq!(
+            report.matched,
+            ["/music/Album/01.flac", "/music/Other/02.flac"]
+        );
+        assert_eq!(
+            report.unmatched,
+            ["/old/disk/CD1/01.flac", "/music/Missing/03.flac"]
+        );
+    }
+}
@@ -0,0 +1,604 @@
+//! Search queries such as `artist:"Miles Davis" year:1955..1960 -genre:live duration:>10m`
+//! or `rating:>=4 loved:1`.
+//!
+//! Words are ANDed together, `OR` (or `|`) separates alternatives, `-`
+//! negates a word or a parenthesised group. `field:value` looks at a single
+//! field: text fields match when they contain the value (or equal it with
+//! `field:=value`), numeric fields take `n`, `>n`, `>=n`, `<n`, `<=n` or
+//! ranges like `a..b`, `a..` and `..b`.
+
+use std::{fmt, ops::Range};
+
+use super::{
+    database::{now, Database},
+    search::{fold, Folded},
+    track::Track,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Path,
+    Year,
+    /// In seconds.
+    Duration,
+    Plays,
+    Skips,
+    /// Days since the track was last played.
+    LastPlayed,
+    /// 0 to 5 stars.
+    Rating,
+    /// 1 for loved tracks, 0 for the rest.
+    Loved,
+}
+
+impl Field {
+    const NAMES: [(&'static str, Field); 20] = [
+        ("title", Field::Title),
+        ("artist", Field::Artist),
+        ("album", Field::Album),
+        ("albumartist", Field::AlbumArtist),
+        ("album_artist", Field::AlbumArtist),
+        ("genre", Field::Genre),
+        ("path", Field::Path),
+        ("file", Field::Path),
+        ("year", Field::Year),
+        ("duration", Field::Duration),
+        ("length", Field::Duration),
+        ("plays", Field::Plays),
+        ("play_count", Field::Plays),
+        ("skips", Field::Skips),
+        ("lastplayed", Field::LastPlayed),
+        ("last_played", Field::LastPlayed),
+        ("rating", Field::Rating),
+        ("stars", Field::Rating),
+        ("loved", Field::Loved),
+        ("favorite", Field::Loved),
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, field)| *field)
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Field::Year
+                | Field::Duration
+                | Field::Plays
+                | Field::Skips
+                | Field::LastPlayed
+                | Field::Rating
+                | Field::Loved
+        )
+    }
+
+    fn text<'a>(self, folded: &'a Folded) -> &'a str {
+        match self {
+            Field::Title => &folded.title,
+            Field::Artist => &folded.artist,
+            Field::Album => &folded.album,
+            Field::AlbumArtist => &folded.album_artist,
+            Field::Genre => &folded.genre,
+            Field::Path => &folded.path,
+            _ => "",
+        }
+    }
+
+    fn number(self, track: &Track, database: &Database) -> Option<f64> {
+        let stats = database.stats(track);
+        match self {
+            Field::Year => track.year.map(f64::from),
+            Field::Duration => Some(track.duration().as_secs_f64()),
+            Field::Plays => Some(stats.map_or(0, |s| s.play_count) as f64),
+            Field::Skips => Some(stats.map_or(0, |s| s.skip_count) as f64),
+            Field::LastPlayed => stats
+                .and_then(|s| s.last_played)
+                .map(|last| now().saturating_sub(last) as f64 / DAY as f64),
+            Field::Rating => Some(f64::from(database.rating(track))),
+            Field::Loved => Some(if database.is_loved(track) { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    /// Reads a value of this field, durations as `90`, `90s`, `10m`, `1h` or `3:30`.
+    fn parse_number(self, value: &str) -> Option<f64> {
+        if self != Field::Duration {
+            return value.parse().ok();
+        }
+
+        if let Some((minutes, seconds)) = value.split_once(':') {
+            let minutes = minutes.parse::<f64>().ok()?;
+            let seconds = seconds.parse::<f64>().ok()?;
+            return Some(minutes * 60.0 + seconds);
+        }
+        let (number, unit) = match value.char_indices().last()? {
+            (i, 's') => (&value[..i], 1.0),
+            (i, 'm') => (&value[..i], 60.0),
+            (i, 'h') => (&value[..i], 3600.0),
+            _ => (value, 1.0),
+        };
+        number.parse::<f64>().ok().map(|n| n * unit)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Folded text the field has to contain.
+    Contains(String),
+    /// Folded text the field has to equal.
+    Equals(String),
+    /// Inclusive bounds, either of which may be open.
+    Range(Option<f64>, Option<f64>),
+    Less(f64),
+    Greater(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Matches every track.
+    All,
+    /// Folded text any searchable field has to contain.
+    Text(String),
+    Field(Field, Condition),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: text.len(),
+        };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(QueryError::new("unexpected `)`", token.span.clone())),
+            None => Ok(query),
+        }
+    }
+
+    pub fn matches(&self, track: &Track, folded: &Folded, database: &Database) -> bool {
+        match self {
+            Query::All => true,
+            Query::Text(text) => folded.haystack.contains(text.as_str()),
+            Query::Field(field, condition) => {
+                if !field.is_numeric() {
+                    let value = field.text(folded);
+                    return match condition {
+                        Condition::Contains(text) => value.contains(text.as_str()),
+                        Condition::Equals(text) => value == text,
+                        _ => false,
+                    };
+                }
+
+                // a missing value, like the year of an untagged track, never matches
+                let Some(value) = field.number(track, database) else {
+                    return false;
+                };
+                match *condition {
+                    Condition::Range(min, max) => {
+                        min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max)
+                    }
+                    Condition::Less(max) => value < max,
+                    Condition::Greater(min) => value > min,
+                    _ => false,
+                }
+            }
+            Query::Not(query) => !query.matches(track, folded, database),
+            Query::And(queries) => queries.iter().all(|q| q.matches(track, folded, database)),
+            Query::Or(queries) => queries.iter().any(|q| q.matches(track, folded, database)),
+        }
+    }
+
+    /// Whether the query is nothing but words that all have to match, so a
+    /// longer version of it can only ever narrow its results down.
+    pub fn is_plain(&self) -> bool {
+        match self {
+            Query::All | Query::Text(_) => true,
+            Query::And(queries) => queries.iter().all(Query::is_plain),
+            _ => false,
+        }
+    }
+
+    /// The words that are not negated and not tied to a field.
+    pub fn words(&self) -> Vec<&str> {
+        match self {
+            Query::Text(text) => vec![text.as_str()],
+            Query::And(queries) => queries.iter().flat_map(Query::words).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    /// Byte range of the offending part of the query.
+    pub span: Range<usize>,
+}
+
+impl QueryError {
+    fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Open,
+    Close,
+    Or,
+    Not,
+    Word(String),
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: Kind,
+    span: Range<usize>,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        let single = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => Some(Kind::Open),
+            ')' => Some(Kind::Close),
+            '|' => Some(Kind::Or),
+            '-' => {
+                // `-` only negates what directly follows it
+                let next = text[start + 1..].chars().next();
+                if next.map_or(true, char::is_whitespace) {
+                    return Err(QueryError::new(
+                        "nothing to negate after `-`",
+                        start..start + 1,
+                    ));
+                }
+                Some(Kind::Not)
+            }
+            _ => None,
+        };
+        if let Some(kind) = single {
+            chars.next();
+            tokens.push(Token {
+                kind,
+                span: start..start + 1,
+            });
+            continue;
+        }
+
+        // a word runs until whitespace or a paren outside of quotes
+        let mut word = String::new();
+        let mut quote = None;
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+            if quote.is_none() && (c.is_whitespace() || c == '(' || c == ')') {
+                break;
+            }
+            if c == '"' {
+                quote = match quote {
+                    Some(_) => None,
+                    None => Some(i),
+                };
+            }
+            word.push(c);
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        if let Some(quote) = quote {
+            return Err(QueryError::new("unterminated quote", quote..text.len()));
+        }
+
+        let kind = if word == "OR" {
+            Kind::Or
+        } else {
+            Kind::Word(word)
+        };
+        tokens.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Kind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let Some(first) = self.and()? else {
+            if self.peek() == Some(&Kind::Or) {
+                let span = self.tokens[self.pos].span.clone();
+                return Err(QueryError::new("nothing before `OR`", span));
+            }
+            return Ok(Query::All);
+        };
+
+        let mut alternatives = vec![first];
+        while self.peek() == Some(&Kind::Or) {
+            let span = self.tokens[self.pos].span.clone();
+            self.pos += 1;
+            let alternative = self
+                .and()?
+                .ok_or_else(|| QueryError::new("nothing after `OR`", span))?;
+            alternatives.push(alternative);
+        }
+
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Query::Or(alternatives),
+        })
+    }
+
+    /// The terms up to the next `OR` or `)`, `None` when there are none.
+    fn and(&mut self) -> Result<Option<Query>, QueryError> {
+        let mut terms = Vec::new();
+        while !matches!(self.peek(), None | Some(Kind::Or) | Some(Kind::Close)) {
+            terms.push(self.unary()?);
+        }
+
+        Ok(match terms.len() {
+            0 => None,
+            1 => Some(terms.remove(0)),
+            _ => Some(Query::And(terms)),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Err(QueryError::new(
+                "expected a search term",
+                self.end..self.end,
+            ));
+        };
+        let span = token.span.clone();
+        self.pos += 1;
+
+        match &token.kind {
+            Kind::Not => match self.peek() {
+                Some(Kind::Word(_)) | Some(Kind::Open) | Some(Kind::Not) => {
+                    Ok(Query::Not(Box::new(self.unary()?)))
+                }
+                _ => Err(QueryError::new("nothing to negate after `-`", span)),
+            },
+            Kind::Open => {
+                let query = self.or()?;
+                if self.peek() != Some(&Kind::Close) {
+                    return Err(QueryError::new("unclosed `(`", span.start..self.end));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            Kind::Word(word) => parse_word(word, span),
+            Kind::Or | Kind::Close => Err(QueryError::new("expected a search term", span)),
+        }
+    }
+}
+
+fn unquote(text: &str) -> String {
+    text.replace('"', "")
+}
+
+fn parse_word(word: &str, span: Range<usize>) -> Result<Query, QueryError> {
+    // `field:value`, as long as the colon is not inside quotes
+    let colon = word.find(':').filter(|&i| !word[..i].contains('"'));
+    let Some(colon) = colon else {
+        return Ok(Query::Text(fold(&unquote(word))));
+    };
+
+    let (name, value) = (&word[..colon], &word[colon + 1..]);
+    let name_span = span.start..span.start + colon;
+    let value_span = span.start + colon + 1..span.end;
+
+    let field = Field::parse(name)
+        .ok_or_else(|| QueryError::new(format!("unknown field `{}`", name), name_span))?;
+    let value = unquote(value);
+    if value.is_empty() {
+        return Err(QueryError::new(
+            format!("missing value for `{}`", name),
+            span,
+        ));
+    }
+
+    if !field.is_numeric() {
+        let condition = match value.strip_prefix('=') {
+            Some(exact) => Condition::Equals(fold(exact)),
+            None => Condition::Contains(fold(&value)),
+        };
+        return Ok(Query::Field(field, condition));
+    }
+
+    let number = |text: &str| {
+        field.parse_number(text.trim()).ok_or_else(|| {
+            QueryError::new(
+                format!("expected a number, got `{}`", text),
+                value_span.clone(),
+            )
+        })
+    };
+    let optional = |text: &str| match text.trim() {
+        "" => Ok(None),
+        text => number(text).map(Some),
+    };
+
+    let condition = if let Some((min, max)) = value.split_once("..") {
+        Condition::Range(optional(min)?, optional(max)?)
+    } else if let Some(n) = value.strip_prefix(">=") {
+        Condition::Range(Some(number(n)?), None)
+    } else if let Some(n) = value.strip_prefix("<=") {
+        Condition::Range(None, Some(number(n)?))
+    } else if let Some(n) = value.strip_prefix('>') {
+        Condition::Greater(number(n)?)
+    } else if let Some(n) = value.strip_prefix('<') {
+        Condition::Less(number(n)?)
+    } else {
+        let n = number(value.strip_prefix('=').unwrap_or(&value))?;
+        Condition::Range(Some(n), Some(n))
+    };
+
+    Ok(Query::Field(field, condition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(word: &str) -> Query {
+        Query::Text(word.to_string())
+    }
+
+    fn error(query: &str) -> QueryError {
+        Query::parse(query).expect_err(query)
+    }
+
+    #[test]
+    fn parses_words_as_and() {
+        assert_eq!(Query::parse("").unwrap(), Query::All);
+        assert_eq!(Query::parse("  ").unwrap(), Query::All);
+        assert_eq!(Query::parse("rock").unwrap(), text("rock"));
+        assert_eq!(
+            Query::parse("rock \"new york\"").unwrap(),
+            Query::And(vec![text("rock"), text("new york")])
+        );
+    }
+
+    #[test]
+    fn parses_or_not_and_groups() {
+        assert_eq!(
+            Query::parse("rock OR jazz | blues").unwrap(),
+            Query::Or(vec![text("rock"), text("jazz"), text("blues")])
+        );
+        assert_eq!(
+            Query::parse("-(rock OR jazz) live").unwrap(),
+            Query::And(vec![
+                Query::Not(Box::new(Query::Or(vec![text("rock"), text("jazz")]))),
+                text("live"),
+            ])
+        );
+        // `-` inside a word is part of it
+        assert_eq!(Query::parse("jay-z").unwrap(), text("jay-z"));
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(
+            Query::parse("artist:\"Miles Davis\"").unwrap(),
+            Query::Field(Field::Artist, Condition::Contains("miles davis".into()))
+        );
+        assert_eq!(
+            Query::parse("genre:=jazz").unwrap(),
+            Query::Field(Field::Genre, Condition::Equals("jazz".into()))
+        );
+        assert_eq!(
+            Query::parse("year:1955..1960").unwrap(),
+            Query::Field(Field::Year, Condition::Range(Some(1955.0), Some(1960.0)))
+        );
+        assert_eq!(
+            Query::parse("year:..1960").unwrap(),
+            Query::Field(Field::Year, Condition::Range(None, Some(1960.0)))
+        );
+        assert_eq!(
+            Query::parse("rating:>=4").unwrap(),
+            Query::Field(Field::Rating, Condition::Range(Some(4.0), None))
+        );
+        assert_eq!(
+            Query::parse("plays:3").unwrap(),
+            Query::Field(Field::Plays, Condition::Range(Some(3.0), Some(3.0)))
+        );
+        assert_eq!(
+            Query::parse("duration:>10m").unwrap(),
+            Query::Field(Field::Duration, Condition::Greater(600.0))
+        );
+        assert_eq!(
+            Query::parse("length:<3:30").unwrap(),
+            Query::Field(Field::Duration, Condition::Less(210.0))
+        );
+    }
+
+    #[test]
+    fn rejects_dangling_operators() {
+        assert_eq!(
+            error("rock OR"),
+            QueryError::new("nothing after `OR`", 5..7)
+        );
+        assert_eq!(
+            error("OR rock"),
+            QueryError::new("nothing before `OR`", 0..2)
+        );
+        assert_eq!(error("rock | | jazz").span, 5..6);
+        assert_eq!(error("(rock OR)").message, "nothing after `OR`");
+        assert_eq!(
+            error("- rock"),
+            QueryError::new("nothing to negate after `-`", 0..1)
+        );
+        assert_eq!(error("rock -").span, 5..6);
+        assert_eq!(error("-)").message, "nothing to negate after `-`");
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        assert_eq!(error("(rock").message, "unclosed `(`");
+        assert_eq!(error("rock)").message, "unexpected `)`");
+        assert_eq!(error("\"rock").message, "unterminated quote");
+        assert_eq!(
+            error("mood:happy"),
+            QueryError::new("unknown field `mood`", 0..4)
+        );
+        assert_eq!(error("year:").message, "missing value for `year`");
+        assert_eq!(error("year:soon").span, 5..9);
+    }
+
+    #[test]
+    fn plain_queries_are_only_words() {
+        assert!(Query::parse("miles davis").unwrap().is_plain());
+        assert!(!Query::parse("miles OR davis").unwrap().is_plain());
+        assert!(!Query::parse("miles -davis").unwrap().is_plain());
+        assert_eq!(
+            Query::parse("miles artist:davis kind").unwrap().words(),
+            ["miles", "kind"]
+        );
+    }
+}
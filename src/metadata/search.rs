@@ -0,0 +1,193 @@
+use std::{collections::HashSet, sync::Arc};
+
+use rayon::prelude::*;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use super::{
+    database::Database,
+    query::{Query, QueryError},
+    track::Track,
+};
+
+const GROUP_LIMIT: usize = 20;
+const TRACK_LIMIT: usize = 500;
+// field separator, so a word cannot match across two fields
+const SEPARATOR: &str = "\u{1f}";
+
+/// Lowercases and strips diacritics, so `Beyoncé` is found by `beyonce`.
+pub fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Folded copies of the searchable fields of a track.
+pub struct Folded {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artist: String,
+    pub genre: String,
+    pub path: String,
+    /// All of the above, for words not tied to a field.
+    pub haystack: String,
+}
+
+impl Folded {
+    fn new(track: &Track) -> Self {
+        let optional = |text: &Option<String>| text.as_deref().map(fold).unwrap_or_default();
+
+        let mut folded = Self {
+            title: fold(&track.title),
+            artist: fold(&track.artist),
+            album: fold(&track.album),
+            album_artist: optional(&track.album_artist),
+            genre: optional(&track.genre),
+            path: optional(&track.file),
+            haystack: String::new(),
+        };
+        folded.haystack = [
+            folded.title.as_str(),
+            &folded.artist,
+            &folded.album,
+            &folded.album_artist,
+            &folded.genre,
+            &folded.path,
+        ]
+        .join(SEPARATOR);
+        folded
+    }
+}
+
+/// The searchable fields of every track in the library, in library order.
+#[derive(Default)]
+pub struct SearchIndex {
+    entries: Vec<Folded>,
+}
+
+impl SearchIndex {
+    pub fn new(tracks: &[Arc<Track>]) -> Self {
+        Self {
+            entries: tracks.par_iter().map(|track| Folded::new(track)).collect(),
+        }
+    }
+
+    /// Refolds the track at `ix` after it changed.
+    pub fn update(&mut self, ix: usize, track: &Track) {
+        self.entries[ix] = Folded::new(track);
+    }
+
+    /// Indices of the `tracks` matching `query`, looking only at `within` when given.
+    pub fn search(
+        &self,
+        tracks: &[Arc<Track>],
+        query: &Query,
+        database: &Database,
+        within: Option<&[usize]>,
+    ) -> Vec<usize> {
+        let matches = |ix: &usize| {
+            !database.is_hidden(&tracks[*ix])
+                && query.matches(&tracks[*ix], &self.entries[*ix], database)
+        };
+
+        match within {
+            Some(within) => within.par_iter().copied().filter(matches).collect(),
+            None => (0..self.entries.len())
+                .into_par_iter()
+                .filter(matches)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SearchResults {
+    pub query: String,
+    /// Why the query could not be parsed, nothing is found then.
+    pub error: Option<QueryError>,
+    /// Indices of the matching tracks in the library.
+    pub matches: Vec<usize>,
+    pub artists: Vec<String>,
+    /// Album and artist.
+    pub albums: Vec<(String, String)>,
+    pub tracks: Vec<Arc<Track>>,
+    // the library change `matches` are indices at
+    pub(super) generation: u64,
+}
+
+impl SearchResults {
+    /// Searches `tracks`, narrowing down `previous` when a plain query only got longer.
+    pub fn new(
+        index: &SearchIndex,
+        tracks: &[Arc<Track>],
+        text: &str,
+        database: &Database,
+        previous: Option<&SearchResults>,
+    ) -> Self {
+        let text = text.trim().to_string();
+        if text.is_empty() {
+            return Self::default();
+        }
+
+        let query = match Query::parse(&text) {
+            Ok(query) => query,
+            Err(error) => {
+                return Self {
+                    query: text,
+                    error: Some(error),
+                    ..Default::default()
+                }
+            }
+        };
+
+        let within = previous
+            .filter(|previous| {
+                previous.error.is_none()
+                    && text.starts_with(&previous.query)
+                    && query.is_plain()
+                    && Query::parse(&previous.query).is_ok_and(|q| q.is_plain())
+            })
+            .map(|previous| previous.matches.as_slice());
+        let matches = index.search(tracks, &query, database, within);
+
+        // artists and albums are listed when their own name has all the plain words
+        let words = query.words();
+        let all_in = |text: &str| !words.is_empty() && words.iter().all(|word| text.contains(word));
+
+        let mut seen_artists = HashSet::new();
+        let mut seen_albums = HashSet::new();
+        let (mut artists, mut albums) = (Vec::new(), Vec::new());
+        for &ix in &matches {
+            let (entry, track) = (&index.entries[ix], &tracks[ix]);
+            if artists.len() < GROUP_LIMIT
+                && all_in(&entry.artist)
+                && seen_artists.insert(&entry.artist)
+            {
+                artists.push(track.artist.clone());
+            }
+            if albums.len() < GROUP_LIMIT
+                && all_in(&format!("{} {}", entry.album, entry.artist))
+                && seen_albums.insert((&entry.album, &entry.artist))
+            {
+                albums.push((track.album.clone(), track.artist.clone()));
+            }
+        }
+
+        let tracks = matches
+            .iter()
+            .take(TRACK_LIMIT)
+            .map(|&ix| Arc::clone(&tracks[ix]))
+            .collect();
+
+        Self {
+            query: text,
+            error: None,
+            matches,
+            artists,
+            albums,
+            tracks,
+            generation: 0,
+        }
+    }
+}
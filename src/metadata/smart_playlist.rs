@@ -0,0 +1,403 @@
+use std::{cmp::Reverse, fmt, sync::Arc};
+
+use log::warn;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    database::{now, Database},
+    library::Library,
+    query::{Query, QueryError},
+    track::Track,
+};
+
+// new playlists match everything until given a query, so they start limited
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartSort {
+    #[default]
+    Random,
+    Title,
+    Artist,
+    Album,
+    Year,
+    HighestRated,
+    MostPlayed,
+    RecentlyPlayed,
+    LeastRecentlyPlayed,
+}
+
+impl SmartSort {
+    pub fn cycle(self) -> Self {
+        match self {
+            SmartSort::Random => SmartSort::Title,
+            SmartSort::Title => SmartSort::Artist,
+            SmartSort::Artist => SmartSort::Album,
+            SmartSort::Album => SmartSort::Year,
+            SmartSort::Year => SmartSort::HighestRated,
+            SmartSort::HighestRated => SmartSort::MostPlayed,
+            SmartSort::MostPlayed => SmartSort::RecentlyPlayed,
+            SmartSort::RecentlyPlayed => SmartSort::LeastRecentlyPlayed,
+            SmartSort::LeastRecentlyPlayed => SmartSort::Random,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SmartSort::Random => "Random",
+            SmartSort::Title => "Title",
+            SmartSort::Artist => "Artist",
+            SmartSort::Album => "Album",
+            SmartSort::Year => "Year",
+            SmartSort::HighestRated => "Highest rated",
+            SmartSort::MostPlayed => "Most played",
+            SmartSort::RecentlyPlayed => "Recently played",
+            SmartSort::LeastRecentlyPlayed => "Least recently played",
+        }
+    }
+}
+
+/// A playlist whose tracks are whatever currently matches its query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredSmartPlaylist")]
+pub struct SmartPlaylist {
+    pub id: u64,
+    pub name: String,
+    /// A search query, e.g. `genre:jazz year:<1970`.
+    pub query: String,
+    /// Rules saved before playlists had queries that have no query
+    /// equivalent. The playlist matches nothing until it gets a new query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unconverted: Option<String>,
+    pub limit: Option<usize>,
+    pub sort: SmartSort,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+    pub modified: u64,
+}
+
+/// A smart playlist as saved, either with a query or with the rules it had
+/// before queries.
+#[derive(Deserialize)]
+struct StoredSmartPlaylist {
+    id: u64,
+    name: String,
+    #[serde(default)]
+    query: Option<String>,
+    #[serde(default)]
+    unconverted: Option<String>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+    #[serde(default)]
+    match_any: bool,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    sort: SmartSort,
+    created: u64,
+    modified: u64,
+}
+
+impl From<StoredSmartPlaylist> for SmartPlaylist {
+    fn from(stored: StoredSmartPlaylist) -> Self {
+        let (query, unconverted) = match stored.query {
+            Some(query) => (query, stored.unconverted),
+            None => match rules_query(&stored.rules, stored.match_any) {
+                Ok(query) => (query, None),
+                Err(rule) => {
+                    warn!(
+                        "Smart playlist {} has a rule with no query equivalent: {}",
+                        stored.name, rule
+                    );
+                    let joiner = if stored.match_any { " or " } else { " and " };
+                    let rules = stored.rules.iter().map(Rule::to_string);
+                    (String::new(), Some(rules.collect::<Vec<_>>().join(joiner)))
+                }
+            },
+        };
+
+        Self {
+            id: stored.id,
+            name: stored.name,
+            query,
+            unconverted,
+            limit: stored.limit,
+            sort: stored.sort,
+            created: stored.created,
+            modified: stored.modified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Path,
+    Year,
+    Duration,
+    PlayCount,
+    SkipCount,
+    LastPlayed,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleOp {
+    Is,
+    IsNot,
+    Contains,
+    NotContains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A condition of a smart playlist from before queries, e.g. `year < 1970`.
+#[derive(Debug, Deserialize)]
+struct Rule {
+    field: RuleField,
+    op: RuleOp,
+    value: String,
+}
+
+impl Rule {
+    /// The same condition as a query word, if there is one.
+    fn to_query(&self) -> Option<String> {
+        let (name, numeric) = match self.field {
+            RuleField::Title => ("title", false),
+            RuleField::Artist => ("artist", false),
+            RuleField::Album => ("album", false),
+            RuleField::Genre => ("genre", false),
+            RuleField::Path => ("path", false),
+            RuleField::Year => ("year", true),
+            RuleField::Duration => ("duration", true),
+            RuleField::PlayCount => ("plays", true),
+            RuleField::SkipCount => ("skips", true),
+            RuleField::LastPlayed => ("last_played", true),
+        };
+        let negated = matches!(self.op, RuleOp::IsNot | RuleOp::NotContains);
+        let sign = if negated { "-" } else { "" };
+
+        if !numeric {
+            // quotes cannot be escaped, and a leading `=` would mean equality
+            let value = &self.value;
+            if value.is_empty() || value.contains('"') || value.starts_with('=') {
+                return None;
+            }
+            let exact = match self.op {
+                RuleOp::Is | RuleOp::IsNot => "=",
+                RuleOp::Contains | RuleOp::NotContains => "",
+                // text was compared in code point order, which queries cannot do
+                _ => return None,
+            };
+            return Some(format!("{}{}:{}\"{}\"", sign, name, exact, value));
+        }
+
+        let number = match self.field {
+            RuleField::Duration => parse_seconds(&self.value)?,
+            _ => self.value.trim().parse::<f64>().ok()?,
+        };
+        let op = match self.op {
+            RuleOp::Is | RuleOp::IsNot | RuleOp::Contains | RuleOp::NotContains => "",
+            RuleOp::Less => "<",
+            RuleOp::LessOrEqual => "<=",
+            RuleOp::Greater => ">",
+            RuleOp::GreaterOrEqual => ">=",
+        };
+        Some(format!("{}{}:{}{}", sign, name, op, number))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self.field {
+            RuleField::Title => "title",
+            RuleField::Artist => "artist",
+            RuleField::Album => "album",
+            RuleField::Genre => "genre",
+            RuleField::Path => "path",
+            RuleField::Year => "year",
+            RuleField::Duration => "duration",
+            RuleField::PlayCount => "play_count",
+            RuleField::SkipCount => "skip_count",
+            RuleField::LastPlayed => "last_played",
+        };
+        let op = match self.op {
+            RuleOp::Is => "is",
+            RuleOp::IsNot => "is not",
+            RuleOp::Contains => "contains",
+            RuleOp::NotContains => "does not contain",
+            RuleOp::Less => "<",
+            RuleOp::LessOrEqual => "<=",
+            RuleOp::Greater => ">",
+            RuleOp::GreaterOrEqual => ">=",
+        };
+        write!(f, "{} {} \"{}\"", field, op, self.value)
+    }
+}
+
+/// Reads `90`, `90s`, `10m` or `1h` as seconds, like the old rules did.
+fn parse_seconds(value: &str) -> Option<f64> {
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1.0),
+        (i, 'm') => (&value[..i], 60.0),
+        (i, 'h') => (&value[..i], 3600.0),
+        _ => (value, 1.0),
+    };
+    number.trim().parse::<f64>().ok().map(|n| n * unit)
+}
+
+/// Turns old rules into a query, or returns the first one that has no
+/// query equivalent.
+fn rules_query(rules: &[Rule], match_any: bool) -> Result<String, &Rule> {
+    let words = rules
+        .iter()
+        .map(|rule| rule.to_query().ok_or(rule))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(words.join(if match_any { " OR " } else { " " }))
+}
+
+impl SmartPlaylist {
+    pub fn new(id: u64, name: String) -> Self {
+        let created = now();
+        Self {
+            id,
+            name,
+            query: String::new(),
+            unconverted: None,
+            limit: Some(DEFAULT_LIMIT),
+            sort: SmartSort::default(),
+            created,
+            modified: created,
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.modified = now();
+    }
+
+    /// Replaces the query, keeping the old one if `query` does not parse.
+    pub fn set_query(&mut self, query: &str) -> Result<(), QueryError> {
+        Query::parse(query)?;
+        self.query = query.trim().to_string();
+        self.unconverted = None;
+        self.touch();
+        Ok(())
+    }
+
+    /// The tracks matching the query, sorted and limited.
+    pub fn evaluate(&self, library: &Library, database: &Database) -> Vec<Arc<Track>> {
+        if self.unconverted.is_some() {
+            return Vec::new();
+        }
+        let Ok(query) = Query::parse(&self.query) else {
+            return Vec::new();
+        };
+        let mut tracks = library.find(&query, database);
+
+        let last_played = |track: &Track| database.stats(track).and_then(|stats| stats.last_played);
+        match self.sort {
+            SmartSort::Random => {
+                // stable between evaluations until the playlist is edited
+                let mut rng = StdRng::seed_from_u64(self.id ^ self.modified);
+                tracks.shuffle(&mut rng);
+            }
+            SmartSort::Title => tracks.sort_by_key(|t| t.title.to_lowercase()),
+            SmartSort::Artist => tracks.sort_by_key(|t| t.artist.to_lowercase()),
+            SmartSort::Album => tracks.sort_by_key(|t| t.album.to_lowercase()),
+            SmartSort::Year => tracks.sort_by_key(|t| t.year),
+            SmartSort::HighestRated => tracks.sort_by_key(|t| Reverse(database.rating(t))),
+            SmartSort::MostPlayed => tracks.sort_by_key(|t| Reverse(database.play_count(t))),
+            SmartSort::RecentlyPlayed => tracks.sort_by_key(|t| Reverse(last_played(t))),
+            SmartSort::LeastRecentlyPlayed => tracks.sort_by_key(|t| last_played(t)),
+        }
+
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+        tracks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(rules: &str, match_any: bool) -> SmartPlaylist {
+        let json = format!(
+            r#"{{"id": 1, "name": "Old", "rules": {}, "match_any": {}, "created": 0, "modified": 0}}"#,
+            rules, match_any
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn converts_old_rules_to_a_query() {
+        let playlist = load(
+            r#"[
+                {"field": "artist", "op": "contains", "value": "Miles Davis"},
+                {"field": "genre", "op": "is_not", "value": "Live"},
+                {"field": "year", "op": "less", "value": "1970"},
+                {"field": "duration", "op": "greater_or_equal", "value": "10m"},
+                {"field": "skip_count", "op": "is", "value": "0"}
+            ]"#,
+            false,
+        );
+        assert_eq!(
+            playlist.query,
+            r#"artist:"Miles Davis" -genre:="Live" year:<1970 duration:>=600 skips:0"#
+        );
+        assert_eq!(playlist.unconverted, None);
+        assert!(Query::parse(&playlist.query).is_ok());
+
+        let playlist = load(
+            r#"[
+                {"field": "genre", "op": "is", "value": "Jazz"},
+                {"field": "play_count", "op": "greater", "value": "5"}
+            ]"#,
+            true,
+        );
+        assert_eq!(playlist.query, r#"genre:="Jazz" OR plays:>5"#);
+    }
+
+    #[test]
+    fn keeps_old_rules_without_an_equivalent_from_matching() {
+        let playlist = load(
+            r#"[
+                {"field": "year", "op": "less", "value": "1970"},
+                {"field": "title", "op": "less", "value": "M"}
+            ]"#,
+            false,
+        );
+        assert_eq!(playlist.query, "");
+        assert_eq!(
+            playlist.unconverted.as_deref(),
+            Some(r#"year < "1970" and title < "M""#)
+        );
+    }
+
+    #[test]
+    fn an_empty_old_playlist_still_matches_everything() {
+        let playlist = load("[]", false);
+        assert_eq!(playlist.query, "");
+        assert_eq!(playlist.unconverted, None);
+    }
+
+    #[test]
+    fn saves_queries_without_rules() {
+        let mut playlist = SmartPlaylist::new(1, "New".into());
+        playlist.set_query("genre:jazz").unwrap();
+        let json = serde_json::to_string(&playlist).unwrap();
+        assert!(!json.contains("rules") && !json.contains("unconverted"));
+
+        let loaded: SmartPlaylist = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.query, "genre:jazz");
+    }
+}
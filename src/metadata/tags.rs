@@ -0,0 +1,536 @@
+use std::{borrow::Cow, path::Path};
+
+use anyhow::{anyhow, Result};
+use lofty::{
+    config::WriteOptions,
+    file::{AudioFile, TaggedFileExt},
+    probe::Probe,
+    tag::{Accessor, ItemKey, ItemValue, Tag, TagItem, TagType},
+};
+use log::error;
+
+use super::{cover::add_sidecar_covers, track::Track};
+
+/// A tag the editor can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Year,
+    TrackNumber,
+    TrackTotal,
+    DiscNumber,
+    DiscTotal,
+    Composer,
+    Comment,
+    TitleSort,
+    ArtistSort,
+    AlbumSort,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 15] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::Genre,
+        TagField::Year,
+        TagField::TrackNumber,
+        TagField::TrackTotal,
+        TagField::DiscNumber,
+        TagField::DiscTotal,
+        TagField::Composer,
+        TagField::Comment,
+        TagField::TitleSort,
+        TagField::ArtistSort,
+        TagField::AlbumSort,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagField::Title => "Title",
+            TagField::Artist => "Artist",
+            TagField::Album => "Album",
+            TagField::AlbumArtist => "Album artist",
+            TagField::Genre => "Genre",
+            TagField::Year => "Year",
+            TagField::TrackNumber => "Track",
+            TagField::TrackTotal => "Tracks",
+            TagField::DiscNumber => "Disc",
+            TagField::DiscTotal => "Discs",
+            TagField::Composer => "Composer",
+            TagField::Comment => "Comment",
+            TagField::TitleSort => "Sort title",
+            TagField::ArtistSort => "Sort artist",
+            TagField::AlbumSort => "Sort album",
+        }
+    }
+
+    pub fn is_number(self) -> bool {
+        matches!(
+            self,
+            TagField::Year
+                | TagField::TrackNumber
+                | TagField::TrackTotal
+                | TagField::DiscNumber
+                | TagField::DiscTotal
+        )
+    }
+
+    /// Fields without an `Accessor` method, set by key.
+    fn key(self) -> Option<ItemKey> {
+        match self {
+            TagField::AlbumArtist => Some(ItemKey::AlbumArtist),
+            TagField::Composer => Some(ItemKey::Composer),
+            TagField::TitleSort => Some(ItemKey::TrackTitleSortOrder),
+            TagField::ArtistSort => Some(ItemKey::TrackArtistSortOrder),
+            TagField::AlbumSort => Some(ItemKey::AlbumTitleSortOrder),
+            _ => None,
+        }
+    }
+
+    pub fn get(self, tag: &Tag) -> Option<String> {
+        if let Some(key) = self.key() {
+            return tag.get_string(&key).map(String::from);
+        }
+
+        let number = |n: Option<u32>| n.map(|n| n.to_string());
+        match self {
+            TagField::Title => tag.title().map(Cow::into_owned),
+            TagField::Artist => tag.artist().map(Cow::into_owned),
+            TagField::Album => tag.album().map(Cow::into_owned),
+            TagField::Genre => tag.genre().map(Cow::into_owned),
+            TagField::Comment => tag.comment().map(Cow::into_owned),
+            TagField::Year => number(tag.year()),
+            TagField::TrackNumber => number(tag.track()),
+            TagField::TrackTotal => number(tag.track_total()),
+            TagField::DiscNumber => number(tag.disk()),
+            TagField::DiscTotal => number(tag.disk_total()),
+            _ => None,
+        }
+    }
+
+    /// The number in `value` for a number field, `None` when it is blank.
+    fn number(self, value: &str) -> Result<Option<u32>> {
+        match value.trim() {
+            "" => Ok(None),
+            value => value
+                .parse()
+                .map(Some)
+                .map_err(|_| anyhow!("{} must be a number", self.label())),
+        }
+    }
+
+    /// Sets the field to `value`, removing it when `value` is blank.
+    pub fn set(self, tag: &mut Tag, value: &str) -> Result<()> {
+        let value = value.trim();
+        if let Some(key) = self.key() {
+            if value.is_empty() {
+                tag.remove_key(&key);
+            } else {
+                tag.insert_text(key, value.to_string());
+            }
+            return Ok(());
+        }
+
+        if self.is_number() {
+            match (self, self.number(value)?) {
+                (TagField::Year, Some(n)) => tag.set_year(n),
+                (TagField::Year, None) => tag.remove_year(),
+                (TagField::TrackNumber, Some(n)) => tag.set_track(n),
+                (TagField::TrackNumber, None) => tag.remove_track(),
+                (TagField::TrackTotal, Some(n)) => tag.set_track_total(n),
+                (TagField::TrackTotal, None) => tag.remove_track_total(),
+                (TagField::DiscNumber, Some(n)) => tag.set_disk(n),
+                (TagField::DiscNumber, None) => tag.remove_disk(),
+                (TagField::DiscTotal, Some(n)) => tag.set_disk_total(n),
+                (TagField::DiscTotal, None) => tag.remove_disk_total(),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let text = (!value.is_empty()).then(|| value.to_string());
+        match (self, text) {
+            (TagField::Title, Some(text)) => tag.set_title(text),
+            (TagField::Title, None) => tag.remove_title(),
+            (TagField::Artist, Some(text)) => tag.set_artist(text),
+            (TagField::Artist, None) => tag.remove_artist(),
+            (TagField::Album, Some(text)) => tag.set_album(text),
+            (TagField::Album, None) => tag.remove_album(),
+            (TagField::Genre, Some(text)) => tag.set_genre(text),
+            (TagField::Genre, None) => tag.remove_genre(),
+            (TagField::Comment, Some(text)) => tag.set_comment(text),
+            (TagField::Comment, None) => tag.remove_comment(),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// What a field holds across the files being edited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldValue {
+    /// The same in every file, empty where none has it.
+    Same(String),
+    Mixed,
+}
+
+fn read_tag(file: impl AsRef<Path>) -> Result<Option<Tag>> {
+    let tagged_file = Probe::open(file)?.read()?;
+    Ok(tagged_file.primary_tag().cloned())
+}
+
+/// Every field as it is in `files`.
+pub fn read_fields(files: &[String]) -> Result<Vec<(TagField, FieldValue)>> {
+    let tags = files
+        .iter()
+        .map(|file| read_tag(file))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(TagField::ALL
+        .into_iter()
+        .map(|field| {
+            let mut values = tags.iter().map(|tag| {
+                tag.as_ref()
+                    .and_then(|tag| field.get(tag))
+                    .unwrap_or_default()
+            });
+            let first = values.next().unwrap_or_default();
+            let value = if values.all(|value| value == first) {
+                FieldValue::Same(first)
+            } else {
+                FieldValue::Mixed
+            };
+            (field, value)
+        })
+        .collect())
+}
+
+/// The fields `file` has.
+pub fn read(file: &Path) -> Result<Vec<(TagField, String)>> {
+    let Some(tag) = read_tag(file)? else {
+        return Ok(Vec::new());
+    };
+    Ok(TagField::ALL
+        .into_iter()
+        .filter_map(|field| field.get(&tag).map(|value| (field, value)))
+        .collect())
+}
+
+/// Checks that `changes` can be written, so a bad value is caught before
+/// any file is touched.
+pub fn validate(changes: &[(TagField, String)]) -> Result<()> {
+    for (field, value) in changes {
+        if field.is_number() {
+            field.number(value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies `changes` to the tags of `file` and saves them.
+pub fn write(file: &Path, changes: &[(TagField, String)]) -> Result<()> {
+    validate(changes)?;
+    update(file, |tag| {
+        for (field, value) in changes {
+            field.set(tag, value)?;
+        }
+        Ok(())
+    })
+}
+
+/// Changes every tag of `file` with `f` and saves them, creating a tag of
+/// the file's usual kind if it has none, so no tag is left stale.
+pub fn update(file: &Path, f: impl Fn(&mut Tag) -> Result<()>) -> Result<()> {
+    let mut tagged_file = Probe::open(file)?.read()?;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        if !tagged_file.supports_tag_type(tag_type) {
+            return Err(anyhow!("{} cannot hold tags", file.display()));
+        }
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+
+    let tag_types = tagged_file
+        .tags()
+        .iter()
+        .map(Tag::tag_type)
+        .collect::<Vec<_>>();
+    for tag_type in tag_types {
+        if let Some(tag) = tagged_file.tag_mut(tag_type) {
+            f(tag)?;
+        }
+    }
+    tagged_file.save_to_path(file, WriteOptions::default())?;
+    Ok(())
+}
+
+// POPM rating bytes for 0 to 5 stars, as Windows Media Player writes them
+const POPM_STARS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+// the email POPM frames written here are told apart by, other players keep
+// their own frames
+const POPM_USER: &str = "ggmusic";
+const LOVED: &str = "LOVED";
+
+/// Stars from a POPM rating byte.
+fn popm_stars(byte: u8) -> u8 {
+    match byte {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+/// The email and rating byte of a POPM item, stored as the email, a nul,
+/// the rating byte and a play counter.
+fn popm(item: &TagItem) -> Option<(&[u8], u8)> {
+    match item.value() {
+        ItemValue::Binary(bytes) => {
+            let nul = bytes.iter().position(|b| *b == 0)?;
+            Some((&bytes[..nul], *bytes.get(nul + 1)?))
+        }
+        ItemValue::Text(text) => Some((&[], text.trim().parse().ok()?)),
+        _ => None,
+    }
+}
+
+fn unknown_key(key: &str) -> ItemKey {
+    ItemKey::Unknown(key.to_string())
+}
+
+/// The 0 to 5 star rating in `tag`, from a POPM frame (ours before other
+/// players'), `FMPS_RATING` (0 to 1) or `RATING` (0 to 5, or 0 to 100).
+pub fn rating(tag: &Tag) -> Option<u8> {
+    let popms = tag
+        .get_items(&ItemKey::Popularimeter)
+        .filter_map(popm)
+        .collect::<Vec<_>>();
+    let byte = popms
+        .iter()
+        .find(|(user, _)| *user == POPM_USER.as_bytes())
+        .or(popms.first())
+        .map(|(_, byte)| *byte);
+    if let Some(byte) = byte {
+        return Some(popm_stars(byte));
+    }
+
+    let unknown = |key: &str| {
+        tag.get_string(&unknown_key(key))
+            .and_then(|value| value.trim().parse::<f64>().ok())
+    };
+    if let Some(fmps) = unknown("FMPS_RATING").or_else(|| unknown("FMPS_Rating")) {
+        return Some((fmps.clamp(0.0, 1.0) * 5.0).round() as u8);
+    }
+    unknown("RATING").map(|rating| match rating {
+        r if r <= 5.0 => r.max(0.0).round() as u8,
+        r => (r.min(100.0) / 20.0).round() as u8,
+    })
+}
+
+/// Whether `tag` marks the track as loved.
+pub fn loved(tag: &Tag) -> bool {
+    tag.get_string(&unknown_key(LOVED))
+        .is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+/// Writes `stars` (0 removes the rating) to the tags of `file`: our own POPM
+/// frame for ID3v2, leaving other players' frames alone, and `FMPS_RATING`
+/// and `RATING` for everything else.
+pub fn write_rating(file: &Path, stars: u8) -> Result<()> {
+    let stars = stars.min(5);
+    update(file, |tag| {
+        if tag.tag_type() == TagType::Id3v2 {
+            let ours = |item: &TagItem| {
+                item.key() == &ItemKey::Popularimeter
+                    && popm(item).is_some_and(|(user, _)| user == POPM_USER.as_bytes())
+            };
+            // the play counter of our frame is kept
+            let counter = tag
+                .items()
+                .find(|item| ours(item))
+                .and_then(|item| match item.value() {
+                    ItemValue::Binary(bytes) => {
+                        bytes.get(POPM_USER.len() + 2..).map(<[u8]>::to_vec)
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default();
+            tag.retain(|item| !ours(item));
+            if stars > 0 {
+                let mut bytes = POPM_USER.as_bytes().to_vec();
+                bytes.push(0);
+                bytes.push(POPM_STARS[stars as usize]);
+                bytes.extend(counter);
+                tag.push(TagItem::new(
+                    ItemKey::Popularimeter,
+                    ItemValue::Binary(bytes),
+                ));
+            }
+            return Ok(());
+        }
+
+        let (fmps, rating) = (unknown_key("FMPS_RATING"), unknown_key("RATING"));
+        if stars == 0 {
+            tag.remove_key(&fmps);
+            tag.remove_key(&rating);
+        } else {
+            tag.insert_text(fmps, format!("{:.1}", f64::from(stars) / 5.0));
+            tag.insert_text(rating, (u32::from(stars) * 20).to_string());
+        }
+        Ok(())
+    })
+}
+
+/// Marks `file` as loved in its tags, or takes the mark away.
+pub fn write_loved(file: &Path, loved: bool) -> Result<()> {
+    update(file, |tag| {
+        if loved {
+            tag.insert_text(unknown_key(LOVED), "1".to_string());
+        } else {
+            tag.remove_key(&unknown_key(LOVED));
+        }
+        Ok(())
+    })
+}
+
+/// Runs `write` on every file and reads it back as a track, taking covers
+/// from images named after `cover_names`. Failures are logged and returned
+/// as one line per file.
+pub fn rewrite(
+    files: &[String],
+    write: impl Fn(&Path) -> Result<()>,
+    cover_names: &[String],
+) -> (Vec<Track>, Vec<String>) {
+    let mut tracks = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let path = Path::new(file);
+        match write(path).and_then(|_| Track::read(path)) {
+            Ok(track) => tracks.push(track),
+            Err(err) => {
+                error!("Failed to write tags of {}: {}", file, err);
+                errors.push(format!("{}: {}", file, err));
+            }
+        }
+    }
+    add_sidecar_covers(&mut tracks, cover_names);
+    (tracks, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// A short silent WAV, which keeps its tags in ID3v2.
+    fn wav(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("track.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for _ in 0..spec.sample_rate {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn popm_item(user: &str, byte: u8) -> TagItem {
+        let mut bytes = user.as_bytes().to_vec();
+        bytes.extend([0, byte, 0, 0, 0, 7]);
+        TagItem::new(ItemKey::Popularimeter, ItemValue::Binary(bytes))
+    }
+
+    fn text_tag(items: &[(&str, &str)]) -> Tag {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        for (key, value) in items {
+            tag.insert_text(unknown_key(key), value.to_string());
+        }
+        tag
+    }
+
+    #[test]
+    fn maps_popm_bytes_to_stars_and_back() {
+        for stars in 0..=5 {
+            assert_eq!(popm_stars(POPM_STARS[stars as usize]), stars);
+        }
+        assert_eq!(popm_stars(31), 1);
+        assert_eq!(popm_stars(32), 2);
+        assert_eq!(popm_stars(159), 3);
+        assert_eq!(popm_stars(223), 4);
+        assert_eq!(popm_stars(224), 5);
+    }
+
+    #[test]
+    fn reads_our_popm_frame_before_other_players() {
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.push(popm_item("someone@example.com", 255));
+        assert_eq!(rating(&tag), Some(5));
+
+        tag.push(popm_item(POPM_USER, POPM_STARS[2]));
+        assert_eq!(rating(&tag), Some(2));
+    }
+
+    #[test]
+    fn reads_fmps_and_rating_text() {
+        assert_eq!(rating(&text_tag(&[])), None);
+        assert_eq!(rating(&text_tag(&[("FMPS_RATING", "0.6")])), Some(3));
+        assert_eq!(rating(&text_tag(&[("FMPS_RATING", "7")])), Some(5));
+        assert_eq!(rating(&text_tag(&[("RATING", "4")])), Some(4));
+        assert_eq!(rating(&text_tag(&[("RATING", "80")])), Some(4));
+        assert_eq!(rating(&text_tag(&[("RATING", "bad")])), None);
+        // FMPS wins over RATING
+        assert_eq!(
+            rating(&text_tag(&[("FMPS_RATING", "0.2"), ("RATING", "100")])),
+            Some(1)
+        );
+
+        assert!(loved(&text_tag(&[(LOVED, " true ")])));
+        assert!(!loved(&text_tag(&[(LOVED, "0")])));
+        assert!(!loved(&text_tag(&[])));
+    }
+
+    #[test]
+    fn rating_and_loved_survive_an_id3v2_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = wav(dir.path());
+        update(&file, |tag| {
+            tag.push(popm_item("someone@example.com", 255));
+            Ok(())
+        })
+        .unwrap();
+
+        write_rating(&file, 3).unwrap();
+        write_loved(&file, true).unwrap();
+        let tag = read_tag(&file).unwrap().unwrap();
+        assert_eq!(tag.tag_type(), TagType::Id3v2);
+        assert_eq!(rating(&tag), Some(3));
+        assert!(loved(&tag));
+        // the other player's frame is left alone
+        let users = tag
+            .get_items(&ItemKey::Popularimeter)
+            .filter_map(popm)
+            .map(|(user, _)| user.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(users.len(), 2);
+
+        write_rating(&file, 0).unwrap();
+        write_loved(&file, false).unwrap();
+        let tag = read_tag(&file).unwrap().unwrap();
+        assert_eq!(rating(&tag), Some(5));
+        assert!(!loved(&tag));
+    }
+}
@@ -1,31 +1,74 @@
-use std::{borrow::Cow, ffi::OsStr, path::Path, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow, collections::HashSet, ffi::OsStr, ops::Range, path::Path, sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Result;
 use gpui::{
-    div, img, px, ElementId, FontWeight, ImageData, InteractiveElement, IntoElement, ParentElement,
-    Render, RenderOnce, StatefulInteractiveElement, Styled, View, ViewContext, VisualContext,
+    div, img, prelude::FluentBuilder, px, uniform_list, AnyElement, AppContext, ClickEvent, Div,
+    ElementId, EventEmitter, InteractiveElement, IntoElement, MouseButton, MouseDownEvent,
+    MouseMoveEvent, MouseUpEvent, ParentElement, Pixels, Render, RenderOnce, Stateful,
+    StatefulInteractiveElement, Styled, UniformListScrollHandle, View, ViewContext, VisualContext,
     WindowContext,
 };
 use lofty::{
     file::{AudioFile, FileType, TaggedFileExt},
-    picture::{self, Picture, PictureType},
     probe::Probe,
     tag::{Accessor, ItemKey},
 };
 
-use crate::{events::UiEvent, theme::Theme};
+use crate::{events::UiEvent, settings::Settings, theme::Theme};
+
+use super::{
+    columns::{Column, SortKey, TrackLayout},
+    cover::{self, front_picture, Cover, CoverSize, CoversModel},
+    database::DatabaseModel,
+    tags,
+};
 
-use super::library::LibraryModel;
+const SPOKEN_WORD_GENRES: [&str; 6] = [
+    "Podcast",
+    "Audiobook",
+    "Speech",
+    "Spoken Word",
+    "Spoken & Audio",
+    "Lecture",
+];
 
 #[derive(Debug, Clone, IntoElement)]
 pub struct Track {
     pub artist: String,
     pub title: String,
     pub album: String,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    /// In kbps.
+    pub bitrate: Option<u32>,
+    /// 0 to 5 stars from the tags, the library database may override it.
+    pub rating: Option<u8>,
+    /// Loved in the tags, the library database may override it.
+    pub loved: bool,
+    pub title_sort: Option<String>,
+    pub artist_sort: Option<String>,
+    pub album_sort: Option<String>,
     pub file: Option<String>,
     duration: Duration,
-    pub cover: Option<Arc<ImageData>>,
+    pub cover: Option<Cover>,
     file_type: Option<FileType>,
+    /// Set for tracks of a cue sheet, whose `file` is the image's path
+    /// followed by `#<track number>`.
+    pub segment: Option<Segment>,
+}
+
+/// Where a track of a cue sheet sits in its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub start: Duration,
+    /// `None` for the last track, which plays to the end of the file.
+    pub end: Option<Duration>,
 }
 
 impl PartialEq for Track {
@@ -39,8 +82,59 @@ impl Track {
         self.file_type.is_some()
     }
 
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The file on disk, without the `#<n>` of a cue sheet track.
+    pub fn path(&self) -> Option<&str> {
+        let file = self.file.as_deref()?;
+        match self.segment {
+            Some(_) => file.rsplit_once('#').map(|(path, _)| path),
+            None => Some(file),
+        }
+    }
+
+    /// Whether `next` picks up in the same file right where this one ends.
+    pub fn continues_into(&self, next: &Track) -> bool {
+        match (self.segment, next.segment) {
+            (Some(this), Some(next_segment)) => {
+                this.end == Some(next_segment.start) && self.path() == next.path()
+            }
+            _ => false,
+        }
+    }
+
+    /// Track `number` of a cue sheet, cut out of this whole file.
+    pub fn cut(&self, number: u32, segment: Segment) -> Self {
+        let path = self.path().unwrap_or_default();
+        let end = segment.end.unwrap_or(self.duration);
+        Self {
+            file: Some(format!("{}#{}", path, number)),
+            duration: end.saturating_sub(segment.start),
+            track_number: Some(number),
+            segment: Some(segment),
+            ..self.clone()
+        }
+    }
+
+    /// Podcasts, audiobooks and the like, whose playback speed is remembered per track.
+    pub fn is_spoken_word(&self) -> bool {
+        self.genre.as_deref().is_some_and(|genre| {
+            SPOKEN_WORD_GENRES
+                .iter()
+                .any(|spoken| genre.eq_ignore_ascii_case(spoken))
+        })
+    }
+
     pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_with_cue(path).map(|(track, _)| track)
+    }
+
+    /// Reads the track along with the cue sheet embedded in its tags.
+    pub fn read_with_cue<P: AsRef<Path>>(path: P) -> Result<(Self, Option<String>)> {
         let path = path.as_ref();
+        let mut cue_sheet = None;
 
         let probe = Probe::open(path)?;
 
@@ -48,6 +142,7 @@ impl Track {
         if let Ok(mut tagged_file) = probe.read() {
             let properties = tagged_file.properties();
             song.duration = properties.duration();
+            song.bitrate = properties.audio_bitrate();
             song.file_type = Some(tagged_file.file_type());
 
             if let Some(tag) = tagged_file.primary_tag_mut() {
@@ -67,25 +162,31 @@ impl Track {
                     .title()
                     .map(Cow::into_owned)
                     .unwrap_or("UNKNOWN".to_string());
+                song.album_artist = tag.get_string(&ItemKey::AlbumArtist).map(String::from);
+                song.genre = tag.genre().map(Cow::into_owned);
+                song.year = tag.year();
+                song.disc_number = tag.disk();
+                song.track_number = tag.track();
+                song.rating = tags::rating(tag);
+                song.loved = tags::loved(tag);
 
-                let mut picture = tag
-                    .pictures()
-                    .iter()
-                    .find(|pic| pic.pic_type() == PictureType::CoverFront);
-                if picture.is_none() {
-                    picture = tag.pictures().first();
-                }
-                // TODO: default album cover
-                let bytes = picture.map(|pic| pic.data());
-                if let Some(bytes) = bytes {
-                    let format = image::guess_format(bytes)?;
-                    let data = image::load_from_memory_with_format(bytes, format)?.into_bgra8();
-                    song.cover = Some(Arc::new(ImageData::new(data)));
-                }
+                let sort_tag = |key| tag.get_string(&key).map(String::from);
+                song.title_sort = sort_tag(ItemKey::TrackTitleSortOrder);
+                song.artist_sort = sort_tag(ItemKey::TrackArtistSortOrder);
+                song.album_sort = sort_tag(ItemKey::AlbumTitleSortOrder);
+
+                // only hashed here, decoding waits until the cover is shown
+                song.cover = front_picture(tag).map(|pic| Cover::embedded(path, pic.data()));
+
+                // Vorbis comments and APE tags
+                cue_sheet = ["CUESHEET", "Cuesheet"].into_iter().find_map(|key| {
+                    tag.get_string(&ItemKey::Unknown(key.to_string()))
+                        .map(String::from)
+                });
             }
         }
 
-        Ok(song)
+        Ok((song, cue_sheet))
     }
 }
 
@@ -103,16 +204,29 @@ impl Track {
             artist: String::new(),
             title,
             album: String::new(),
+            album_artist: None,
+            genre: None,
+            year: None,
+            disc_number: None,
+            track_number: None,
+            bitrate: None,
+            rating: None,
+            loved: false,
+            title_sort: None,
+            artist_sort: None,
+            album_sort: None,
             file,
             duration,
             cover: None,
             file_type: None,
+            segment: None,
         }
     }
 }
 
-impl RenderOnce for Track {
-    fn render(self, cx: &mut WindowContext) -> impl gpui::IntoElement {
+impl Track {
+    /// Cover, title, artist and album, as shown in track lists.
+    pub fn render_row(&self, cx: &mut WindowContext) -> Stateful<Div> {
         let theme = cx.global::<Theme>();
 
         let e = div()
@@ -128,10 +242,10 @@ impl RenderOnce for Track {
             })
             .text_color(theme.text);
 
-        let e = if let Some(cover) = self.cover.clone() {
+        let e = if let Some(cover) = cover::cover(self, CoverSize::List, cx) {
             e.child(img(cover).flex_none().w_16().h_16())
         } else {
-            e
+            e.child(cover::placeholder(self, cx).w_16().h_16())
         };
 
         e.child(
@@ -150,23 +264,551 @@ impl RenderOnce for Track {
     }
 }
 
+impl RenderOnce for Track {
+    fn render(self, cx: &mut WindowContext) -> impl gpui::IntoElement {
+        self.render_row(cx)
+    }
+}
+
+/// A column header being dragged to a new place.
+#[derive(Clone)]
+struct DraggedColumn {
+    index: usize,
+    label: &'static str,
+}
+
+impl Render for DraggedColumn {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        div()
+            .px_2()
+            .rounded_sm()
+            .bg(theme.surface0)
+            .text_color(theme.text)
+            .child(self.label)
+    }
+}
+
+struct Resize {
+    index: usize,
+    start_x: Pixels,
+    start_width: f32,
+    width: f32,
+}
+
 #[derive(Default)]
 pub struct Tracks {
-    pub tracks: Vec<Track>,
+    pub tracks: Vec<Arc<Track>>,
+    // indices into `tracks` in table order, with the sort they were made for
+    sorted: Option<(Vec<SortKey>, Vec<usize>)>,
+    // file of the track that is playing, highlighted when listed
+    current: Option<String>,
+    // indices into `tracks`
+    selected: HashSet<usize>,
+    // row a shift-click selects from
+    anchor: Option<usize>,
+    scroll: UniformListScrollHandle,
+    resizing: Option<Resize>,
+    choosing_columns: bool,
 }
 
 impl Tracks {
-    pub fn new(cx: &mut WindowContext, model: &LibraryModel) -> View<Self> {
-        cx.new_view(|cx| model.get_tracks(cx))
+    pub fn new(cx: &mut WindowContext, tracks: Vec<Arc<Track>>) -> View<Self> {
+        cx.new_view(|cx| {
+            let covers = cx.global::<CoversModel>().clone();
+            cx.observe(covers.model(), |_, _, cx| cx.notify()).detach();
+            // ratings are shown in the rows, and play counts may be sorted by
+            if let Some(database) = cx.try_global::<DatabaseModel>().cloned() {
+                cx.observe(database.model(), |this: &mut Self, _, cx| {
+                    this.sorted = None;
+                    cx.notify();
+                })
+                .detach();
+            }
+
+            let mut this = Self::default();
+            this.set_tracks(tracks, cx);
+            this
+        })
+    }
+
+    pub fn set_tracks(&mut self, tracks: Vec<Arc<Track>>, cx: &mut ViewContext<Self>) {
+        self.tracks = tracks;
+        self.sorted = None;
+        self.selected.clear();
+        self.anchor = None;
+        cx.notify();
+    }
+
+    pub fn set_current(&mut self, file: Option<String>, cx: &mut ViewContext<Self>) {
+        self.current = file;
+        cx.notify();
+    }
+
+    /// Scrolls the playing track into view, if it is listed.
+    fn scroll_to_current(&mut self, cx: &mut ViewContext<Self>) {
+        let layout = cx.global::<Settings>().track_layout.clone();
+        self.sort(&layout, cx);
+
+        let row = self.current.as_ref().and_then(|current| {
+            (0..self.tracks.len()).find(|&row| {
+                self.tracks[self.track_index(&layout, row)].file.as_ref() == Some(current)
+            })
+        });
+        if let Some(row) = row {
+            self.scroll.scroll_to_item(row);
+            cx.notify();
+        }
+    }
+
+    /// Plays the track at `row` and selects it alone. Ctrl-click adds or
+    /// removes it from the selection, shift-click selects up to it.
+    fn click(&mut self, row: usize, event: &ClickEvent, cx: &mut ViewContext<Self>) {
+        let layout = cx.global::<Settings>().track_layout.clone();
+        let ix = self.track_index(&layout, row);
+        let modifiers = event.down.modifiers;
+
+        if modifiers.shift {
+            let anchor = self.anchor.unwrap_or(row);
+            self.selected = (anchor.min(row)..=anchor.max(row))
+                .map(|row| self.track_index(&layout, row))
+                .collect();
+        } else if modifiers.control || modifiers.platform {
+            if !self.selected.remove(&ix) {
+                self.selected.insert(ix);
+            }
+            self.anchor = Some(row);
+        } else {
+            self.selected = HashSet::from([ix]);
+            self.anchor = Some(row);
+            cx.emit(UiEvent::play(&self.tracks[ix]));
+        }
+        cx.notify();
+    }
+
+    /// Selected tracks, in library order.
+    fn selection(&self) -> Vec<Arc<Track>> {
+        let mut selected = self.selected.iter().copied().collect::<Vec<_>>();
+        selected.sort_unstable();
+        selected
+            .into_iter()
+            .map(|ix| Arc::clone(&self.tracks[ix]))
+            .collect()
+    }
+
+    /// Changes the layout shared by all track lists and saves it.
+    fn update_layout(&mut self, cx: &mut ViewContext<Self>, f: impl FnOnce(&mut TrackLayout)) {
+        Settings::update(cx, |settings| f(&mut settings.track_layout));
+        cx.notify();
+    }
+
+    /// Brings the table order up to date with the sort in `layout`.
+    fn sort(&mut self, layout: &TrackLayout, cx: &mut ViewContext<Self>) {
+        if let Some((sort, _)) = &self.sorted {
+            if *sort == layout.sort {
+                return;
+            }
+        }
+
+        let database = cx.try_global::<DatabaseModel>().map(|db| db.read(cx));
+        let order = layout
+            .order(&self.tracks, database)
+            .unwrap_or_else(|| (0..self.tracks.len()).collect());
+        self.sorted = Some((layout.sort.clone(), order));
     }
+
+    /// Index into `tracks` of what is shown at `row`; the list is never sorted.
+    fn track_index(&self, layout: &TrackLayout, row: usize) -> usize {
+        match &self.sorted {
+            Some((_, order)) if layout.table => order[row],
+            _ => row,
+        }
+    }
+
+    fn render_list_rows(
+        &mut self,
+        rows: Range<usize>,
+        cx: &mut ViewContext<Self>,
+    ) -> Vec<AnyElement> {
+        let theme = cx.global::<Theme>();
+        let (highlight, selection) = (theme.surface0, theme.surface2);
+
+        rows.map(|ix| {
+            let track = Arc::clone(&self.tracks[ix]);
+            let current = track.file.is_some() && track.file == self.current;
+
+            div()
+                .id(ElementId::Integer(ix))
+                .flex()
+                .items_center()
+                .h_16()
+                .when(current, |e| e.bg(highlight))
+                .when(self.selected.contains(&ix), |e| e.bg(selection))
+                .child(div().flex_grow().child(track.render_row(cx)))
+                .child(rating_buttons(&track, cx))
+                .child(add_button(track, cx))
+                .on_click(
+                    cx.listener(move |this, event: &ClickEvent, cx| this.click(ix, event, cx)),
+                )
+                .into_any_element()
+        })
+        .collect()
+    }
+
+    /// Visible columns as their index in the layout, column and width.
+    fn columns(&self, layout: &TrackLayout) -> Vec<(usize, Column, f32)> {
+        layout
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.visible)
+            .map(|(index, state)| {
+                let width = match &self.resizing {
+                    Some(resize) if resize.index == index => resize.width,
+                    _ => state.width,
+                };
+                (index, state.column, width)
+            })
+            .collect()
+    }
+
+    fn render_table_rows(
+        &mut self,
+        rows: Range<usize>,
+        cx: &mut ViewContext<Self>,
+    ) -> Vec<AnyElement> {
+        let layout = cx.global::<Settings>().track_layout.clone();
+        let theme = cx.global::<Theme>();
+        let (text, hover, highlight, selection) =
+            (theme.text, theme.surface0, theme.surface1, theme.surface2);
+        let columns = self.columns(&layout);
+
+        let database = cx.try_global::<DatabaseModel>().map(|db| db.read(cx));
+        let rows = rows
+            .map(|row| {
+                let ix = self.track_index(&layout, row);
+                let track = Arc::clone(&self.tracks[ix]);
+                let cells = columns
+                    .iter()
+                    .map(|(_, column, width)| (*width, *column, column.cell(&track, database)))
+                    .collect::<Vec<_>>();
+                let rating = database.map_or((0, false), |database| {
+                    (database.rating(&track), database.is_loved(&track))
+                });
+                (row, self.selected.contains(&ix), track, cells, rating)
+            })
+            .collect::<Vec<_>>();
+
+        rows.into_iter()
+            .map(|(row, selected, track, cells, (rating, loved))| {
+                let current = track.file.is_some() && track.file == self.current;
+
+                div()
+                    .id(ElementId::Integer(row))
+                    .flex()
+                    .items_center()
+                    .h_6()
+                    .text_color(text)
+                    .when(current, |e| e.bg(highlight))
+                    .when(selected, |e| e.bg(selection))
+                    .hover(|style| style.bg(hover))
+                    .children(cells.into_iter().map(|(width, column, text)| {
+                        let cell = div()
+                            .flex_none()
+                            .w(px(width))
+                            .px_1()
+                            .overflow_hidden()
+                            .whitespace_nowrap();
+                        // ratings are set right in their cells
+                        match column {
+                            Column::Rating => cell.child(rating_stars(&track, rating, cx)),
+                            Column::Loved => cell.child(love_button(&track, loved, cx)),
+                            _ => cell.child(text),
+                        }
+                    }))
+                    .child(add_button(track, cx))
+                    .on_click(
+                        cx.listener(move |this, event: &ClickEvent, cx| this.click(row, event, cx)),
+                    )
+                    .into_any_element()
+            })
+            .collect()
+    }
+
+    fn render_header(&mut self, layout: &TrackLayout, cx: &mut ViewContext<Self>) -> Div {
+        let theme = cx.global::<Theme>();
+        let (heading, hover) = (theme.subtext0, theme.surface0);
+        let columns = self.columns(layout);
+
+        div()
+            .flex()
+            .text_sm()
+            .text_color(heading)
+            .children(columns.into_iter().map(|(index, column, width)| {
+                let key = layout.sort.iter().position(|key| key.column == column);
+                let mut label = column.label().to_string();
+                if let Some(position) = key {
+                    label.push_str(if layout.sort[position].ascending {
+                        " ▲"
+                    } else {
+                        " ▼"
+                    });
+                    if layout.sort.len() > 1 {
+                        label.push_str(&(position + 1).to_string());
+                    }
+                }
+
+                div()
+                    .id(ElementId::Name(format!("column-{}", column.label()).into()))
+                    .flex()
+                    .flex_none()
+                    .items_center()
+                    .w(px(width))
+                    .child(
+                        div()
+                            .flex_grow()
+                            .px_1()
+                            .overflow_hidden()
+                            .whitespace_nowrap()
+                            .child(label),
+                    )
+                    .child(
+                        div()
+                            .id(ElementId::Name(format!("resize-{}", column.label()).into()))
+                            .w_1()
+                            .h_full()
+                            .cursor_col_resize()
+                            .hover(|style| style.bg(hover))
+                            .on_mouse_down(
+                                MouseButton::Left,
+                                cx.listener(move |this, event: &MouseDownEvent, cx| {
+                                    cx.stop_propagation();
+                                    this.resizing = Some(Resize {
+                                        index,
+                                        start_x: event.position.x,
+                                        start_width: width,
+                                        width,
+                                    });
+                                }),
+                            ),
+                    )
+                    // click to sort, shift-click to sort by more columns
+                    .on_click(cx.listener(move |this, event: &ClickEvent, cx| {
+                        let add = event.down.modifiers.shift;
+                        this.update_layout(cx, |layout| layout.sort_by(column, add));
+                    }))
+                    .on_drag(
+                        DraggedColumn {
+                            index,
+                            label: column.label(),
+                        },
+                        |dragged, cx| cx.new_view(|_| dragged.clone()),
+                    )
+                    .on_drop(cx.listener(move |this, dragged: &DraggedColumn, cx| {
+                        let from = dragged.index;
+                        this.update_layout(cx, |layout| layout.move_column(from, index));
+                    }))
+            }))
+    }
+
+    fn end_resize(&mut self, _: &MouseUpEvent, cx: &mut ViewContext<Self>) {
+        if let Some(resize) = self.resizing.take() {
+            self.update_layout(cx, |layout| layout.resize(resize.index, resize.width));
+        }
+    }
+
+    fn render_column_chooser(&self, layout: &TrackLayout, cx: &mut ViewContext<Self>) -> Div {
+        let theme = cx.global::<Theme>();
+        let (shown, hidden) = (theme.text, theme.overlay0);
+
+        div()
+            .flex()
+            .flex_wrap()
+            .gap_3()
+            .px_3()
+            .text_sm()
+            .children(layout.columns.iter().map(|state| {
+                let column = state.column;
+                div()
+                    .id(ElementId::Name(format!("show-{}", column.label()).into()))
+                    .text_color(if state.visible { shown } else { hidden })
+                    .child(column.label())
+                    .on_click(cx.listener(move |this, _, cx| {
+                        this.update_layout(cx, |layout| layout.toggle(column))
+                    }))
+            }))
+    }
+}
+
+fn add_button(track: Arc<Track>, cx: &mut ViewContext<Tracks>) -> impl IntoElement {
+    div()
+        .id("add-to-playlist")
+        .px_3()
+        .child("+")
+        .on_click(cx.listener(move |_, _, cx| {
+            cx.stop_propagation();
+            cx.emit(UiEvent::add_to_playlist(&track));
+        }))
+}
+
+/// The rating and whether `track` is loved, as the library database has them.
+pub fn rating(track: &Track, cx: &AppContext) -> (u8, bool) {
+    match cx.try_global::<DatabaseModel>() {
+        Some(database) => {
+            let database = database.read(cx);
+            (database.rating(track), database.is_loved(track))
+        }
+        None => (track.rating.unwrap_or(0), track.loved),
+    }
+}
+
+/// Five stars to rate `track` with, clicking its current rating clears it.
+pub fn rating_stars<V: EventEmitter<Arc<UiEvent>> + 'static>(
+    track: &Arc<Track>,
+    rating: u8,
+    cx: &mut ViewContext<V>,
+) -> Div {
+    let theme = cx.global::<Theme>();
+    let (on, off) = (theme.yellow, theme.overlay0);
+
+    div().flex().children((1..=5).map(|star| {
+        let track = Arc::clone(track);
+        div()
+            .id(ElementId::Name(format!("star-{}", star).into()))
+            .text_color(if star <= rating { on } else { off })
+            .child(if star <= rating { "★" } else { "☆" })
+            .on_click(cx.listener(move |_, _, cx| {
+                cx.stop_propagation();
+                let rating = if star == rating { 0 } else { star };
+                cx.emit(Arc::new(UiEvent::RatingChanged(Arc::clone(&track), rating)));
+            }))
+    }))
+}
+
+pub fn love_button<V: EventEmitter<Arc<UiEvent>> + 'static>(
+    track: &Arc<Track>,
+    loved: bool,
+    cx: &mut ViewContext<V>,
+) -> impl IntoElement {
+    let theme = cx.global::<Theme>();
+    let (on, off) = (theme.red, theme.overlay0);
+    let track = Arc::clone(track);
+
+    div()
+        .id("love")
+        .text_color(if loved { on } else { off })
+        .child(if loved { "♥" } else { "♡" })
+        .on_click(cx.listener(move |_, _, cx| {
+            cx.stop_propagation();
+            cx.emit(Arc::new(UiEvent::LovedToggled(Arc::clone(&track))));
+        }))
+}
+
+/// Stars and heart of `track`, as the database has them.
+pub fn rating_buttons<V: EventEmitter<Arc<UiEvent>> + 'static>(
+    track: &Arc<Track>,
+    cx: &mut ViewContext<V>,
+) -> impl IntoElement {
+    let (stars, loved) = rating(track, cx);
+    div()
+        .flex()
+        .gap_2()
+        .items_center()
+        .child(rating_stars(track, stars, cx))
+        .child(love_button(track, loved, cx))
 }
 
 impl Render for Tracks {
     fn render(&mut self, cx: &mut gpui::ViewContext<Self>) -> impl gpui::IntoElement {
+        let layout = cx.global::<Settings>().track_layout.clone();
+        self.sort(&layout, cx);
+
+        let toolbar = div()
+            .flex()
+            .gap_3()
+            .px_3()
+            .text_sm()
+            .child(
+                div()
+                    .id("toggle-table")
+                    .child(if layout.table { "List" } else { "Table" })
+                    .on_click(cx.listener(|this, _, cx| {
+                        this.update_layout(cx, |layout| layout.table = !layout.table)
+                    })),
+            )
+            .when(layout.table, |e| {
+                e.child(
+                    div()
+                        .id("choose-columns")
+                        .child("Columns")
+                        .on_click(cx.listener(|this, _, cx| {
+                            this.choosing_columns = !this.choosing_columns;
+                            cx.notify();
+                        })),
+                )
+            })
+            .when(!self.selected.is_empty(), |e| {
+                e.child(
+                    div()
+                        .id("edit-tags")
+                        .child(format!("Edit tags ({})", self.selected.len()))
+                        .on_click(cx.listener(|this, _, cx| {
+                            cx.emit(Arc::new(UiEvent::EditTagsClicked(this.selection())))
+                        })),
+                )
+                .child(
+                    div()
+                        .id("rename-files")
+                        .child("Paths & names")
+                        .on_click(cx.listener(|this, _, cx| {
+                            cx.emit(Arc::new(UiEvent::RenameClicked(this.selection())))
+                        })),
+                )
+            })
+            .when(self.current.is_some(), |e| {
+                e.child(
+                    div()
+                        .id("scroll-to-current")
+                        .child("Show playing")
+                        .on_click(cx.listener(|this, _, cx| this.scroll_to_current(cx))),
+                )
+            });
+
+        // only the rows in view are laid out
+        let view = cx.view().clone();
+        let rows = if layout.table {
+            uniform_list(
+                view,
+                "track-rows",
+                self.tracks.len(),
+                Self::render_table_rows,
+            )
+        } else {
+            uniform_list(
+                view,
+                "track-rows",
+                self.tracks.len(),
+                Self::render_list_rows,
+            )
+        };
+
         div()
             .flex()
             .flex_col()
-            .gap(px(1.))
-            .children(self.tracks.clone())
+            .size_full()
+            .child(toolbar)
+            .when(layout.table && self.choosing_columns, |e| {
+                e.child(self.render_column_chooser(&layout, cx))
+            })
+            .when(layout.table, |e| e.child(self.render_header(&layout, cx)))
+            .on_mouse_move(cx.listener(|this, event: &MouseMoveEvent, cx| {
+                if let Some(resize) = &mut this.resizing {
+                    resize.width = resize.start_width + (event.position.x - resize.start_x).0;
+                    cx.notify();
+                }
+            }))
+            .on_mouse_up(MouseButton::Left, cx.listener(Self::end_resize))
+            .on_mouse_up_out(MouseButton::Left, cx.listener(Self::end_resize))
+            .child(rows.flex_grow().track_scroll(self.scroll.clone()))
     }
 }
@@ -0,0 +1,1138 @@
+//! The MPRIS2 interfaces on the session bus, so media keys, desktop widgets
+//! and `playerctl` can control playback.
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use gpui::{AnyWindowHandle, AppContext, Context, Global, Model, ModelContext, WindowContext};
+use log::{error, warn};
+use url::Url;
+use zbus::{
+    blocking::connection,
+    interface,
+    names::BusName,
+    zvariant::{ObjectPath, Value},
+};
+
+use crate::{
+    metadata::{
+        cover, database::DatabaseModel, library::LibraryModel, playlist::PlaylistsModel,
+        track::Track,
+    },
+    playback::{Playback, Repeat, SPEED_RANGE},
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.ggmusic";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+const TRACK_LIST: &str = "org.mpris.MediaPlayer2.TrackList";
+const PLAYLISTS: &str = "org.mpris.MediaPlayer2.Playlists";
+// queue entries are known by an id they keep for as long as they are queued
+const TRACK_PATH: &str = "/org/ggmusic/track";
+const PLAYLIST_PATH: &str = "/org/ggmusic/playlist";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+const MIME_TYPES: [&str; 6] = [
+    "audio/mpeg",
+    "audio/flac",
+    "audio/ogg",
+    "audio/wav",
+    "audio/mp4",
+    "audio/aac",
+];
+// a position further than this from where playback should be was a seek
+const SEEK_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// What the bus asks ggmusic to do.
+enum Command {
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    /// Microseconds from the current position.
+    Seek(i64),
+    /// Id of the queue entry and where to go in it.
+    SetPosition(u64, Duration),
+    SetVolume(f64),
+    SetRate(f64),
+    SetShuffle(bool),
+    SetLoop(Repeat),
+    OpenUri(String),
+    /// Id of the queue entry.
+    GoTo(u64),
+    ActivatePlaylist(u64),
+    Raise,
+    Quit,
+}
+
+/// What ggmusic tells the bus.
+enum Signal {
+    /// New values of properties of an interface.
+    Changed(&'static str, HashMap<String, Value<'static>>),
+    Seeked(Duration),
+    TrackListReplaced(Vec<ObjectPath<'static>>, ObjectPath<'static>),
+}
+
+/// Playback as last seen, for the bus to answer from.
+#[derive(Clone, Default)]
+struct State {
+    playing: bool,
+    stopped: bool,
+    /// Position of the current track in `queue`.
+    current: Option<usize>,
+    /// In play order.
+    queue: Vec<Arc<Track>>,
+    /// Of the entries of `queue`, as the bus knows them.
+    ids: Vec<u64>,
+    /// The id the next newly queued entry gets.
+    next_id: u64,
+    /// A file with the cover of the current track.
+    art: Option<String>,
+    position: Duration,
+    /// When `position` was read.
+    read_at: Option<Instant>,
+    volume: f64,
+    rate: f64,
+    shuffle: bool,
+    repeat: Repeat,
+    playlists: Vec<(u64, String)>,
+}
+
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+fn track_id(id: u64) -> ObjectPath<'static> {
+    ObjectPath::from_string_unchecked(format!("{}/{}", TRACK_PATH, id))
+}
+
+fn playlist_id(id: u64) -> ObjectPath<'static> {
+    ObjectPath::from_string_unchecked(format!("{}/{}", PLAYLIST_PATH, id))
+}
+
+/// The number at the end of `path` under `prefix`.
+fn id_in(path: &ObjectPath, prefix: &str) -> Option<u64> {
+    path.as_str()
+        .strip_prefix(prefix)?
+        .strip_prefix('/')?
+        .parse()
+        .ok()
+}
+
+fn file_url(path: &str) -> Option<String> {
+    Url::from_file_path(path).ok().map(String::from)
+}
+
+fn metadata(
+    track: &Track,
+    id: ObjectPath<'static>,
+    art: Option<&str>,
+) -> HashMap<String, Value<'static>> {
+    let mut metadata = HashMap::<String, Value<'static>>::new();
+    let mut insert = |key: &str, value: Value<'static>| {
+        metadata.insert(key.to_string(), value);
+    };
+    insert("mpris:trackid", Value::from(id));
+    insert("mpris:length", Value::from(micros(track.duration())));
+    insert("xesam:title", Value::from(track.title.clone()));
+    insert("xesam:artist", Value::from(vec![track.artist.clone()]));
+    insert("xesam:album", Value::from(track.album.clone()));
+    if let Some(album_artist) = &track.album_artist {
+        insert("xesam:albumArtist", Value::from(vec![album_artist.clone()]));
+    }
+    if let Some(genre) = &track.genre {
+        insert("xesam:genre", Value::from(vec![genre.clone()]));
+    }
+    if let Some(number) = track.track_number {
+        insert("xesam:trackNumber", Value::from(number as i32));
+    }
+    if let Some(url) = track.path().and_then(file_url) {
+        insert("xesam:url", Value::from(url));
+    }
+    if let Some(art) = art.and_then(file_url) {
+        insert("mpris:artUrl", Value::from(art));
+    }
+    metadata
+}
+
+impl State {
+    fn current_track(&self) -> Option<&Arc<Track>> {
+        self.current.and_then(|position| self.queue.get(position))
+    }
+
+    /// Where the entry with `id` is in the queue.
+    fn position_of(&self, id: u64) -> Option<usize> {
+        self.ids.iter().position(|&known| known == id)
+    }
+
+    /// Gives the queue entries ids, keeping those of tracks that were
+    /// already queued in `old` so that ids the bus was told about still
+    /// lead to the same track, or to none once it is gone.
+    fn assign_ids(&mut self, old: &State) {
+        let mut old_ids = HashMap::<*const Track, Vec<u64>>::new();
+        // reversed, so that repeats of a track pop their ids in queue order
+        for (track, &id) in old.queue.iter().zip(&old.ids).rev() {
+            old_ids.entry(Arc::as_ptr(track)).or_default().push(id);
+        }
+
+        let mut next_id = old.next_id;
+        self.ids = self
+            .queue
+            .iter()
+            .map(|track| {
+                old_ids
+                    .get_mut(&Arc::as_ptr(track))
+                    .and_then(Vec::pop)
+                    .unwrap_or_else(|| {
+                        next_id += 1;
+                        next_id - 1
+                    })
+            })
+            .collect();
+        self.next_id = next_id;
+    }
+
+    fn track_ids(&self) -> Vec<ObjectPath<'static>> {
+        self.ids.iter().copied().map(track_id).collect()
+    }
+
+    fn playback_status(&self) -> &'static str {
+        match (self.current, self.playing) {
+            (None, _) => "Stopped",
+            _ if self.stopped => "Stopped",
+            (Some(_), true) => "Playing",
+            (Some(_), false) => "Paused",
+        }
+    }
+
+    /// Where playback has got to by now, going on from `position` while
+    /// playing.
+    fn position_now(&self) -> Duration {
+        let position = match self.read_at {
+            Some(read_at) if self.playing && !self.stopped => {
+                self.position + read_at.elapsed().mul_f64(self.rate)
+            }
+            _ => self.position,
+        };
+        match self.current_track() {
+            Some(track) => position.min(track.duration()),
+            None => position,
+        }
+    }
+
+    fn loop_status(&self) -> &'static str {
+        match self.repeat {
+            Repeat::Off => "None",
+            Repeat::One => "Track",
+            Repeat::All => "Playlist",
+        }
+    }
+
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        match (self.current, self.current_track()) {
+            (Some(position), Some(track)) => {
+                metadata(track, track_id(self.ids[position]), self.art.as_deref())
+            }
+            _ => HashMap::from([(
+                "mpris:trackid".to_string(),
+                Value::from(ObjectPath::from_static_str_unchecked(NO_TRACK)),
+            )]),
+        }
+    }
+
+    fn can_go_next(&self) -> bool {
+        self.current
+            .is_some_and(|position| position + 1 < self.queue.len())
+            || (self.repeat != Repeat::Off && !self.queue.is_empty())
+    }
+
+    fn same_queue(&self, other: &State) -> bool {
+        self.queue.len() == other.queue.len()
+            && self
+                .queue
+                .iter()
+                .zip(&other.queue)
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+
+    fn same_track(&self, other: &State) -> bool {
+        match (self.current_track(), other.current_track()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b) && self.current == other.current,
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether playback jumped away from where it would have got to since
+    /// `old`, rather than just playing on.
+    fn seeked(&self, old: &State) -> bool {
+        let drift = self.position.abs_diff(old.position_now());
+        self.same_track(old) && !self.stopped && !old.stopped && drift > SEEK_TOLERANCE
+    }
+
+    /// The properties of the player interface that differ from `old`.
+    fn changes(&self, old: &State) -> HashMap<String, Value<'static>> {
+        let mut changes = HashMap::<String, Value<'static>>::new();
+        let mut changed = |name: &str, value: Value<'static>| {
+            changes.insert(name.to_string(), value);
+        };
+
+        if self.playback_status() != old.playback_status() {
+            changed("PlaybackStatus", Value::from(self.playback_status()));
+        }
+        if self.repeat != old.repeat {
+            changed("LoopStatus", Value::from(self.loop_status()));
+        }
+        if self.shuffle != old.shuffle {
+            changed("Shuffle", Value::from(self.shuffle));
+        }
+        if self.volume != old.volume {
+            changed("Volume", Value::from(self.volume));
+        }
+        if self.rate != old.rate {
+            changed("Rate", Value::from(self.rate));
+        }
+        if !self.same_track(old) || self.art != old.art {
+            changed("Metadata", Value::from(self.metadata()));
+        }
+        if !self.same_track(old) || !self.same_queue(old) || self.repeat != old.repeat {
+            let has_track = self.current.is_some();
+            changed("CanGoNext", Value::from(self.can_go_next()));
+            changed("CanGoPrevious", Value::from(has_track));
+            changed("CanPlay", Value::from(!self.queue.is_empty()));
+            changed("CanPause", Value::from(has_track));
+            changed("CanSeek", Value::from(has_track));
+        }
+        changes
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    commands: UnboundedSender<Command>,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap()
+    }
+
+    fn send(&self, command: Command) {
+        // only fails once the app is closing
+        let _ = self.commands.unbounded_send(command);
+    }
+}
+
+struct MediaPlayer(Arc<Shared>);
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl MediaPlayer {
+    fn raise(&self) {
+        self.0.send(Command::Raise);
+    }
+
+    fn quit(&self) {
+        self.0.send(Command::Quit);
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_track_list(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn identity(&self) -> String {
+        "ggmusic".to_string()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn desktop_entry(&self) -> String {
+        "ggmusic".to_string()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_mime_types(&self) -> Vec<String> {
+        MIME_TYPES.map(String::from).to_vec()
+    }
+}
+
+struct Player(Arc<Shared>);
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        self.0.send(Command::Next);
+    }
+
+    fn previous(&self) {
+        self.0.send(Command::Previous);
+    }
+
+    fn pause(&self) {
+        self.0.send(Command::Pause);
+    }
+
+    fn play_pause(&self) {
+        self.0.send(Command::PlayPause);
+    }
+
+    fn stop(&self) {
+        self.0.send(Command::Stop);
+    }
+
+    fn play(&self) {
+        self.0.send(Command::Play);
+    }
+
+    fn seek(&self, offset: i64) {
+        self.0.send(Command::Seek(offset));
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        if let (Some(id), Ok(position)) = (id_in(&track_id, TRACK_PATH), u64::try_from(position)) {
+            self.0
+                .send(Command::SetPosition(id, Duration::from_micros(position)));
+        }
+    }
+
+    fn open_uri(&self, uri: String) {
+        self.0.send(Command::OpenUri(uri));
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.0.state().playback_status().to_string()
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> String {
+        self.0.state().loop_status().to_string()
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, status: String) {
+        let repeat = match status.as_str() {
+            "None" => Repeat::Off,
+            "Track" => Repeat::One,
+            "Playlist" => Repeat::All,
+            _ => return,
+        };
+        self.0.send(Command::SetLoop(repeat));
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        self.0.state().rate
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, rate: f64) {
+        // a rate of 0 is not to be set, pausing is what it would mean
+        if rate > 0.0 {
+            self.0.send(Command::SetRate(rate));
+        }
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.0.state().shuffle
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, shuffle: bool) {
+        self.0.send(Command::SetShuffle(shuffle));
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        self.0.state().metadata()
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.0.state().volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) {
+        self.0.send(Command::SetVolume(volume));
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        micros(self.0.state().position_now())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn minimum_rate(&self) -> f64 {
+        f64::from(*SPEED_RANGE.start())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn maximum_rate(&self) -> f64 {
+        f64::from(*SPEED_RANGE.end())
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.0.state().can_go_next()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.0.state().current.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        !self.0.state().queue.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.0.state().current.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.0.state().current.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// The queue, which can be looked at and jumped around in but not edited.
+struct TrackList(Arc<Shared>);
+
+#[interface(name = "org.mpris.MediaPlayer2.TrackList")]
+impl TrackList {
+    fn get_tracks_metadata(
+        &self,
+        track_ids: Vec<ObjectPath<'_>>,
+    ) -> Vec<HashMap<String, Value<'static>>> {
+        let state = self.0.state();
+        track_ids
+            .iter()
+            .filter_map(|path| {
+                let id = id_in(path, TRACK_PATH)?;
+                let position = state.position_of(id)?;
+                let art = (state.current == Some(position))
+                    .then_some(state.art.as_deref())
+                    .flatten();
+                Some(metadata(&state.queue[position], track_id(id), art))
+            })
+            .collect()
+    }
+
+    /// Does nothing, as `CanEditTracks` is false.
+    fn add_track(&self, _uri: String, _after_track: ObjectPath<'_>, _set_as_current: bool) {}
+
+    /// Does nothing, as `CanEditTracks` is false.
+    fn remove_track(&self, _track_id: ObjectPath<'_>) {}
+
+    fn go_to(&self, track_id: ObjectPath<'_>) {
+        if let Some(id) = id_in(&track_id, TRACK_PATH) {
+            self.0.send(Command::GoTo(id));
+        }
+    }
+
+    #[zbus(property(emits_changed_signal = "invalidates"))]
+    fn tracks(&self) -> Vec<ObjectPath<'static>> {
+        self.0.state().track_ids()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_edit_tracks(&self) -> bool {
+        false
+    }
+}
+
+/// Playlists and smart playlists, which share their ids.
+struct Playlists(Arc<Shared>);
+
+#[interface(name = "org.mpris.MediaPlayer2.Playlists")]
+impl Playlists {
+    fn activate_playlist(&self, playlist_id: ObjectPath<'_>) {
+        if let Some(id) = id_in(&playlist_id, PLAYLIST_PATH) {
+            self.0.send(Command::ActivatePlaylist(id));
+        }
+    }
+
+    fn get_playlists(
+        &self,
+        index: u32,
+        max_count: u32,
+        order: String,
+        reverse_order: bool,
+    ) -> Vec<(ObjectPath<'static>, String, String)> {
+        let mut playlists = self.0.state().playlists.clone();
+        if order == "Alphabetical" {
+            playlists.sort_by_key(|(_, name)| name.to_lowercase());
+        }
+        if reverse_order {
+            playlists.reverse();
+        }
+        playlists
+            .into_iter()
+            .skip(index as usize)
+            .take(max_count as usize)
+            .map(|(id, name)| (playlist_id(id), name, String::new()))
+            .collect()
+    }
+
+    #[zbus(property)]
+    fn playlist_count(&self) -> u32 {
+        self.0.state().playlists.len() as u32
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn orderings(&self) -> Vec<String> {
+        vec!["Alphabetical".to_string(), "UserDefined".to_string()]
+    }
+
+    #[zbus(property)]
+    fn active_playlist(&self) -> (bool, (ObjectPath<'static>, String, String)) {
+        let none = ObjectPath::from_static_str_unchecked("/");
+        (false, (none, String::new(), String::new()))
+    }
+}
+
+/// Registers on the session bus and sends `signals` until the app closes.
+fn serve(shared: Arc<Shared>, signals: mpsc::Receiver<Signal>) -> Result<()> {
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, MediaPlayer(Arc::clone(&shared)))?
+        .serve_at(OBJECT_PATH, Player(Arc::clone(&shared)))?
+        .serve_at(OBJECT_PATH, TrackList(Arc::clone(&shared)))?
+        .serve_at(OBJECT_PATH, Playlists(shared))?
+        .build()?;
+
+    for signal in signals {
+        let sent = match signal {
+            Signal::Changed(interface, changes) => connection.emit_signal(
+                None::<BusName>,
+                OBJECT_PATH,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                &(interface, changes, Vec::<String>::new()),
+            ),
+            Signal::Seeked(position) => connection.emit_signal(
+                None::<BusName>,
+                OBJECT_PATH,
+                PLAYER,
+                "Seeked",
+                &micros(position),
+            ),
+            Signal::TrackListReplaced(tracks, current) => connection.emit_signal(
+                None::<BusName>,
+                OBJECT_PATH,
+                TRACK_LIST,
+                "TrackListReplaced",
+                &(tracks, current),
+            ),
+        };
+        if let Err(err) = sent {
+            warn!("Failed to signal on the session bus: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the bus up to date with playback and carries out what it asks.
+pub struct Mpris {
+    playback: Model<Playback>,
+    window: AnyWindowHandle,
+    shared: Arc<Shared>,
+    signals: mpsc::Sender<Signal>,
+    // what the bus was last told
+    sent: State,
+}
+
+struct MprisModel(Model<Mpris>);
+
+impl Global for MprisModel {}
+
+impl Mpris {
+    pub fn init(cx: &mut WindowContext, playback: &Model<Playback>) {
+        let (commands, mut received) = futures::channel::mpsc::unbounded();
+        let (signals, to_send) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            commands,
+        });
+
+        let serving = Arc::clone(&shared);
+        thread::spawn(move || {
+            if let Err(err) = serve(serving, to_send) {
+                error!("Failed to register on the session bus: {}", err);
+            }
+        });
+
+        let window = cx.window_handle();
+        let playlists = cx.global::<PlaylistsModel>().clone();
+        let model = cx.new_model(|cx| {
+            cx.observe(playback, |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+            cx.observe(playlists.model(), |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+
+            // wakes up only when the bus asks for something
+            cx.spawn(|this, mut cx| async move {
+                while let Some(command) = received.next().await {
+                    if this
+                        .update(&mut cx, |this, cx| this.run(command, cx))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            })
+            .detach();
+
+            let mut this = Self {
+                playback: playback.clone(),
+                window,
+                shared,
+                signals,
+                sent: State::default(),
+            };
+            this.refresh(cx);
+            this
+        });
+        cx.set_global(MprisModel(model));
+    }
+
+    fn run(&mut self, command: Command, cx: &mut ModelContext<Self>) {
+        match command {
+            Command::Raise => {
+                let _ = self.window.update(cx, |_, cx| cx.activate_window());
+            }
+            Command::Quit => cx.quit(),
+            Command::OpenUri(uri) => {
+                let track = Url::parse(&uri)
+                    .ok()
+                    .and_then(|url| url.to_file_path().ok())
+                    .and_then(|path| {
+                        let path = path.to_str()?.to_string();
+                        cx.global::<LibraryModel>().find_track(&path, cx)
+                    });
+                match track {
+                    Some(track) => self.playback.update(cx, |playback, cx| {
+                        playback.play(track, cx);
+                        cx.notify();
+                    }),
+                    None => warn!("Cannot open {} from the session bus", uri),
+                }
+            }
+            Command::ActivatePlaylist(id) => {
+                let tracks = playlist_tracks(id, cx);
+                self.playback
+                    .update(cx, |playback, cx| playback.play_tracks(tracks, cx));
+            }
+            command => self.playback.update(cx, |playback, cx| match command {
+                Command::Play => playback.resume(cx),
+                Command::Pause => playback.pause(cx),
+                Command::PlayPause if playback.is_playing() => playback.pause(cx),
+                Command::PlayPause => playback.resume(cx),
+                Command::Stop => playback.stop(cx),
+                Command::Next => playback.next(cx),
+                Command::Previous => playback.previous(cx),
+                Command::Seek(offset) => {
+                    let by = Duration::from_micros(offset.unsigned_abs());
+                    let position = if offset < 0 {
+                        playback.position().saturating_sub(by)
+                    } else {
+                        playback.position() + by
+                    };
+                    // seeking past the end moves on, as the specification asks
+                    match playback.current() {
+                        Some(track) if position >= track.duration() => playback.next(cx),
+                        Some(_) => playback.seek(position, cx),
+                        None => {}
+                    }
+                }
+                Command::SetPosition(id, position) => {
+                    let current = playback.current();
+                    let track = self.sent.position_of(id);
+                    if track.is_some()
+                        && playback.current_position() == track
+                        && current.is_some_and(|current| position <= current.duration())
+                    {
+                        playback.seek(position, cx);
+                    }
+                }
+                Command::SetVolume(volume) => {
+                    playback.set_volume(volume.clamp(0.0, 1.0) as f32, cx)
+                }
+                Command::SetRate(rate) => playback.set_speed(rate as f32, cx),
+                Command::SetShuffle(shuffle) => playback.set_shuffle(shuffle, cx),
+                Command::SetLoop(repeat) => playback.set_repeat(repeat, cx),
+                Command::GoTo(id) => {
+                    if let Some(position) = self.sent.position_of(id) {
+                        playback.go_to(position, cx);
+                    }
+                }
+                _ => {}
+            }),
+        }
+    }
+
+    /// Reads playback and tells the bus what changed since the last time.
+    fn refresh(&mut self, cx: &mut ModelContext<Self>) {
+        let playback = self.playback.read(cx);
+        let playlists = cx.global::<PlaylistsModel>().read(cx);
+        let mut state = State {
+            playing: playback.is_playing(),
+            stopped: playback.is_stopped(),
+            current: playback.current_position(),
+            queue: playback.queue(),
+            ids: Vec::new(),
+            next_id: 0,
+            art: None,
+            position: playback.position(),
+            read_at: Some(Instant::now()),
+            volume: f64::from(playback.volume()),
+            rate: f64::from(playback.speed()),
+            shuffle: playback.shuffle(),
+            repeat: playback.repeat(),
+            playlists: playlists
+                .playlists
+                .iter()
+                .map(|playlist| (playlist.id, playlist.name.clone()))
+                .chain(
+                    playlists
+                        .smart
+                        .iter()
+                        .map(|smart| (smart.id, smart.name.clone())),
+                )
+                .collect(),
+        };
+        if state.same_queue(&self.sent) {
+            // keep the old tracks so they still compare equal
+            state.queue = self.sent.queue.clone();
+        }
+        state.assign_ids(&self.sent);
+
+        let same_track = state.same_track(&self.sent);
+        if same_track {
+            state.art = self.sent.art.clone();
+        } else if let Some(cover) = state.current_track().and_then(|track| track.cover.clone()) {
+            self.find_art(cover, state.current_track().cloned(), cx);
+        }
+
+        if state.seeked(&self.sent) {
+            self.signal(Signal::Seeked(state.position));
+        }
+
+        let changes = state.changes(&self.sent);
+        if !changes.is_empty() {
+            self.signal(Signal::Changed(PLAYER, changes));
+        }
+        if !state.same_queue(&self.sent) {
+            let current = state.current.map_or(
+                ObjectPath::from_static_str_unchecked(NO_TRACK),
+                |position| track_id(state.ids[position]),
+            );
+            self.signal(Signal::TrackListReplaced(state.track_ids(), current));
+        }
+        if state.playlists.len() != self.sent.playlists.len() {
+            let count = Value::from(state.playlists.len() as u32);
+            self.signal(Signal::Changed(
+                PLAYLISTS,
+                HashMap::from([("PlaylistCount".to_string(), count)]),
+            ));
+        }
+
+        *self.shared.state() = state.clone();
+        self.sent = state;
+    }
+
+    /// Works out a file with `cover` in the background, then tells the bus
+    /// if `track` is still playing.
+    fn find_art(
+        &self,
+        cover: cover::Cover,
+        track: Option<Arc<Track>>,
+        cx: &mut ModelContext<Self>,
+    ) {
+        cx.spawn(|this, mut cx| async move {
+            let art = cx
+                .background_executor()
+                .spawn(async move { cover::art_file(&cover) })
+                .await;
+            let art = match art {
+                Ok(art) => art,
+                Err(err) => {
+                    warn!("Failed to save cover art for the session bus: {}", err);
+                    return;
+                }
+            };
+
+            let _ = this.update(&mut cx, |this, _| {
+                let current = this.sent.current_track().cloned();
+                let still_playing = match (&current, &track) {
+                    (Some(current), Some(track)) => Arc::ptr_eq(current, track),
+                    _ => false,
+                };
+                if !still_playing {
+                    return;
+                }
+                this.sent.art = art.to_str().map(String::from);
+                let metadata = Value::from(this.sent.metadata());
+                this.shared.state().art = this.sent.art.clone();
+                this.signal(Signal::Changed(
+                    PLAYER,
+                    HashMap::from([("Metadata".to_string(), metadata)]),
+                ));
+            });
+        })
+        .detach();
+    }
+
+    fn signal(&self, signal: Signal) {
+        // only fails when the bus could not be reached, which was logged
+        let _ = self.signals.send(signal);
+    }
+}
+
+/// The tracks of a playlist or smart playlist, as they are now.
+fn playlist_tracks(id: u64, cx: &AppContext) -> Vec<Arc<Track>> {
+    let library = cx.global::<LibraryModel>().clone();
+    let playlists = cx.global::<PlaylistsModel>().read(cx);
+    if let Some(playlist) = playlists.get(id) {
+        return library
+            .find_tracks(&playlist.tracks, cx)
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+    let Some(smart) = playlists.get_smart(id) else {
+        return Vec::new();
+    };
+    let database = cx.global::<DatabaseModel>().read(cx);
+    smart.evaluate(library.read(cx), database)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// A silent mono WAV of `seconds`.
+    fn wav(dir: &Path, name: &str, seconds: u32) -> Arc<Track> {
+        let path = dir.join(name);
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for _ in 0..seconds * spec.sample_rate {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        Arc::new(Track::read(&path).unwrap())
+    }
+
+    fn state(queue: Vec<Arc<Track>>, current: Option<usize>) -> State {
+        let mut state = State {
+            queue,
+            current,
+            rate: 1.0,
+            ..State::default()
+        };
+        state.assign_ids(&State::default());
+        state
+    }
+
+    #[test]
+    fn reads_ids_under_their_prefix() {
+        let path = |path| ObjectPath::try_from(path).unwrap();
+        assert_eq!(id_in(&path("/org/ggmusic/track/12"), TRACK_PATH), Some(12));
+        assert_eq!(id_in(&track_id(7), TRACK_PATH), Some(7));
+        assert_eq!(id_in(&playlist_id(3), TRACK_PATH), None);
+        assert_eq!(id_in(&path("/org/ggmusic/track"), TRACK_PATH), None);
+        assert_eq!(id_in(&path("/org/ggmusic/tracks/1"), TRACK_PATH), None);
+        assert_eq!(id_in(&path("/org/ggmusic/track/a"), TRACK_PATH), None);
+        assert_eq!(id_in(&path(NO_TRACK), TRACK_PATH), None);
+    }
+
+    #[test]
+    fn tells_playing_paused_and_stopped_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state(vec![wav(dir.path(), "a.wav", 1)], Some(0));
+
+        assert_eq!(state.playback_status(), "Paused");
+        state.playing = true;
+        assert_eq!(state.playback_status(), "Playing");
+        state.stopped = true;
+        assert_eq!(state.playback_status(), "Stopped");
+        state.stopped = false;
+        state.current = None;
+        assert_eq!(state.playback_status(), "Stopped");
+    }
+
+    #[test]
+    fn moves_the_position_on_only_while_playing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state(vec![wav(dir.path(), "a.wav", 10)], Some(0));
+        state.position = Duration::from_secs(2);
+        state.read_at = Instant::now().checked_sub(Duration::from_secs(3));
+        state.rate = 2.0;
+
+        assert_eq!(state.position_now(), Duration::from_secs(2));
+        state.playing = true;
+        let now = state.position_now();
+        assert!(now >= Duration::from_secs(8) && now < Duration::from_secs(9));
+
+        // never past the end of the track
+        state.rate = 4.0;
+        assert_eq!(state.position_now(), Duration::from_secs(10));
+
+        state.stopped = true;
+        assert_eq!(state.position_now(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn goes_next_within_the_queue_or_when_repeating() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = wav(dir.path(), "a.wav", 1);
+        let b = wav(dir.path(), "b.wav", 1);
+        let mut state = state(vec![a, b], Some(0));
+
+        assert!(state.can_go_next());
+        state.current = Some(1);
+        assert!(!state.can_go_next());
+        state.repeat = Repeat::All;
+        assert!(state.can_go_next());
+        state.repeat = Repeat::One;
+        assert!(state.can_go_next());
+
+        let mut empty = self::state(Vec::new(), None);
+        assert!(!empty.can_go_next());
+        empty.repeat = Repeat::All;
+        assert!(!empty.can_go_next());
+    }
+
+    #[test]
+    fn lists_only_the_properties_that_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = wav(dir.path(), "a.wav", 1);
+        let b = wav(dir.path(), "b.wav", 1);
+        let old = state(vec![a, b], Some(0));
+        let names = |changes: HashMap<String, Value>| {
+            let mut names = changes.into_keys().collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert!(old.clone().changes(&old).is_empty());
+
+        let mut new = old.clone();
+        new.playing = true;
+        new.volume = 0.5;
+        assert_eq!(names(new.changes(&old)), ["PlaybackStatus", "Volume"]);
+
+        let mut new = old.clone();
+        new.current = Some(1);
+        assert_eq!(
+            names(new.changes(&old)),
+            [
+                "CanGoNext",
+                "CanGoPrevious",
+                "CanPause",
+                "CanPlay",
+                "CanSeek",
+                "Metadata"
+            ]
+        );
+
+        let mut new = old.clone();
+        new.art = Some("/tmp/cover.jpg".to_string());
+        assert_eq!(names(new.changes(&old)), ["Metadata"]);
+    }
+
+    #[test]
+    fn signals_a_seek_only_for_jumps_in_the_same_track() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = wav(dir.path(), "a.wav", 60);
+        let b = wav(dir.path(), "b.wav", 60);
+        let mut old = state(vec![a, b], Some(0));
+        old.position = Duration::from_secs(10);
+        old.read_at = Some(Instant::now());
+
+        let at = |position: u64| {
+            let mut new = old.clone();
+            new.position = Duration::from_secs(position);
+            new
+        };
+        assert!(!at(10).seeked(&old));
+        assert!(at(30).seeked(&old));
+        assert!(at(2).seeked(&old));
+
+        let mut next = at(0);
+        next.current = Some(1);
+        assert!(!next.seeked(&old));
+
+        let mut stopped = at(0);
+        stopped.stopped = true;
+        assert!(!stopped.seeked(&old));
+    }
+
+    #[test]
+    fn keeps_ids_of_tracks_that_stay_queued() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = wav(dir.path(), "a.wav", 1);
+        let b = wav(dir.path(), "b.wav", 1);
+        let c = wav(dir.path(), "c.wav", 1);
+        let old = state(vec![a.clone(), b.clone(), a.clone()], Some(0));
+        assert_eq!(old.ids, [0, 1, 2]);
+
+        let mut new = State {
+            queue: vec![c, a.clone(), b, a],
+            ..old.clone()
+        };
+        new.assign_ids(&old);
+        assert_eq!(new.ids, [3, 0, 1, 2]);
+        assert_eq!(new.position_of(1), Some(2));
+
+        // an id handed out for a track that left the queue leads nowhere
+        let mut shorter = State {
+            queue: vec![new.queue[0].clone()],
+            ..new.clone()
+        };
+        shorter.assign_ids(&new);
+        assert_eq!(shorter.ids, [3]);
+        assert_eq!(shorter.position_of(0), None);
+        assert_eq!(shorter.next_id, 4);
+    }
+}
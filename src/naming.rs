@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use gpui::{
+    div, prelude::FluentBuilder, px, AppContext, ElementId, InteractiveElement, IntoElement,
+    ParentElement, Render, SharedString, StatefulInteractiveElement, Styled, Task, View,
+    ViewContext, VisualContext, WindowContext,
+};
+
+use crate::{
+    events::{InputEvent, UiEvent},
+    input::TextInput,
+    metadata::{
+        cover::add_sidecar_covers,
+        cue,
+        database::DatabaseModel,
+        library::{Library, LibraryModel},
+        pattern::{self, Pattern, Rename},
+        playlist::PlaylistsModel,
+        tags::{self, TagField},
+        track::Track,
+    },
+    settings::Settings,
+    theme::Theme,
+};
+
+const TAGS_PATTERN: &str = "%artist%/%album%/%track% - %title%";
+const PATH_PATTERN: &str = "%albumartist%/%album%/%track% - %title%";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    TagsFromPath,
+    PathFromTags,
+}
+
+/// What applying the pattern would do to each file.
+enum Preview {
+    /// The tags read out of each path, `None` where the pattern does not fit.
+    Tags(Vec<Option<Vec<(TagField, String)>>>),
+    Paths(Vec<Rename>),
+}
+
+/// Reads tags out of file paths, or moves files to paths made from their
+/// tags, with a preview of either before anything is touched.
+pub struct NamingTool {
+    files: Vec<String>,
+    mode: Mode,
+    input: View<TextInput>,
+    // `None` while the preview is worked out or the pattern is invalid
+    preview: Option<Preview>,
+    // works out the preview of paths, dropped to cancel it for a newer pattern
+    planning: Option<Task<()>>,
+    pattern_error: Option<String>,
+    // moves made by the last rename, for undo
+    moved: Vec<(PathBuf, PathBuf)>,
+    status: Option<String>,
+    error: Option<String>,
+    busy: bool,
+}
+
+/// Path relative to the library directory, for display.
+fn short(path: &Path) -> String {
+    path.strip_prefix(Library::root())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+fn lossy(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Reads moved files from their new paths, paired with their old ones.
+pub fn read_moved(
+    moved: &[(PathBuf, PathBuf)],
+    cover_names: &[String],
+) -> Vec<(String, Vec<Track>)> {
+    moved
+        .iter()
+        .filter_map(|(from, to)| {
+            let mut tracks = cue::read_tracks(to).ok()?;
+            add_sidecar_covers(&mut tracks, cover_names);
+            Some((lossy(from), tracks))
+        })
+        .collect()
+}
+
+/// Points the library, statistics and playlists at the new paths of moved
+/// files. `tracks` are the moved files read again, paired with their old path.
+/// Returns the event telling playback to follow them too.
+pub fn follow_moves(
+    moved: &[(PathBuf, PathBuf)],
+    tracks: Vec<(String, Vec<Track>)>,
+    cx: &mut AppContext,
+) -> Arc<UiEvent> {
+    let paths = moved
+        .iter()
+        .map(|(from, to)| (lossy(from), lossy(to)))
+        .collect::<Vec<_>>();
+
+    cx.global::<LibraryModel>().clone().relocate(tracks, cx);
+    cx.global::<DatabaseModel>().clone().relocate(&paths, cx);
+    cx.global::<PlaylistsModel>()
+        .clone()
+        .update(cx, |playlists| playlists.relocate(&paths));
+    Arc::new(UiEvent::FilesMoved(paths))
+}
+
+impl NamingTool {
+    pub fn new(cx: &mut WindowContext, tracks: &[Arc<Track>]) -> View<Self> {
+        // the tags and path of an image are not those of any one cue sheet
+        // track, so those are left out
+        let files = tracks
+            .iter()
+            .filter(|track| track.segment.is_none())
+            .filter_map(|track| track.file.clone())
+            .collect::<Vec<_>>();
+
+        cx.new_view(|cx| {
+            let input = TextInput::new(cx, "%artist%/%album%/%track% - %title%");
+            input.update(cx, |input, cx| input.set_text(PATH_PATTERN, cx));
+            cx.subscribe(&input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Changed(_) = event {
+                    this.refresh(cx);
+                }
+            })
+            .detach();
+
+            let mut this = Self {
+                files,
+                mode: Mode::PathFromTags,
+                input,
+                preview: None,
+                planning: None,
+                pattern_error: None,
+                moved: Vec::new(),
+                status: None,
+                error: None,
+                busy: false,
+            };
+            this.refresh(cx);
+            this
+        })
+    }
+
+    fn set_mode(&mut self, mode: Mode, cx: &mut ViewContext<Self>) {
+        if mode == self.mode {
+            return;
+        }
+        self.mode = mode;
+        let text = match mode {
+            Mode::TagsFromPath => TAGS_PATTERN,
+            Mode::PathFromTags => PATH_PATTERN,
+        };
+        self.input.update(cx, |input, cx| input.set_text(text, cx));
+        self.refresh(cx);
+    }
+
+    /// Works the preview out again for the current pattern.
+    fn refresh(&mut self, cx: &mut ViewContext<Self>) {
+        let text = self.input.read(cx).text().to_string();
+        self.preview = None;
+        self.planning = None;
+        self.error = None;
+        cx.notify();
+
+        let layout = match Pattern::parse(&text) {
+            Ok(layout) => layout,
+            Err(err) => {
+                self.pattern_error = Some(err.to_string());
+                return;
+            }
+        };
+        self.pattern_error = None;
+
+        match self.mode {
+            Mode::TagsFromPath => {
+                let found = self
+                    .files
+                    .iter()
+                    .map(|file| layout.extract(Path::new(file)))
+                    .collect();
+                self.preview = Some(Preview::Tags(found));
+            }
+            Mode::PathFromTags => {
+                // reads the tags of every file
+                let files = self.files.clone();
+                self.planning = Some(cx.spawn(|this, mut cx| async move {
+                    let renames = cx
+                        .background_executor()
+                        .spawn(
+                            async move { pattern::plan_renames(&files, &layout, &Library::root()) },
+                        )
+                        .await;
+
+                    this.update(&mut cx, |this, cx| {
+                        this.preview = Some(Preview::Paths(renames));
+                        this.planning = None;
+                        cx.notify();
+                    })
+                    .ok();
+                }));
+            }
+        }
+    }
+
+    fn apply(&mut self, cx: &mut ViewContext<Self>) {
+        match &self.preview {
+            Some(Preview::Tags(found)) => {
+                let changes = self
+                    .files
+                    .iter()
+                    .zip(found)
+                    .filter_map(|(file, fields)| Some((PathBuf::from(file), fields.clone()?)))
+                    .collect::<HashMap<_, _>>();
+                self.write_tags(changes, cx);
+            }
+            Some(Preview::Paths(renames)) => {
+                if renames.iter().any(|rename| rename.conflict.is_some()) {
+                    self.error = Some("Some files cannot be moved, see above".to_string());
+                    cx.notify();
+                    return;
+                }
+                let renames = renames.clone();
+                self.move_files(renames, false, cx);
+            }
+            None => {}
+        }
+    }
+
+    fn write_tags(
+        &mut self,
+        changes: HashMap<PathBuf, Vec<(TagField, String)>>,
+        cx: &mut ViewContext<Self>,
+    ) {
+        if changes.is_empty() {
+            self.error = Some("The pattern fits none of the paths".to_string());
+            cx.notify();
+            return;
+        }
+        if let Some(err) = changes.values().find_map(|c| tags::validate(c).err()) {
+            self.error = Some(err.to_string());
+            cx.notify();
+            return;
+        }
+        self.busy = true;
+        self.status = None;
+        cx.notify();
+
+        let files = changes.keys().map(|path| lossy(path)).collect::<Vec<_>>();
+        let cover_names = cx.global::<Settings>().cover_names.clone();
+        cx.spawn(|this, mut cx| async move {
+            let (tracks, errors) = cx
+                .background_executor()
+                .spawn(async move {
+                    tags::rewrite(
+                        &files,
+                        |path| tags::write(path, &changes[path]),
+                        &cover_names,
+                    )
+                })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                this.busy = false;
+                this.status = Some(format!("Tagged {} files", tracks.len()));
+                if !errors.is_empty() {
+                    this.error = Some(errors.join("\n"));
+                }
+                cx.global::<LibraryModel>().clone().replace(tracks, cx);
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Moves files in the background, then follows them around the app.
+    /// Undoing leaves nothing to undo but what failed to move back.
+    fn move_files(&mut self, renames: Vec<Rename>, undoing: bool, cx: &mut ViewContext<Self>) {
+        self.busy = true;
+        self.status = None;
+        self.error = None;
+        cx.notify();
+
+        let cover_names = cx.global::<Settings>().cover_names.clone();
+        cx.spawn(|this, mut cx| async move {
+            let (moved, failure, tracks) = cx
+                .background_executor()
+                .spawn(async move {
+                    let (moved, failure) = pattern::apply(&renames, &Library::root());
+                    let tracks = read_moved(&moved, &cover_names);
+                    (moved, failure, tracks)
+                })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                let event = follow_moves(&moved, tracks, cx);
+                cx.emit(event);
+
+                let new_paths = moved
+                    .iter()
+                    .map(|(from, to)| (lossy(from), lossy(to)))
+                    .collect::<HashMap<_, _>>();
+                for file in &mut this.files {
+                    if let Some(new) = new_paths.get(file) {
+                        *file = new.clone();
+                    }
+                }
+
+                if undoing {
+                    let left = this.moved.len() - moved.len();
+                    this.moved.truncate(left);
+                    this.status = Some(format!("Moved {} files back", moved.len()));
+                } else {
+                    this.status = Some(format!("Moved {} files", moved.len()));
+                    this.moved = moved;
+                }
+                this.error = failure.map(|err| err.to_string());
+                this.busy = false;
+                this.refresh(cx);
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn undo(&mut self, cx: &mut ViewContext<Self>) {
+        let renames = pattern::reverse(&self.moved);
+        self.move_files(renames, true, cx);
+    }
+
+    fn button(
+        &self,
+        id: &'static str,
+        label: impl Into<SharedString>,
+        cx: &mut ViewContext<Self>,
+        on_click: impl Fn(&mut Self, &mut ViewContext<Self>) + 'static,
+    ) -> impl IntoElement {
+        div()
+            .id(id)
+            .px_2()
+            .child(label.into())
+            .on_click(cx.listener(move |this, _, cx| on_click(this, cx)))
+    }
+
+    fn render_preview(&self, cx: &mut ViewContext<Self>) -> Vec<impl IntoElement> {
+        let theme = cx.global::<Theme>();
+        let (label, invalid) = (theme.subtext0, theme.red);
+
+        let row = |ix: usize, from: String| {
+            div()
+                .id(ElementId::Integer(ix))
+                .flex()
+                .gap_2()
+                .text_sm()
+                .whitespace_nowrap()
+                .child(
+                    div()
+                        .flex_none()
+                        .w(px(260.))
+                        .overflow_hidden()
+                        .text_color(label)
+                        .child(from),
+                )
+        };
+
+        match &self.preview {
+            Some(Preview::Tags(found)) => self
+                .files
+                .iter()
+                .zip(found)
+                .enumerate()
+                .map(|(ix, (file, fields))| {
+                    let e = row(ix, short(Path::new(file)));
+                    match fields {
+                        Some(fields) => e.child(
+                            fields
+                                .iter()
+                                .map(|(field, value)| format!("{}: {}", field.label(), value))
+                                .collect::<Vec<_>>()
+                                .join(" · "),
+                        ),
+                        None => e.child(div().text_color(invalid).child("does not fit")),
+                    }
+                })
+                .collect(),
+            Some(Preview::Paths(renames)) => renames
+                .iter()
+                .enumerate()
+                .map(|(ix, rename)| {
+                    let e = row(ix, short(&rename.from));
+                    match &rename.conflict {
+                        Some(conflict) => e.child(div().text_color(invalid).child(format!(
+                            "{} ({})",
+                            short(&rename.to),
+                            conflict
+                        ))),
+                        None if rename.to == rename.from => e.child("(stays)"),
+                        None => e.child(short(&rename.to)),
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Render for NamingTool {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (background, text, label, invalid) =
+            (theme.base, theme.text, theme.subtext0, theme.red);
+
+        let mode_button =
+            |id: &'static str, mode: Mode, title: &'static str, cx: &mut ViewContext<Self>| {
+                div()
+                    .id(id)
+                    .px_2()
+                    .text_color(if self.mode == mode { text } else { label })
+                    .child(title)
+                    .on_click(cx.listener(move |this, _, cx| this.set_mode(mode, cx)))
+            };
+        let modes = div()
+            .flex()
+            .gap_2()
+            .child(mode_button(
+                "paths-from-tags",
+                Mode::PathFromTags,
+                "Paths from tags",
+                cx,
+            ))
+            .child(mode_button(
+                "tags-from-paths",
+                Mode::TagsFromPath,
+                "Tags from paths",
+                cx,
+            ));
+
+        let preview = self.render_preview(cx);
+        let waiting = self.preview.is_none() && self.pattern_error.is_none();
+
+        let apply_label = match self.mode {
+            Mode::TagsFromPath => "Write tags",
+            Mode::PathFromTags => "Move files",
+        };
+        let buttons = div()
+            .flex()
+            .justify_end()
+            .gap_2()
+            .when(!self.moved.is_empty() && !self.busy, |e| {
+                e.child(self.button("undo-rename", "Undo", cx, Self::undo))
+            })
+            .child(self.button("close-naming", "Close", cx, |_, cx| {
+                cx.emit(Arc::new(UiEvent::DialogClosed))
+            }))
+            .when(self.preview.is_some() && !self.busy, |e| {
+                e.child(self.button("apply-naming", apply_label, cx, Self::apply))
+            });
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .w(px(720.))
+            .p_3()
+            .rounded_md()
+            .bg(background)
+            .text_color(text)
+            .child(modes)
+            .child(self.input.clone())
+            .when_some(self.pattern_error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .child(
+                div()
+                    .id("naming-preview")
+                    .flex()
+                    .flex_col()
+                    .max_h(px(360.))
+                    .overflow_y_scroll()
+                    .children(preview)
+                    .when(waiting, |e| {
+                        e.child(div().text_sm().text_color(label).child("Reading tags…"))
+                    }),
+            )
+            .when_some(self.status.clone(), |e, status| {
+                e.child(div().text_sm().text_color(label).child(status))
+            })
+            .when_some(self.error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .when(self.busy, |e| {
+                e.child(div().text_sm().text_color(label).child("Working…"))
+            })
+            .child(buttons)
+    }
+}
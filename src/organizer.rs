@@ -0,0 +1,302 @@
+use std::{fs, ops::Range, path::Path, sync::Arc};
+
+use gpui::{
+    div, prelude::FluentBuilder, px, uniform_list, AnyElement, ElementId, InteractiveElement,
+    IntoElement, ParentElement, Render, SharedString, StatefulInteractiveElement, Styled, View,
+    ViewContext, VisualContext, WindowContext,
+};
+
+use crate::{
+    events::{InputEvent, UiEvent},
+    input::TextInput,
+    metadata::{
+        library::{Library, LibraryModel},
+        organize::Plan,
+        pattern::{Pattern, Rename},
+    },
+    naming::{follow_moves, read_moved},
+    settings::Settings,
+    theme::Theme,
+};
+
+/// Moves the whole library into the layout of one pattern. Nothing moves
+/// until the dry run of the pattern has been looked at.
+pub struct Organizer {
+    input: View<TextInput>,
+    // the pattern the plan was made for
+    planned: Option<String>,
+    plan: Option<Plan>,
+    // only the files that move or cannot, for the preview
+    rows: Vec<Rename>,
+    pattern_error: Option<String>,
+    status: Option<String>,
+    error: Option<String>,
+    busy: bool,
+}
+
+/// Path relative to the library directory, for display.
+fn short(path: &Path) -> String {
+    path.strip_prefix(Library::root())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+impl Organizer {
+    pub fn new(cx: &mut WindowContext) -> View<Self> {
+        let pattern = cx.global::<Settings>().organize_pattern.clone();
+
+        cx.new_view(|cx| {
+            let input = TextInput::new(cx, "%albumartist%/%album%/%track% - %title%");
+            input.update(cx, |input, cx| input.set_text(pattern, cx));
+            // planning reads the tags of the whole library, so not on every key
+            cx.subscribe(&input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Submitted(_) = event {
+                    this.make_plan(cx);
+                }
+                cx.notify();
+            })
+            .detach();
+
+            let mut this = Self {
+                input,
+                planned: None,
+                plan: None,
+                rows: Vec::new(),
+                pattern_error: None,
+                status: None,
+                error: None,
+                busy: false,
+            };
+            this.make_plan(cx);
+            this
+        })
+    }
+
+    /// Works out a dry run of the current pattern in the background.
+    fn make_plan(&mut self, cx: &mut ViewContext<Self>) {
+        let text = self.input.read(cx).text().to_string();
+        let layout = match Pattern::parse(&text) {
+            Ok(layout) => layout,
+            Err(err) => {
+                self.pattern_error = Some(err.to_string());
+                cx.notify();
+                return;
+            }
+        };
+        self.pattern_error = None;
+        self.busy = true;
+        cx.notify();
+
+        let files = cx.global::<LibraryModel>().paths(cx);
+        let cover_names = cx.global::<Settings>().cover_names.clone();
+        cx.spawn(|this, mut cx| async move {
+            let plan = cx
+                .background_executor()
+                .spawn(async move { Plan::new(&files, &layout, &Library::root(), &cover_names) })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                this.busy = false;
+                this.show(text, plan);
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn show(&mut self, text: String, plan: Plan) {
+        self.rows = plan
+            .tracks
+            .iter()
+            .chain(&plan.sidecars)
+            .filter(|rename| rename.conflict.is_some() || rename.from != rename.to)
+            .cloned()
+            .collect();
+        self.planned = Some(text);
+        self.plan = Some(plan);
+    }
+
+    /// Whether the plan is for the pattern as it is typed now.
+    fn is_current(&self, cx: &WindowContext) -> bool {
+        self.planned.as_deref() == Some(self.input.read(cx).text())
+    }
+
+    fn organize(&mut self, cx: &mut ViewContext<Self>) {
+        let (Some(plan), Some(text)) = (self.plan.take(), self.planned.take()) else {
+            return;
+        };
+        self.rows.clear();
+        self.busy = true;
+        self.status = None;
+        self.error = None;
+        cx.notify();
+
+        let cover_names = cx.global::<Settings>().cover_names.clone();
+        cx.spawn(|this, mut cx| async move {
+            let result = cx
+                .background_executor()
+                .spawn(async move {
+                    plan.apply().map(|moved| {
+                        let tracks = read_moved(&moved, &cover_names);
+                        (moved, tracks)
+                    })
+                })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                this.busy = false;
+                match result {
+                    Ok((moved, tracks)) => {
+                        let event = follow_moves(&moved, tracks, cx);
+                        cx.emit(event);
+                        Settings::update(cx, |settings| settings.organize_pattern = text);
+                        this.status = Some(format!("Moved {} tracks", moved.len()));
+                    }
+                    Err(err) => this.error = Some(err.to_string()),
+                }
+                this.make_plan(cx);
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Asks where to save the dry run as text.
+    fn save_report(&mut self, cx: &mut ViewContext<Self>) {
+        let Some(report) = self.plan.as_ref().map(Plan::report) else {
+            return;
+        };
+        let destination = cx.prompt_for_new_path(&Library::root());
+
+        cx.spawn(|this, mut cx| async move {
+            let Ok(Some(destination)) = destination.await else {
+                return;
+            };
+            let saved = fs::write(&destination, report);
+
+            this.update(&mut cx, |this, cx| {
+                match saved {
+                    Ok(()) => this.status = Some(format!("Saved {}", destination.display())),
+                    Err(err) => this.error = Some(format!("Failed to save the report: {}", err)),
+                }
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn render_rows(&mut self, rows: Range<usize>, cx: &mut ViewContext<Self>) -> Vec<AnyElement> {
+        let theme = cx.global::<Theme>();
+        let (label, invalid) = (theme.subtext0, theme.red);
+
+        rows.filter_map(|ix| {
+            let rename = self.rows.get(ix)?;
+            let e = div()
+                .id(ElementId::Integer(ix))
+                .flex()
+                .gap_2()
+                .h_6()
+                .text_sm()
+                .whitespace_nowrap()
+                .child(
+                    div()
+                        .flex_none()
+                        .w(px(340.))
+                        .overflow_hidden()
+                        .text_color(label)
+                        .child(short(&rename.from)),
+                );
+            let e = match &rename.conflict {
+                Some(conflict) => e.child(div().text_color(invalid).child(conflict.clone())),
+                None => e.child(short(&rename.to)),
+            };
+            Some(e.into_any_element())
+        })
+        .collect()
+    }
+
+    fn button(
+        &self,
+        id: &'static str,
+        label: impl Into<SharedString>,
+        cx: &mut ViewContext<Self>,
+        on_click: impl Fn(&mut Self, &mut ViewContext<Self>) + 'static,
+    ) -> impl IntoElement {
+        div()
+            .id(id)
+            .px_2()
+            .child(label.into())
+            .on_click(cx.listener(move |this, _, cx| on_click(this, cx)))
+    }
+}
+
+impl Render for Organizer {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (background, text, label, invalid) =
+            (theme.base, theme.text, theme.subtext0, theme.red);
+
+        let current = self.is_current(cx);
+        let summary = match &self.plan {
+            _ if self.busy => "Working…".to_string(),
+            Some(_) if !current => "Press enter to see what the new pattern does".to_string(),
+            Some(plan) => format!(
+                "{} files to move, {} problems",
+                plan.moves(),
+                plan.conflicts()
+            ),
+            None => String::new(),
+        };
+        let can_organize = !self.busy
+            && current
+            && self
+                .plan
+                .as_ref()
+                .is_some_and(|plan| plan.moves() > 0 && plan.conflicts() == 0);
+
+        let view = cx.view().clone();
+        let rows =
+            uniform_list(view, "organize-rows", self.rows.len(), Self::render_rows).h(px(360.));
+
+        let buttons = div()
+            .flex()
+            .justify_end()
+            .gap_2()
+            .when(self.plan.is_some() && !self.busy, |e| {
+                e.child(self.button("save-report", "Save report", cx, Self::save_report))
+            })
+            .child(self.button("close-organizer", "Close", cx, |_, cx| {
+                cx.emit(Arc::new(UiEvent::DialogClosed))
+            }))
+            .when(can_organize, |e| {
+                e.child(self.button("organize-library", "Organize", cx, Self::organize))
+            });
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .w(px(800.))
+            .p_3()
+            .rounded_md()
+            .bg(background)
+            .text_color(text)
+            .child("Organize library")
+            .child(self.input.clone())
+            .when_some(self.pattern_error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .child(div().text_sm().text_color(label).child(summary))
+            .child(rows)
+            .when_some(self.status.clone(), |e, status| {
+                e.child(div().text_sm().text_color(label).child(status))
+            })
+            .when_some(self.error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .child(buttons)
+    }
+}
@@ -0,0 +1,986 @@
+mod output;
+mod position;
+mod queue;
+mod session;
+mod stretch;
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering::SeqCst;
+use std::time::Duration;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use anyhow::{Context as _, Result};
+use gpui::{AppContext, Context, Model, ModelContext, Task, WindowContext};
+use log::{error, warn};
+use rodio::{Decoder, Sink, Source};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::PlaybackEvent,
+    metadata::{cue, library::LibraryModel, track::Track},
+    settings::Settings,
+    storage,
+};
+
+pub use self::{
+    output::{output_devices, AudioOutput},
+    queue::Repeat,
+    stretch::{PITCH_RANGE, SPEED_RANGE},
+};
+use self::{
+    position::{Position, Tracked},
+    queue::Queue,
+    session::Session,
+    stretch::{Stretch, StretchControls},
+};
+
+const POLL_DURATION: Duration = Duration::from_millis(100);
+const DEVICE_POLL_DURATION: Duration = Duration::from_secs(2);
+// changes within this long of each other are saved together
+const SESSION_SAVE_DELAY: Duration = Duration::from_secs(10);
+const RATES_FILE: &str = "rates.json";
+// going back this far into a track restarts it rather than going to the previous one
+const RESTART_AFTER: Duration = Duration::from_secs(3);
+
+pub struct Playback {
+    queue: Queue,
+    player: Player,
+    rates: TrackRates,
+    // where the session and playback rates are kept
+    data_dir: PathBuf,
+    pending_save: Option<Task<()>>,
+}
+
+impl Playback {
+    pub fn init(cx: &mut WindowContext) -> Model<Self> {
+        let device = cx.global::<Settings>().output_device.clone();
+
+        let data_dir = storage::data_dir();
+        let session = Session::load(&data_dir);
+        let library = cx.global::<LibraryModel>().clone();
+        let tracks = session
+            .tracks
+            .iter()
+            .map(|path| library.find_track(path, cx))
+            .collect::<Vec<_>>();
+
+        cx.new_model(|cx| {
+            let mut this = Self::new(Player::new(device), data_dir, cx);
+            this.restore(session, tracks);
+
+            cx.on_app_quit(|this, _| {
+                this.pending_save = None;
+                this.save_session();
+                async {}
+            })
+            .detach();
+
+            this
+        })
+    }
+
+    /// Creates a playback driving the given output instead of a system
+    /// device, keeping its files in `data_dir`, for headless tests.
+    #[cfg(test)]
+    pub fn with_output(
+        output: Box<dyn AudioOutput>,
+        data_dir: PathBuf,
+        cx: &mut ModelContext<Self>,
+    ) -> Self {
+        Self::new(Player::with_output(output), data_dir, cx)
+    }
+
+    fn new(player: Player, data_dir: PathBuf, cx: &mut ModelContext<Self>) -> Self {
+        player.watch(cx);
+
+        Self {
+            queue: Queue::default(),
+            player,
+            rates: storage::load_from(&data_dir, RATES_FILE),
+            data_dir,
+            pending_save: None,
+        }
+    }
+
+    pub fn play(&mut self, track: Arc<Track>, cx: &mut ModelContext<Self>) {
+        self.end_current(false, cx);
+
+        self.queue.play(&track);
+        self.start(&track, cx);
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    /// Replaces the queue with `tracks` and starts playing them.
+    pub fn play_tracks(&mut self, tracks: Vec<Arc<Track>>, cx: &mut ModelContext<Self>) {
+        self.end_current(false, cx);
+
+        self.queue.replace(tracks);
+        match self.queue.get_current() {
+            Some(track) => self.start(&track, cx),
+            None => self.player.stop(),
+        }
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    /// Appends tracks to the queue, starting playback if nothing is playing.
+    pub fn enqueue(&mut self, tracks: Vec<Arc<Track>>, cx: &mut ModelContext<Self>) {
+        let first = self.queue.tracks.len();
+        self.queue.enqueue(tracks);
+
+        if self.queue.current.is_none() && first < self.queue.tracks.len() {
+            self.queue.current = Some(first);
+            if let Some(track) = self.queue.get_current() {
+                self.queue.playing = true;
+                self.start(&track, cx);
+            }
+        }
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn pause(&mut self, cx: &mut ModelContext<Self>) {
+        self.player.pause();
+        self.queue.playing = false;
+        self.save_later(cx);
+
+        cx.emit(Arc::new(PlaybackEvent::Paused));
+        cx.notify();
+    }
+
+    /// Goes on playing, starting the current track over after a stop.
+    pub fn resume(&mut self, cx: &mut ModelContext<Self>) {
+        let Some(track) = self.queue.get_current() else {
+            return;
+        };
+
+        self.queue.playing = true;
+        if self.is_stopped() {
+            self.start(&track, cx);
+        } else {
+            self.player.resume();
+        }
+        self.save_later(cx);
+
+        cx.emit(Arc::new(PlaybackEvent::Resumed));
+        cx.notify();
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.queue.playing
+    }
+
+    /// Whether no track is loaded, after a stop or at the end of the queue.
+    pub fn is_stopped(&self) -> bool {
+        self.player.current.is_none()
+    }
+
+    pub fn current(&self) -> Option<Arc<Track>> {
+        self.queue.get_current()
+    }
+
+    /// Queued tracks in play order.
+    pub fn queue(&self) -> Vec<Arc<Track>> {
+        self.queue
+            .order
+            .iter()
+            .filter_map(|&index| self.queue.tracks.get(index))
+            .cloned()
+            .collect()
+    }
+
+    /// Skips to the next track in the queue.
+    pub fn next(&mut self, cx: &mut ModelContext<Self>) {
+        self.advance(true, cx);
+    }
+
+    /// Goes back to the previous track in the queue, or to the start of the
+    /// current one once it has played for a bit.
+    pub fn previous(&mut self, cx: &mut ModelContext<Self>) {
+        match self.queue.current {
+            Some(position) if position > 0 && self.position() < RESTART_AFTER => {
+                self.go_to(position - 1, cx)
+            }
+            Some(_) => self.seek(Duration::ZERO, cx),
+            None => {}
+        }
+    }
+
+    /// Plays the track at `position` in play order.
+    pub fn go_to(&mut self, position: usize, cx: &mut ModelContext<Self>) {
+        if position >= self.queue.order.len() {
+            return;
+        }
+        self.end_current(false, cx);
+
+        self.queue.current = Some(position);
+        self.queue.playing = true;
+        if let Some(track) = self.queue.get_current() {
+            self.start(&track, cx);
+        }
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    /// Stops playing and unloads the current track, keeping the queue.
+    pub fn stop(&mut self, cx: &mut ModelContext<Self>) {
+        if self.is_stopped() {
+            return;
+        }
+        self.end_current(false, cx);
+
+        self.player.stop();
+        self.queue.playing = false;
+        self.save_later(cx);
+
+        cx.emit(Arc::new(PlaybackEvent::Paused));
+        cx.notify();
+    }
+
+    /// Where the current track is in play order.
+    pub fn current_position(&self) -> Option<usize> {
+        self.queue.current
+    }
+
+    pub fn seek(&mut self, position: Duration, cx: &mut ModelContext<Self>) {
+        self.player.seek(position);
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.queue.shuffle
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool, cx: &mut ModelContext<Self>) {
+        self.queue.set_shuffle(shuffle);
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        self.queue.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat, cx: &mut ModelContext<Self>) {
+        self.queue.repeat = repeat;
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.player.volume
+    }
+
+    pub fn set_volume(&mut self, volume: f32, cx: &mut ModelContext<Self>) {
+        self.player.set_volume(volume);
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.player.controls.speed()
+    }
+
+    pub fn set_speed(&mut self, speed: f32, cx: &mut ModelContext<Self>) {
+        self.player.controls.set_speed(speed);
+
+        if let Some(track) = self.queue.get_current().filter(|t| t.is_spoken_word()) {
+            self.rates.set(&track, self.speed());
+            if let Err(err) = storage::save_to(&self.data_dir, RATES_FILE, &self.rates) {
+                error!("Failed to save playback rates: {}", err);
+            }
+        }
+
+        cx.notify();
+    }
+
+    /// Pitch shift in semitones.
+    pub fn pitch(&self) -> f32 {
+        self.player.controls.pitch()
+    }
+
+    pub fn set_pitch(&mut self, semitones: f32, cx: &mut ModelContext<Self>) {
+        self.player.controls.set_pitch(semitones);
+        cx.notify();
+    }
+
+    pub fn position(&self) -> Duration {
+        self.player.position()
+    }
+
+    /// Name of the output device in use, `None` when not playing to a device.
+    pub fn output_device(&self) -> Option<&str> {
+        self.player.device()
+    }
+
+    /// The device picked to play to, `None` for the system default.
+    pub fn preferred_device(&self) -> Option<&str> {
+        self.player.preferred_device.as_ref()?.as_deref()
+    }
+
+    /// Follows files moved from the first path to the second: the queue
+    /// takes the library tracks at the new paths and the remembered rates
+    /// and saved session point there.
+    pub fn relocate(&mut self, moved: &[(String, String)], cx: &mut ModelContext<Self>) {
+        let moved = moved.iter().cloned().collect::<HashMap<_, _>>();
+        let app: &AppContext = cx;
+        let library = app.global::<LibraryModel>();
+        let relocated = |track: &Arc<Track>| {
+            let new = cue::relocated(track.file.as_deref()?, &moved)?;
+            library.find_track(&new, app)
+        };
+
+        for track in &mut self.queue.tracks {
+            if let Some(new) = relocated(track) {
+                *track = new;
+            }
+        }
+        if let Some(new) = self.player.current.as_ref().and_then(relocated) {
+            self.player.current = Some(new);
+        }
+
+        self.rates.relocate(&moved);
+        if let Err(err) = storage::save_to(&self.data_dir, RATES_FILE, &self.rates) {
+            error!("Failed to save playback rates: {}", err);
+        }
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    pub fn set_output_device(&mut self, device: Option<String>, cx: &mut ModelContext<Self>) {
+        self.player.set_device(device);
+        cx.notify();
+    }
+
+    fn start(&mut self, track: &Arc<Track>, cx: &mut ModelContext<Self>) {
+        self.apply_rate(track);
+
+        self.player.runs_on = self.runs_on();
+        if let Err(err) = self.player.play(track) {
+            error!("Failed to play {:?}: {}", track.file, err);
+        }
+
+        cx.emit(PlaybackEvent::start(track));
+    }
+
+    /// Plays spoken-word tracks at their remembered speed and everything
+    /// else at normal speed.
+    fn apply_rate(&self, track: &Track) {
+        let speed = match track.is_spoken_word() {
+            true => self.rates.get(track).unwrap_or(1.0),
+            false => 1.0,
+        };
+        self.player.controls.set_speed(speed);
+    }
+
+    fn end_current(&mut self, completed: bool, cx: &mut ModelContext<Self>) {
+        if let Some(track) = self.queue.get_current() {
+            cx.emit(PlaybackEvent::end(&track, self.position(), completed));
+        }
+    }
+
+    fn advance(&mut self, skip: bool, cx: &mut ModelContext<Self>) {
+        self.end_current(!skip, cx);
+
+        let next = self.queue.get_next(skip);
+        cx.notify();
+
+        match next {
+            Some(next) => self.start(&next, cx),
+            None => self.player.stop(),
+        }
+        self.save_later(cx);
+    }
+
+    fn on_track_end(&mut self, cx: &mut ModelContext<Self>) {
+        self.advance(false, cx);
+    }
+
+    /// Whether the current track goes straight on into the next one in the
+    /// queue, both being consecutive tracks of one cue sheet.
+    fn runs_on(&self) -> bool {
+        let (Some(current), Some(next)) = (self.queue.get_current(), self.queue.peek_next()) else {
+            return false;
+        };
+        self.queue.repeat != Repeat::One && current.continues_into(&next)
+    }
+
+    /// Moves on from a cue sheet track that ends inside its file. When the
+    /// next track follows on in the file it is already playing, so only the
+    /// queue moves and there is no gap.
+    fn check_segment_end(&mut self, cx: &mut ModelContext<Self>) {
+        if !self.player.runs_on {
+            return;
+        }
+        // the last track ends with the file, which `on_track_end` handles
+        let Some(current) = self
+            .queue
+            .get_current()
+            .filter(|track| track.segment.is_some_and(|segment| segment.end.is_some()))
+        else {
+            return;
+        };
+        if self.position() < current.duration() {
+            return;
+        }
+
+        if !self.runs_on() {
+            // the queue changed since the track started
+            self.advance(false, cx);
+            return;
+        }
+
+        self.end_current(true, cx);
+        if let Some(next) = self.queue.get_next(false) {
+            self.player.current = Some(Arc::clone(&next));
+            cx.emit(PlaybackEvent::start(&next));
+        }
+        self.save_later(cx);
+        cx.notify();
+    }
+
+    /// Saves the session once changes settle down, and again every so often
+    /// while playing so the position is kept.
+    fn save_later(&mut self, cx: &mut ModelContext<Self>) {
+        if self.pending_save.is_some() {
+            return;
+        }
+        self.pending_save = Some(cx.spawn(|this, mut cx| async move {
+            cx.background_executor().timer(SESSION_SAVE_DELAY).await;
+            this.update(&mut cx, |playback, cx| {
+                playback.pending_save = None;
+                playback.save_session();
+                if playback.is_playing() {
+                    playback.save_later(cx);
+                }
+            })
+            .ok();
+        }));
+    }
+
+    fn save_session(&self) {
+        Session {
+            tracks: self
+                .queue
+                .tracks
+                .iter()
+                .filter_map(|track| track.file.clone())
+                .collect(),
+            order: self.queue.order.clone(),
+            current: self.queue.current,
+            position: self.position(),
+            shuffle: self.queue.shuffle,
+            repeat: self.queue.repeat,
+            volume: self.player.volume,
+        }
+        .save(&self.data_dir);
+    }
+
+    /// Restores a saved session, paused at the saved position. Tracks that
+    /// could not be found are dropped; if the current one is among them
+    /// playback resumes at the start of the next remaining track.
+    fn restore(&mut self, session: Session, tracks: Vec<Option<Arc<Track>>>) {
+        self.player.set_volume(session.volume);
+        self.queue.shuffle = session.shuffle;
+        self.queue.repeat = session.repeat;
+
+        let mut remap = Vec::with_capacity(tracks.len());
+        for track in tracks {
+            remap.push(track.as_ref().map(|_| self.queue.tracks.len()));
+            self.queue.tracks.extend(track);
+        }
+
+        let mut start = Duration::ZERO;
+        for (position, &index) in session.order.iter().enumerate() {
+            let Some(&Some(index)) = remap.get(index) else {
+                continue;
+            };
+            if self.queue.current.is_none() && session.current.is_some_and(|c| position >= c) {
+                self.queue.current = Some(self.queue.order.len());
+                if session.current == Some(position) {
+                    start = session.position;
+                }
+            }
+            self.queue.order.push(index);
+        }
+
+        // an unusable order, e.g. from an older session file
+        if self.queue.order.len() != self.queue.tracks.len() {
+            self.queue.order = (0..self.queue.tracks.len()).collect();
+            self.queue.current = self.queue.current.filter(|&c| c < self.queue.tracks.len());
+        }
+
+        if let Some(track) = self.queue.get_current() {
+            self.apply_rate(&track);
+            if let Err(err) = self.player.load(&track, start, true) {
+                error!("Failed to restore {:?}: {}", track.file, err);
+            }
+        }
+    }
+}
+
+/// Playback speeds remembered for spoken-word tracks, keyed by file path.
+#[derive(Default, Serialize, Deserialize)]
+struct TrackRates(HashMap<String, f32>);
+
+impl TrackRates {
+    fn get(&self, track: &Track) -> Option<f32> {
+        track
+            .file
+            .as_ref()
+            .and_then(|file| self.0.get(file))
+            .copied()
+    }
+
+    fn set(&mut self, track: &Track, speed: f32) {
+        if let Some(file) = track.file.clone() {
+            self.0.insert(file, speed);
+        }
+    }
+
+    fn relocate(&mut self, moved: &HashMap<String, String>) {
+        self.0 = std::mem::take(&mut self.0)
+            .into_iter()
+            .map(|(file, speed)| (cue::relocated(&file, moved).unwrap_or(file), speed))
+            .collect();
+    }
+}
+
+/// The decoder playback reads the file of `track` with.
+pub fn decode(track: &Track) -> Result<Decoder<BufReader<File>>> {
+    let path = track.path().context("track has no file")?;
+    let file = BufReader::new(File::open(path)?);
+    Ok(Decoder::new(file)?)
+}
+
+struct Player {
+    output: Box<dyn AudioOutput>,
+    sink: Sink,
+    // `None` when the output was handed in rather than picked from the system devices
+    preferred_device: Option<Option<String>>,
+    queue_len: Arc<AtomicUsize>,
+    controls: Arc<StretchControls>,
+    position: Position,
+    current: Option<Arc<Track>>,
+    // the current cue sheet track is not cut off at its end, playback
+    // goes on into the next one
+    runs_on: bool,
+    volume: f32,
+}
+
+impl Player {
+    fn new(preferred_device: Option<String>) -> Self {
+        let mut player = Self::with_output(output::open(preferred_device.as_deref()));
+        player.preferred_device = Some(preferred_device);
+        player
+    }
+
+    fn with_output(output: Box<dyn AudioOutput>) -> Self {
+        let sink = output.sink();
+
+        Self {
+            output,
+            sink,
+            preferred_device: None,
+            queue_len: Arc::new(AtomicUsize::new(0)),
+            controls: Arc::new(StretchControls::default()),
+            position: Position::default(),
+            current: None,
+            runs_on: false,
+            volume: 1.0,
+        }
+    }
+
+    fn watch(&self, cx: &mut ModelContext<Playback>) {
+        let queue_len = Arc::clone(&self.queue_len);
+
+        cx.spawn(|this, mut cx| async move {
+            let mut prev_len = queue_len.load(SeqCst);
+
+            loop {
+                let current_len = queue_len.load(SeqCst);
+
+                let updated = this.update(&mut cx, |playback, cx| {
+                    if current_len < prev_len {
+                        playback.on_track_end(cx);
+                    } else {
+                        playback.check_segment_end(cx);
+                    }
+                });
+                if updated.is_err() {
+                    break;
+                }
+                prev_len = current_len;
+                cx.background_executor().timer(POLL_DURATION).await;
+            }
+        })
+        .detach();
+
+        if self.preferred_device.is_none() {
+            return;
+        }
+
+        cx.spawn(|this, mut cx| async move {
+            loop {
+                cx.background_executor().timer(DEVICE_POLL_DURATION).await;
+
+                // asking the system for its devices can take a while
+                let devices = cx
+                    .background_executor()
+                    .spawn(async { output_devices() })
+                    .await;
+                let checked = this.update(&mut cx, |playback, _| {
+                    playback.player.check_output(&devices);
+                });
+                if checked.is_err() {
+                    break;
+                }
+            }
+        })
+        .detach();
+    }
+
+    fn get_source(track: &Arc<Track>) -> Result<Decoder<BufReader<File>>> {
+        decode(track)
+    }
+
+    fn play(&mut self, track: &Arc<Track>) -> Result<()> {
+        self.load(track, Duration::ZERO, false)
+    }
+
+    /// Starts `track` at `start` on a fresh sink, so that nothing has to
+    /// wait for the previous one to drain.
+    fn load(&mut self, track: &Arc<Track>, start: Duration, paused: bool) -> Result<()> {
+        self.current = Some(Arc::clone(track));
+
+        // positions in the file, which a cue sheet track is only part of
+        let start = track.segment.map_or(Duration::ZERO, |s| s.start) + start;
+        let end = track.segment.and_then(|s| s.end).filter(|_| !self.runs_on);
+
+        let mut decoder = Self::get_source(track)?;
+        let source: Box<dyn Source<Item = i16> + Send> = if start.is_zero() {
+            Box::new(decoder)
+        } else {
+            match decoder.try_seek(start) {
+                Ok(()) => Box::new(decoder),
+                // e.g. rodio cannot seek in FLAC, so the audio up to `start`
+                // is decoded and thrown away instead
+                Err(err) => {
+                    warn!("Cannot seek to {:?}, skipping there: {}", start, err);
+                    Box::new(decoder.skip_duration(start))
+                }
+            }
+        };
+
+        let source = Tracked::new(source.convert_samples(), self.position.clone(), start);
+        let source: Box<dyn Source<Item = f32> + Send> = match end {
+            Some(end) => Box::new(source.take_duration(end.saturating_sub(start))),
+            None => Box::new(source),
+        };
+        let source = Stretch::new(source, Arc::clone(&self.controls));
+        let source = rodio::source::Done::new(source, Arc::clone(&self.queue_len));
+
+        self.queue_len.store(1, SeqCst);
+        self.sink.stop();
+        self.sink = self.output.sink();
+        self.sink.set_volume(self.volume);
+        if paused {
+            self.sink.pause();
+        }
+        self.sink.append(source);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.sink.pause();
+    }
+
+    fn resume(&self) {
+        self.sink.play();
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.sink.set_volume(self.volume);
+    }
+
+    fn stop(&mut self) {
+        self.current = None;
+        self.runs_on = false;
+        self.sink.stop();
+    }
+
+    fn seek(&mut self, position: Duration) {
+        if let Some(track) = self.current.clone() {
+            let paused = self.sink.is_paused();
+            if let Err(err) = self.load(&track, position, paused) {
+                error!("Failed to seek {:?}: {}", track.file, err);
+            }
+        }
+    }
+
+    /// Position in the current track, which for a cue sheet track is not
+    /// the position in the file.
+    fn position(&self) -> Duration {
+        let Some(current) = &self.current else {
+            return Duration::ZERO;
+        };
+        let start = current
+            .segment
+            .map_or(Duration::ZERO, |segment| segment.start);
+        self.position.get().saturating_sub(start)
+    }
+
+    fn device(&self) -> Option<&str> {
+        self.output.name()
+    }
+
+    /// Reopens the output on another device, resuming the current track at
+    /// the same position.
+    fn switch_output(&mut self, output: Box<dyn AudioOutput>) {
+        let position = self.position();
+        let paused = self.sink.is_paused();
+
+        self.sink.stop();
+        self.output = output;
+        self.sink = self.output.sink();
+
+        if let Some(track) = self.current.clone() {
+            if let Err(err) = self.load(&track, position, paused) {
+                error!("Failed to resume {:?}: {}", track.file, err);
+            }
+        }
+    }
+
+    fn set_device(&mut self, device: Option<String>) {
+        let output = output::open(device.as_deref());
+        self.preferred_device = Some(device);
+        self.switch_output(output);
+    }
+
+    /// Falls back to another device when the current one disappears from
+    /// `devices`, and returns to the preferred one once it is back.
+    fn check_output(&mut self, devices: &[String]) {
+        let Some(preferred) = self.preferred_device.clone() else {
+            return;
+        };
+
+        let current = self.device().map(String::from);
+
+        let lost = current.as_ref().is_some_and(|d| !devices.contains(d));
+        let preferred_back = preferred
+            .as_ref()
+            .is_some_and(|p| current.as_ref() != Some(p) && devices.contains(p));
+        let appeared = current.is_none() && !devices.is_empty();
+
+        if !(lost || preferred_back || appeared) {
+            return;
+        }
+
+        let output = output::open(preferred.as_deref());
+        if lost || output.name().is_some() {
+            warn!(
+                "Switching audio output from {:?} to {:?}",
+                current,
+                output.name()
+            );
+            self.switch_output(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use gpui::{Context as _, TestAppContext};
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::{
+        output::{Clock, SimulatedClock, WavOutput},
+        *,
+    };
+
+    const RATE: u32 = 44_100;
+
+    /// A stereo sine tone `length` long, read back as a library track.
+    fn tone(dir: &Path, name: &str, length: Duration) -> Arc<Track> {
+        let path = dir.join(name);
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        let frames = (length.as_secs_f64() * RATE as f64) as u32;
+        for frame in 0..frames {
+            let t = frame as f32 / RATE as f32;
+            let sample = ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        Arc::new(Track::read(&path).unwrap())
+    }
+
+    fn wav_output(dir: &Path, clock: &SimulatedClock) -> Box<dyn AudioOutput> {
+        let clock = Clock::Simulated(clock.clone());
+        Box::new(WavOutput::create(dir.join("out.wav"), &clock).unwrap())
+    }
+
+    fn assert_near(actual: Duration, expected: Duration) {
+        let off = actual.abs_diff(expected);
+        assert!(
+            off <= Duration::from_millis(50),
+            "{:?} is not near {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn position_follows_the_simulated_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = tone(dir.path(), "tone.wav", Duration::from_secs(2));
+        let clock = SimulatedClock::default();
+        let mut player = Player::with_output(wav_output(dir.path(), &clock));
+
+        player.play(&track).unwrap();
+        assert_eq!(player.position(), Duration::ZERO);
+        clock.advance(Duration::from_millis(500));
+        assert_near(player.position(), Duration::from_millis(500));
+
+        // nothing is played while paused
+        player.pause();
+        clock.advance(Duration::from_millis(500));
+        assert_near(player.position(), Duration::from_millis(500));
+
+        player.resume();
+        player.seek(Duration::from_millis(1500));
+        assert_near(player.position(), Duration::from_millis(1500));
+        assert_eq!(player.queue_len.load(SeqCst), 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(player.queue_len.load(SeqCst), 0);
+    }
+
+    #[test]
+    fn wav_output_renders_what_is_played() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = tone(dir.path(), "tone.wav", Duration::from_secs(1));
+        let clock = SimulatedClock::default();
+        let mut player = Player::with_output(wav_output(dir.path(), &clock));
+
+        player.play(&track).unwrap();
+        clock.advance(Duration::from_secs(2));
+
+        let reader = hound::WavReader::open(dir.path().join("out.wav")).unwrap();
+        assert_eq!(reader.spec().sample_rate, RATE);
+        // the second after the track is padded with silence
+        assert_eq!(reader.duration(), 2 * RATE);
+        let samples = reader
+            .into_samples::<f32>()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let (played, silence) = samples.split_at(RATE as usize * 2);
+        assert!(played.iter().any(|sample| sample.abs() > 0.1));
+        assert!(silence.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[gpui::test]
+    fn advances_through_the_queue(cx: &mut TestAppContext) {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let tracks = vec![
+            tone(dir.path(), "one.wav", Duration::from_secs(1)),
+            tone(dir.path(), "two.wav", Duration::from_secs(1)),
+        ];
+        let clock = SimulatedClock::default();
+        let output = wav_output(dir.path(), &clock);
+        let playback = cx.new_model(|cx| Playback::with_output(output, data_dir.clone(), cx));
+
+        playback.update(cx, |playback, cx| playback.play_tracks(tracks, cx));
+        cx.run_until_parked();
+        clock.advance(Duration::from_millis(1200));
+        cx.executor().advance_clock(POLL_DURATION);
+        cx.run_until_parked();
+
+        playback.read_with(cx, |playback, _| {
+            assert_eq!(playback.current_position(), Some(1));
+            assert!(playback.is_playing());
+            assert!(playback.position() < Duration::from_millis(100));
+        });
+
+        clock.advance(Duration::from_millis(1200));
+        cx.executor().advance_clock(POLL_DURATION);
+        cx.run_until_parked();
+        playback.read_with(cx, |playback, _| {
+            assert_eq!(playback.current_position(), None);
+            assert!(!playback.is_playing());
+        });
+
+        // the session went to the data dir handed in
+        assert!(data_dir.join("session.json").is_file());
+    }
+
+    #[gpui::test]
+    fn only_spoken_word_tracks_keep_their_speed(cx: &mut TestAppContext) {
+        let dir = tempfile::tempdir().unwrap();
+        let music = tone(dir.path(), "music.wav", Duration::from_secs(1));
+        let mut podcast = (*tone(dir.path(), "podcast.wav", Duration::from_secs(1))).clone();
+        podcast.genre = Some("Podcast".to_string());
+        let podcast = Arc::new(podcast);
+        let clock = SimulatedClock::default();
+        let output = wav_output(dir.path(), &clock);
+        let data_dir = dir.path().join("data");
+        let playback = cx.new_model(|cx| Playback::with_output(output, data_dir, cx));
+
+        playback.update(cx, |playback, cx| {
+            playback.play(Arc::clone(&podcast), cx);
+            playback.set_speed(1.5, cx);
+
+            playback.play(Arc::clone(&music), cx);
+            assert_eq!(playback.speed(), 1.0);
+
+            playback.play(podcast, cx);
+            assert_eq!(playback.speed(), 1.5);
+        });
+    }
+
+    #[gpui::test]
+    fn stop_unloads_the_track_and_play_starts_it_over(cx: &mut TestAppContext) {
+        let dir = tempfile::tempdir().unwrap();
+        let track = tone(dir.path(), "tone.wav", Duration::from_secs(2));
+        let clock = SimulatedClock::default();
+        let output = wav_output(dir.path(), &clock);
+        let data_dir = dir.path().join("data");
+        let playback = cx.new_model(|cx| Playback::with_output(output, data_dir, cx));
+
+        playback.update(cx, |playback, cx| playback.play(track, cx));
+        clock.advance(Duration::from_millis(500));
+
+        playback.update(cx, |playback, cx| {
+            playback.stop(cx);
+            assert!(playback.is_stopped());
+            assert!(!playback.is_playing());
+            assert_eq!(playback.current_position(), Some(0));
+            assert_eq!(playback.position(), Duration::ZERO);
+
+            playback.resume(cx);
+            assert!(!playback.is_stopped());
+            assert!(playback.is_playing());
+        });
+        clock.advance(Duration::from_millis(300));
+        playback.read_with(cx, |playback, _| {
+            assert_near(playback.position(), Duration::from_millis(300));
+        });
+    }
+}
@@ -0,0 +1,244 @@
+#[cfg(test)]
+use std::{path::Path, sync::Mutex};
+use std::{
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+#[cfg(test)]
+use anyhow::Result;
+#[cfg(test)]
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{error, warn};
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait},
+    },
+    dynamic_mixer::{self, DynamicMixer, DynamicMixerController},
+    OutputStream, OutputStreamHandle, Sink,
+};
+
+const TICK: Duration = Duration::from_millis(100);
+const CHANNELS: u16 = 2;
+const SAMPLE_RATE: u32 = 44_100;
+
+pub fn output_devices() -> Vec<String> {
+    cpal::default_host()
+        .output_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Opens the preferred device, falling back to the system default and
+/// finally to a null output running in real time.
+pub fn open(preferred: Option<&str>) -> Box<dyn AudioOutput> {
+    match DeviceOutput::open(preferred) {
+        Some(output) => Box::new(output),
+        None => {
+            warn!("No audio output device available, using null output");
+            Box::new(NullOutput::new(&Clock::RealTime))
+        }
+    }
+}
+
+/// Destination of everything the `Player` plays.
+pub trait AudioOutput {
+    /// Device name, `None` when not playing to a real device.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Creates a new sink playing into this output.
+    fn sink(&self) -> Sink;
+}
+
+pub struct DeviceOutput {
+    name: String,
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+}
+
+impl DeviceOutput {
+    pub fn open(preferred: Option<&str>) -> Option<Self> {
+        let host = cpal::default_host();
+        let device = preferred
+            .and_then(|name| {
+                let device = host
+                    .output_devices()
+                    .ok()?
+                    .find(|device| device.name().is_ok_and(|n| n == name));
+                if device.is_none() {
+                    warn!("Output device not found: {}", name);
+                }
+                device
+            })
+            .or_else(|| host.default_output_device())?;
+
+        match OutputStream::try_from_device(&device) {
+            Ok((_stream, handle)) => Some(Self {
+                name: device.name().unwrap_or_default(),
+                _stream,
+                handle,
+            }),
+            Err(err) => {
+                error!("Failed to open output device: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl AudioOutput for DeviceOutput {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn sink(&self) -> Sink {
+        Sink::try_new(&self.handle).unwrap_or_else(|err| {
+            error!("Failed to create sink: {}", err);
+            Sink::new_idle().0
+        })
+    }
+}
+
+/// Discards everything it plays.
+pub struct NullOutput(MixerOutput);
+
+impl NullOutput {
+    pub fn new(clock: &Clock) -> Self {
+        Self(MixerOutput::new(clock, |_| {}))
+    }
+}
+
+impl AudioOutput for NullOutput {
+    fn sink(&self) -> Sink {
+        self.0.sink()
+    }
+}
+
+/// Renders everything it plays into a 32-bit float WAV file.
+#[cfg(test)]
+pub struct WavOutput(MixerOutput);
+
+#[cfg(test)]
+impl WavOutput {
+    pub fn create<P: AsRef<Path>>(path: P, clock: &Clock) -> Result<Self> {
+        let spec = WavSpec {
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(path, spec)?;
+
+        Ok(Self(MixerOutput::new(clock, move |samples| {
+            let written = samples
+                .iter()
+                .try_for_each(|sample| writer.write_sample(*sample))
+                .and_then(|_| writer.flush());
+            if let Err(err) = written {
+                error!("Failed to write wav output: {}", err);
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+impl AudioOutput for WavOutput {
+    fn sink(&self) -> Sink {
+        self.0.sink()
+    }
+}
+
+/// What drives outputs that are not backed by a device.
+#[derive(Clone)]
+pub enum Clock {
+    /// Samples are pulled on a background thread as fast as a device would.
+    RealTime,
+    /// Samples are only pulled when the clock is advanced by hand.
+    #[cfg(test)]
+    Simulated(SimulatedClock),
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct SimulatedClock(Arc<Mutex<Vec<Drain>>>);
+
+#[cfg(test)]
+impl SimulatedClock {
+    /// Plays `by` worth of audio on every output driven by this clock.
+    pub fn advance(&self, by: Duration) {
+        self.0.lock().unwrap().retain_mut(|drain| drain.pull(by));
+    }
+}
+
+struct MixerOutput {
+    mixer: Arc<DynamicMixerController<f32>>,
+    // drains stop once the output they belong to is dropped
+    _alive: Arc<()>,
+}
+
+impl MixerOutput {
+    fn new(clock: &Clock, write: impl FnMut(&[f32]) + Send + 'static) -> Self {
+        let (mixer, source) = dynamic_mixer::mixer(CHANNELS, SAMPLE_RATE);
+        let alive = Arc::new(());
+
+        let mut drain = Drain {
+            source,
+            write: Box::new(write),
+            alive: Arc::downgrade(&alive),
+            buf: Vec::new(),
+        };
+        match clock {
+            Clock::RealTime => {
+                thread::spawn(move || {
+                    while drain.pull(TICK) {
+                        thread::sleep(TICK);
+                    }
+                });
+            }
+            #[cfg(test)]
+            Clock::Simulated(clock) => clock.0.lock().unwrap().push(drain),
+        }
+
+        Self {
+            mixer,
+            _alive: alive,
+        }
+    }
+
+    fn sink(&self) -> Sink {
+        let (sink, queue) = Sink::new_idle();
+        self.mixer.add(queue);
+        sink
+    }
+}
+
+struct Drain {
+    source: DynamicMixer<f32>,
+    write: Box<dyn FnMut(&[f32]) + Send>,
+    alive: Weak<()>,
+    buf: Vec<f32>,
+}
+
+impl Drain {
+    /// Pulls `by` worth of samples, padding with silence while nothing plays.
+    /// Returns `false` once the output is gone.
+    fn pull(&mut self, by: Duration) -> bool {
+        if self.alive.strong_count() == 0 {
+            return false;
+        }
+
+        let frames = SAMPLE_RATE as u128 * by.as_micros() / 1_000_000;
+        let samples = frames as usize * CHANNELS as usize;
+
+        self.buf.clear();
+        self.buf.extend(self.source.by_ref().take(samples));
+        self.buf.resize(samples, 0.0);
+        (self.write)(&self.buf);
+
+        true
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+
+// how often the shared position is refreshed, in samples
+const UPDATE_INTERVAL: u64 = 512;
+
+/// Playback position of the current track, shared with the audio thread.
+#[derive(Clone, Default)]
+pub struct Position(Arc<AtomicU64>);
+
+impl Position {
+    pub fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Relaxed))
+    }
+
+    fn set(&self, position: Duration) {
+        self.0.store(position.as_millis() as u64, Relaxed);
+    }
+}
+
+/// Reports how far into its input playback has progressed.
+pub struct Tracked<I> {
+    input: I,
+    position: Position,
+    offset: Duration,
+    samples: u64,
+}
+
+impl<I> Tracked<I>
+where
+    I: Source,
+    I::Item: rodio::Sample,
+{
+    pub fn new(input: I, position: Position, offset: Duration) -> Self {
+        position.set(offset);
+
+        Self {
+            input,
+            position,
+            offset,
+            samples: 0,
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        let rate = self.input.sample_rate() as u64 * self.input.channels().max(1) as u64;
+        self.offset + Duration::from_millis(self.samples * 1000 / rate.max(1))
+    }
+}
+
+impl<I> Iterator for Tracked<I>
+where
+    I: Source,
+    I::Item: rodio::Sample,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let sample = self.input.next()?;
+
+        self.samples += 1;
+        if self.samples % UPDATE_INTERVAL == 0 {
+            self.position.set(self.elapsed());
+        }
+
+        Some(sample)
+    }
+}
+
+impl<I> Source for Tracked<I>
+where
+    I: Source,
+    I::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+
+        self.offset = pos;
+        self.samples = 0;
+        self.position.set(pos);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::track::Track;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Repeat {
+    #[default]
+    Off,
+    All,
+    One,
+}
+
+impl Repeat {
+    pub fn cycle(self) -> Self {
+        match self {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Queue {
+    pub tracks: Vec<Arc<Track>>,
+    /// Play order as indices into `tracks`, shuffled while shuffle is on.
+    pub order: Vec<usize>,
+    /// Position in `order` of the current track.
+    pub current: Option<usize>,
+    pub playing: bool,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+}
+
+impl Queue {
+    pub fn play(&mut self, track: &Arc<Track>) {
+        self.tracks = vec![Arc::clone(track)];
+        self.order = vec![0];
+        self.current = Some(0);
+        self.playing = true;
+    }
+
+    pub fn replace(&mut self, tracks: Vec<Arc<Track>>) {
+        self.tracks = tracks;
+        self.order = (0..self.tracks.len()).collect();
+        if self.shuffle {
+            self.order.shuffle(&mut rand::thread_rng());
+        }
+        self.current = Some(0).filter(|_| !self.tracks.is_empty());
+        self.playing = self.current.is_some();
+    }
+
+    pub fn get_current(&self) -> Option<Arc<Track>> {
+        self.current
+            .and_then(|position| self.order.get(position))
+            .and_then(|&index| self.tracks.get(index))
+            .cloned()
+    }
+
+    /// The track after the current one in play order, without moving on.
+    pub fn peek_next(&self) -> Option<Arc<Track>> {
+        self.current
+            .and_then(|position| self.order.get(position + 1))
+            .and_then(|&index| self.tracks.get(index))
+            .cloned()
+    }
+
+    /// Moves to the next track in play order. A `skip` leaves a track
+    /// repeating on its own.
+    pub fn get_next(&mut self, skip: bool) -> Option<Arc<Track>> {
+        self.current = match self.current {
+            None => None,
+            Some(position) if self.repeat == Repeat::One && !skip => Some(position),
+            Some(position) if position + 1 < self.order.len() => Some(position + 1),
+            Some(_) if self.repeat != Repeat::Off && !self.order.is_empty() => {
+                if self.shuffle {
+                    self.order.shuffle(&mut rand::thread_rng());
+                }
+                Some(0)
+            }
+            Some(_) => None,
+        };
+        self.playing = self.current.is_some();
+
+        self.get_current()
+    }
+
+    pub fn enqueue(&mut self, tracks: Vec<Arc<Track>>) {
+        let start = self.tracks.len();
+        self.tracks.extend(tracks);
+
+        let mut added = (start..self.tracks.len()).collect::<Vec<_>>();
+        if self.shuffle {
+            added.shuffle(&mut rand::thread_rng());
+        }
+        self.order.extend(added);
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        let current = self
+            .current
+            .and_then(|position| self.order.get(position))
+            .copied();
+
+        self.shuffle = shuffle;
+        self.order = (0..self.tracks.len()).collect();
+        self.current = current;
+
+        if shuffle {
+            self.order.shuffle(&mut rand::thread_rng());
+            // keep the current track playing, with everything else after it
+            if let Some(index) = current {
+                if let Some(position) = self.order.iter().position(|&i| i == index) {
+                    self.order.swap(0, position);
+                }
+                self.current = Some(0);
+            }
+        }
+    }
+}
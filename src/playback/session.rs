@@ -0,0 +1,49 @@
+use std::{path::Path, time::Duration};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::queue::Repeat;
+
+const SESSION_FILE: &str = "session.json";
+
+/// Everything needed to pick playback up again after a restart.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub tracks: Vec<String>,
+    pub order: Vec<usize>,
+    pub current: Option<usize>,
+    pub position: Duration,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+    pub volume: f32,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            tracks: Vec::new(),
+            order: Vec::new(),
+            current: None,
+            position: Duration::ZERO,
+            shuffle: false,
+            repeat: Repeat::Off,
+            volume: 1.0,
+        }
+    }
+}
+
+impl Session {
+    pub fn load(dir: &Path) -> Self {
+        storage::load_from(dir, SESSION_FILE)
+    }
+
+    pub fn save(&self, dir: &Path) {
+        if let Err(err) = storage::save_to(dir, SESSION_FILE, self) {
+            error!("Failed to save session: {}", err);
+        }
+    }
+}
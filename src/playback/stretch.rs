@@ -0,0 +1,405 @@
+use std::{
+    collections::VecDeque,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+
+pub const SPEED_RANGE: RangeInclusive<f32> = 0.5..=3.0;
+pub const PITCH_RANGE: RangeInclusive<f32> = -12.0..=12.0;
+
+// WSOLA window sizes, tuned for speech and music alike
+const SEQUENCE_MS: u32 = 40;
+const OVERLAP_MS: u32 = 10;
+const SEEK_MS: u32 = 15;
+
+/// Shared speed and pitch settings, read by the audio thread on every block.
+pub struct StretchControls {
+    speed: AtomicU32,
+    pitch: AtomicU32,
+}
+
+impl Default for StretchControls {
+    fn default() -> Self {
+        Self {
+            speed: AtomicU32::new(1f32.to_bits()),
+            pitch: AtomicU32::new(0f32.to_bits()),
+        }
+    }
+}
+
+impl StretchControls {
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Relaxed))
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        let speed = speed.clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end());
+        self.speed.store(speed.to_bits(), Relaxed);
+    }
+
+    /// Pitch shift in semitones.
+    pub fn pitch(&self) -> f32 {
+        f32::from_bits(self.pitch.load(Relaxed))
+    }
+
+    pub fn set_pitch(&self, semitones: f32) {
+        let semitones = semitones.clamp(*PITCH_RANGE.start(), *PITCH_RANGE.end());
+        self.pitch.store(semitones.to_bits(), Relaxed);
+    }
+
+    fn pitch_ratio(&self) -> f64 {
+        2f64.powf(self.pitch() as f64 / 12.0)
+    }
+
+    /// Time stretch factor applied before resampling, so that the resampler
+    /// raising the pitch does not also change the overall speed.
+    fn tempo(&self) -> f64 {
+        self.speed() as f64 / self.pitch_ratio()
+    }
+
+    fn is_neutral(&self) -> bool {
+        (self.speed() - 1.0).abs() < f32::EPSILON && self.pitch().abs() < f32::EPSILON
+    }
+}
+
+/// A pipeline stage changing playback speed without affecting pitch (WSOLA),
+/// followed by a linear resampler for the independent pitch shift.
+pub struct Stretch<I> {
+    input: I,
+    controls: Arc<StretchControls>,
+    channels: usize,
+    sample_rate: u32,
+    sequence: usize,
+    overlap: usize,
+    seek: usize,
+    input_buf: Vec<f32>,
+    overlap_buf: Vec<f32>,
+    stretched: Vec<f32>,
+    output: VecDeque<f32>,
+    skip_fract: f64,
+    resample_pos: f64,
+    input_done: bool,
+}
+
+impl<I> Stretch<I>
+where
+    I: Source<Item = f32>,
+{
+    pub fn new(input: I, controls: Arc<StretchControls>) -> Self {
+        let channels = input.channels().max(1) as usize;
+        let sample_rate = input.sample_rate();
+        let frames = |ms: u32| (sample_rate as u64 * ms as u64 / 1000) as usize;
+
+        Self {
+            input,
+            controls,
+            channels,
+            sample_rate,
+            sequence: frames(SEQUENCE_MS),
+            overlap: frames(OVERLAP_MS),
+            seek: frames(SEEK_MS),
+            input_buf: Vec::new(),
+            overlap_buf: Vec::new(),
+            stretched: Vec::new(),
+            output: VecDeque::new(),
+            skip_fract: 0.0,
+            resample_pos: 0.0,
+            input_done: false,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.input_buf.is_empty() && self.overlap_buf.is_empty() && self.stretched.is_empty()
+    }
+
+    fn fill_input(&mut self, frames: usize) -> bool {
+        while self.input_buf.len() < frames * self.channels {
+            if self.input_done {
+                return false;
+            }
+            match self.input.next() {
+                Some(sample) => self.input_buf.push(sample),
+                None => self.input_done = true,
+            }
+        }
+        true
+    }
+
+    /// Finds the offset into the input whose start best continues the
+    /// previously emitted overlap, by normalized cross-correlation.
+    fn best_offset(&self) -> usize {
+        if self.overlap_buf.is_empty() {
+            return 0;
+        }
+
+        let mut best = 0;
+        let mut best_score = f32::MIN;
+        for offset in 0..self.seek {
+            let start = offset * self.channels;
+            let window = &self.input_buf[start..start + self.overlap_buf.len()];
+
+            let (mut corr, mut norm) = (0.0, 0.0);
+            for (a, b) in self.overlap_buf.iter().zip(window) {
+                corr += a * b;
+                norm += b * b;
+            }
+
+            let score = corr / norm.sqrt().max(1e-6);
+            if score > best_score {
+                best_score = score;
+                best = offset;
+            }
+        }
+        best
+    }
+
+    fn process(&mut self) {
+        let ch = self.channels;
+
+        if !self.fill_input(self.seek + self.sequence) {
+            // not enough left for a full sequence
+            self.flush();
+            return;
+        }
+
+        let offset = self.best_offset();
+        let ovl = self.overlap;
+        let segment = &self.input_buf[offset * ch..(offset + self.sequence) * ch];
+
+        if self.overlap_buf.is_empty() {
+            self.stretched.extend_from_slice(&segment[..ovl * ch]);
+        } else {
+            for i in 0..ovl {
+                let t = i as f32 / ovl as f32;
+                for c in 0..ch {
+                    let k = i * ch + c;
+                    self.stretched
+                        .push(self.overlap_buf[k] * (1.0 - t) + segment[k] * t);
+                }
+            }
+        }
+        self.stretched
+            .extend_from_slice(&segment[ovl * ch..(self.sequence - ovl) * ch]);
+        self.overlap_buf.clear();
+        self.overlap_buf
+            .extend_from_slice(&segment[(self.sequence - ovl) * ch..]);
+
+        let advance = (self.sequence - ovl) as f64 * self.controls.tempo() + self.skip_fract;
+        let frames = advance.floor() as usize;
+        self.skip_fract = advance - frames as f64;
+
+        self.fill_input(frames);
+        let drained = (frames * ch).min(self.input_buf.len());
+        self.input_buf.drain(..drained);
+
+        self.resample(false);
+    }
+
+    fn flush(&mut self) {
+        self.stretched.append(&mut self.overlap_buf);
+        self.stretched.append(&mut self.input_buf);
+        self.resample(true);
+    }
+
+    fn resample(&mut self, flush: bool) {
+        let ratio = self.controls.pitch_ratio();
+        let ch = self.channels;
+
+        if (ratio - 1.0).abs() < f64::EPSILON {
+            self.output.extend(self.stretched.drain(..));
+            self.resample_pos = 0.0;
+            return;
+        }
+
+        let frames = self.stretched.len() / ch;
+        while self.resample_pos + 1.0 < frames as f64 {
+            let index = self.resample_pos as usize;
+            let t = (self.resample_pos - index as f64) as f32;
+            for c in 0..ch {
+                let a = self.stretched[index * ch + c];
+                let b = self.stretched[(index + 1) * ch + c];
+                self.output.push_back(a + (b - a) * t);
+            }
+            self.resample_pos += ratio;
+        }
+
+        if flush {
+            self.stretched.clear();
+            self.resample_pos = 0.0;
+            return;
+        }
+
+        let consumed = (self.resample_pos as usize).min(frames);
+        self.stretched.drain(..consumed * ch);
+        self.resample_pos -= consumed as f64;
+    }
+}
+
+impl<I> Iterator for Stretch<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if let Some(sample) = self.output.pop_front() {
+                return Some(sample);
+            }
+
+            if self.is_idle() && self.input_done {
+                return None;
+            }
+
+            // skip all processing while neither speed nor pitch is changed
+            if self.controls.is_neutral() {
+                if !self.is_idle() {
+                    self.flush();
+                    continue;
+                }
+                let sample = self.input.next();
+                self.input_done = sample.is_none();
+                return sample;
+            }
+
+            self.process();
+        }
+    }
+}
+
+impl<I> Source for Stretch<I>
+where
+    I: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+
+        self.input_buf.clear();
+        self.overlap_buf.clear();
+        self.stretched.clear();
+        self.output.clear();
+        self.skip_fract = 0.0;
+        self.resample_pos = 0.0;
+        self.input_done = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const RATE: u32 = 44_100;
+    const TONE: f32 = 440.0;
+
+    /// Two seconds of a stereo sine tone.
+    fn tone() -> SamplesBuffer<f32> {
+        let samples = (0..RATE * 2)
+            .flat_map(|frame| {
+                let t = frame as f32 / RATE as f32;
+                let sample = (t * TONE * std::f32::consts::TAU).sin() * 0.5;
+                [sample, sample]
+            })
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(2, RATE, samples)
+    }
+
+    fn stretch(speed: f32, semitones: f32) -> Vec<f32> {
+        let controls = Arc::new(StretchControls::default());
+        controls.set_speed(speed);
+        controls.set_pitch(semitones);
+        Stretch::new(tone(), controls).collect()
+    }
+
+    /// Length in seconds of interleaved stereo samples.
+    fn seconds(samples: &[f32]) -> f32 {
+        samples.len() as f32 / 2.0 / RATE as f32
+    }
+
+    /// Frequency of the tone in interleaved stereo samples, from how often
+    /// the left channel crosses zero.
+    fn frequency(samples: &[f32]) -> f32 {
+        let left = samples.iter().step_by(2).collect::<Vec<_>>();
+        let crossings = left
+            .windows(2)
+            .filter(|pair| (*pair[0] < 0.0) != (*pair[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 / seconds(samples)
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * tolerance,
+            "{} is not near {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn clamps_speed_and_pitch() {
+        let controls = StretchControls::default();
+        assert_eq!(controls.speed(), 1.0);
+        assert_eq!(controls.pitch(), 0.0);
+
+        controls.set_speed(10.0);
+        assert_eq!(controls.speed(), 3.0);
+        controls.set_speed(0.1);
+        assert_eq!(controls.speed(), 0.5);
+        controls.set_pitch(24.0);
+        assert_eq!(controls.pitch(), 12.0);
+        controls.set_pitch(-24.0);
+        assert_eq!(controls.pitch(), -12.0);
+    }
+
+    #[test]
+    fn passes_audio_through_untouched_at_normal_speed_and_pitch() {
+        let input = tone().collect::<Vec<_>>();
+        assert_eq!(stretch(1.0, 0.0), input);
+    }
+
+    #[test]
+    fn changes_speed_without_changing_pitch() {
+        for speed in [0.5, 1.5, 2.0] {
+            let output = stretch(speed, 0.0);
+            assert_near(seconds(&output), 2.0 / speed, 0.05);
+            assert_near(frequency(&output), TONE, 0.05);
+        }
+    }
+
+    #[test]
+    fn changes_pitch_without_changing_speed() {
+        let output = stretch(1.0, 12.0);
+        assert_near(seconds(&output), 2.0, 0.05);
+        assert_near(frequency(&output), TONE * 2.0, 0.05);
+
+        let output = stretch(1.0, -12.0);
+        assert_near(seconds(&output), 2.0, 0.05);
+        assert_near(frequency(&output), TONE / 2.0, 0.05);
+    }
+}
@@ -1,27 +1,87 @@
-use std::sync::Arc;
+use std::{iter, sync::Arc};
 
 use gpui::{
-    div, img, InteractiveElement, ParentElement, Render, Styled, View, VisualContext, WindowContext,
+    div, img, ElementId, InteractiveElement, IntoElement, Model, ParentElement, Render,
+    StatefulInteractiveElement, Styled, View, ViewContext, VisualContext, WindowContext,
 };
 
-use crate::metadata::{
-    library::LibraryModel,
-    track::{self, Track},
+use crate::{
+    events::UiEvent,
+    lyrics::LyricsView,
+    metadata::{
+        cover::{self, CoverSize, CoversModel},
+        database::DatabaseModel,
+        track::rating_buttons,
+    },
+    playback::{output_devices, Playback},
 };
 
+const SPEED_STEP: f32 = 0.1;
+const PITCH_STEP: f32 = 1.0;
+const VOLUME_STEP: f32 = 0.05;
+
 pub struct Playing {
-    current_track: Option<Track>,
+    playback: Model<Playback>,
+    lyrics: View<LyricsView>,
 }
 
 impl Playing {
-    pub fn init(cx: &mut WindowContext, model: LibraryModel) -> View<Self> {
-        let current_track = model.get_tracks(cx).tracks.first().cloned();
-        cx.new_view(|_| Self { current_track })
+    pub fn init(cx: &mut WindowContext, playback: Model<Playback>) -> View<Self> {
+        cx.new_view(|cx| {
+            cx.observe(&playback, |_, _, cx| cx.notify()).detach();
+            let covers = cx.global::<CoversModel>().clone();
+            cx.observe(covers.model(), |_, _, cx| cx.notify()).detach();
+            let database = cx.global::<DatabaseModel>().clone();
+            cx.observe(database.model(), |_, _, cx| cx.notify())
+                .detach();
+
+            let lyrics = LyricsView::new(cx, playback.clone());
+            cx.subscribe(&lyrics, |_, _, event: &Arc<UiEvent>, cx| {
+                cx.emit(Arc::clone(event))
+            })
+            .detach();
+
+            Self { playback, lyrics }
+        })
+    }
+
+    fn render_stepper(
+        &self,
+        id: &str,
+        label: String,
+        down: UiEvent,
+        up: UiEvent,
+        cx: &mut ViewContext<Self>,
+    ) -> impl IntoElement {
+        let down = Arc::new(down);
+        let up = Arc::new(up);
+
+        div()
+            .flex()
+            .flex_1()
+            .justify_between()
+            .items_center()
+            .py_1()
+            .px_3()
+            .child(
+                div()
+                    .id(ElementId::Name(format!("{}-down", id).into()))
+                    .child("-")
+                    .on_click(cx.listener(move |_, _, cx| cx.emit(Arc::clone(&down)))),
+            )
+            .child(label)
+            .child(
+                div()
+                    .id(ElementId::Name(format!("{}-up", id).into()))
+                    .child("+")
+                    .on_click(cx.listener(move |_, _, cx| cx.emit(Arc::clone(&up)))),
+            )
     }
 }
 
 impl Render for Playing {
     fn render(&mut self, cx: &mut gpui::ViewContext<Self>) -> impl gpui::IntoElement {
+        let current = self.playback.read(cx).current();
         let e = div().flex().flex_grow().flex_col().max_w_80().child(
             div()
                 .id("track-info")
@@ -32,32 +92,140 @@ impl Render for Playing {
                 .rounded_b_sm()
                 .child(
                     div().py_1().px_3().children([
-                        // TODO: avoid clone
-                        self.current_track
-                            .clone()
-                            .map_or("-".to_string(), |track| track.title),
-                        self.current_track
-                            .clone()
-                            .map_or("-".to_string(), |track| track.artist),
-                        self.current_track
-                            .clone()
-                            .map_or("-".to_string(), |track| track.album),
+                        current
+                            .as_ref()
+                            .map_or("-".to_string(), |track| track.title.clone()),
+                        current
+                            .as_ref()
+                            .map_or("-".to_string(), |track| track.artist.clone()),
+                        current
+                            .as_ref()
+                            .map_or("-".to_string(), |track| track.album.clone()),
                     ]),
                 ),
         );
 
-        let e = if let Some(track) = self.current_track.clone() {
-            if let Some(cover) = track.cover.clone() {
-                e.child(img(cover).flex_none().w_80().h_80())
-            } else {
-                e
-            }
-        } else {
-            e
+        // the grid thumbnail stands in while the full size cover decodes
+        let cover = current.as_ref().and_then(|track| {
+            cover::cover(track, CoverSize::Full, cx)
+                .or_else(|| cover::cover(track, CoverSize::Grid, cx))
+        });
+        let e = match (cover, &current) {
+            (Some(cover), _) => e.child(img(cover).flex_none().w_80().h_80()),
+            (None, Some(track)) => e.child(cover::placeholder(track, cx).w_80().h_80().text_3xl()),
+            (None, None) => e,
+        };
+        // ratings of the track that is playing
+        let e = match &current {
+            Some(playing) => e.child(div().py_1().px_3().child(rating_buttons(playing, cx))),
+            None => e,
         };
+        let e = e.child(self.lyrics.clone());
+
+        let playback = self.playback.read(cx);
+        let (speed, pitch) = (playback.speed(), playback.pitch());
+        let device = playback.output_device().map(String::from);
+        let preferred = playback.preferred_device().map(String::from);
+        let (shuffle, repeat, volume) = (playback.shuffle(), playback.repeat(), playback.volume());
 
         e.child(
-            div().flex().mt_auto().gap_px().children([
+            div()
+                .flex()
+                .mt_auto()
+                .gap_px()
+                .child(self.render_stepper(
+                    "speed",
+                    format!("{:.2}x", speed),
+                    UiEvent::SpeedChanged(speed - SPEED_STEP),
+                    UiEvent::SpeedChanged(speed + SPEED_STEP),
+                    cx,
+                ))
+                .child(self.render_stepper(
+                    "pitch",
+                    format!("{:+} st", pitch),
+                    UiEvent::PitchChanged(pitch - PITCH_STEP),
+                    UiEvent::PitchChanged(pitch + PITCH_STEP),
+                    cx,
+                )),
+        )
+        .child(
+            div()
+                .flex()
+                .gap_px()
+                .child(
+                    div()
+                        .id("shuffle")
+                        .flex_1()
+                        .py_1()
+                        .px_3()
+                        .flex()
+                        .justify_center()
+                        .child(if shuffle {
+                            "Shuffle: On"
+                        } else {
+                            "Shuffle: Off"
+                        })
+                        .on_click(
+                            cx.listener(|_, _, cx| cx.emit(Arc::new(UiEvent::ShuffleToggled))),
+                        ),
+                )
+                .child(
+                    div()
+                        .id("repeat")
+                        .flex_1()
+                        .py_1()
+                        .px_3()
+                        .flex()
+                        .justify_center()
+                        .child(format!("Repeat: {:?}", repeat))
+                        .on_click(
+                            cx.listener(|_, _, cx| cx.emit(Arc::new(UiEvent::RepeatToggled))),
+                        ),
+                )
+                .child(self.render_stepper(
+                    "volume",
+                    format!("{:.0}%", volume * 100.),
+                    UiEvent::VolumeChanged(volume - VOLUME_STEP),
+                    UiEvent::VolumeChanged(volume + VOLUME_STEP),
+                    cx,
+                )),
+        )
+        .child(
+            div()
+                .id("output-device")
+                .py_1()
+                .px_3()
+                .text_sm()
+                .child(match (&preferred, &device) {
+                    (_, None) => "No output".to_string(),
+                    (None, Some(device)) => format!("Default ({})", device),
+                    (Some(_), Some(device)) => device.clone(),
+                })
+                .on_click(cx.listener(move |_, _, cx| {
+                    // cycle through the system default and then every device
+                    let choices = iter::once(None)
+                        .chain(output_devices().into_iter().map(Some))
+                        .collect::<Vec<_>>();
+                    let next = choices
+                        .iter()
+                        .position(|choice| *choice == preferred)
+                        .map_or(0, |i| (i + 1) % choices.len());
+                    cx.emit(Arc::new(UiEvent::OutputDeviceSelected(
+                        choices[next].clone(),
+                    )));
+                })),
+        )
+        .child(
+            div()
+                .id("export-queue")
+                .py_1()
+                .px_3()
+                .text_sm()
+                .child("Export queue")
+                .on_click(cx.listener(|_, _, cx| cx.emit(Arc::new(UiEvent::ExportQueueClicked)))),
+        )
+        .child(
+            div().flex().gap_px().children([
                 div()
                     .id("pause")
                     .flex_1()
@@ -0,0 +1,666 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use gpui::{
+    div, px, uniform_list, AnyElement, AppContext, ElementId, InteractiveElement, IntoElement,
+    ParentElement, PathPromptOptions, Render, SharedString, StatefulInteractiveElement, Styled,
+    Task, View, ViewContext, VisualContext, WindowContext,
+};
+use log::error;
+
+use crate::{
+    events::{InputEvent, UiEvent},
+    input::TextInput,
+    metadata::{
+        database::DatabaseModel,
+        library::LibraryModel,
+        playlist::{Playlist, PlaylistsModel},
+        playlist_file,
+        smart_playlist::SmartPlaylist,
+        track::Track,
+    },
+    theme::Theme,
+};
+
+const SMART_LIMIT_STEP: usize = 10;
+
+pub struct PlaylistsView {
+    playlists: PlaylistsModel,
+    library: LibraryModel,
+    database: DatabaseModel,
+    selected: Option<u64>,
+    // entries of the selected playlist, `None` where the file could not be found
+    entries: Vec<(String, Option<Arc<Track>>)>,
+    name_input: View<TextInput>,
+    query_input: View<TextInput>,
+    query_error: Option<String>,
+    // summary of the last import or export and the entries it could not
+    // match or left out
+    report: Option<(String, Vec<String>)>,
+}
+
+/// Asks where to save `tracks` and writes them out as a playlist file, the
+/// format picked from the extension. Resolves to the tracks left out for
+/// having no file, `None` when nothing was written.
+pub fn export(
+    name: String,
+    tracks: Vec<Arc<Track>>,
+    cx: &mut WindowContext,
+) -> Task<Option<Vec<String>>> {
+    let directory = dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+    let path = cx.prompt_for_new_path(&directory);
+
+    cx.spawn(|_| async move {
+        let Ok(Some(path)) = path.await else {
+            return None;
+        };
+        match playlist_file::export(&path, &name, &tracks) {
+            Ok(skipped) => Some(skipped),
+            Err(err) => {
+                error!("Failed to export playlist to {}: {}", path.display(), err);
+                None
+            }
+        }
+    })
+}
+
+impl PlaylistsView {
+    pub fn new(
+        cx: &mut WindowContext,
+        playlists: &PlaylistsModel,
+        library: &LibraryModel,
+        database: &DatabaseModel,
+    ) -> View<Self> {
+        let name_input = TextInput::new(cx, "Playlist name");
+        let query_input = TextInput::new(cx, "Query, e.g. genre:jazz year:<1970");
+
+        cx.new_view(|cx| {
+            // smart playlists follow the library and play statistics
+            cx.observe(playlists.model(), |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+            cx.observe(library.model(), |this: &mut Self, _, cx| this.refresh(cx))
+                .detach();
+            cx.observe(database.model(), |this: &mut Self, _, cx| {
+                if this.is_smart(cx) {
+                    this.refresh(cx);
+                }
+            })
+            .detach();
+            cx.subscribe(&name_input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Submitted(name) = event {
+                    this.rename(name.clone(), cx);
+                }
+            })
+            .detach();
+            cx.subscribe(
+                &query_input,
+                |this: &mut Self, _, event: &InputEvent, cx| {
+                    if let InputEvent::Submitted(query) = event {
+                        this.set_query(query.clone(), cx);
+                    }
+                },
+            )
+            .detach();
+
+            Self {
+                playlists: playlists.clone(),
+                library: library.clone(),
+                database: database.clone(),
+                selected: None,
+                entries: Vec::new(),
+                name_input,
+                query_input,
+                query_error: None,
+                report: None,
+            }
+        })
+    }
+
+    fn select(&mut self, id: Option<u64>, cx: &mut ViewContext<Self>) {
+        self.selected = id;
+        // tracks can only be added to normal playlists
+        self.playlists.set_target(id, cx);
+
+        let name = self.name(cx).unwrap_or_default();
+        self.name_input
+            .update(cx, |input, cx| input.set_text(name, cx));
+
+        let smart = self.smart(cx);
+        let query = smart.map(|playlist| playlist.query.clone());
+        self.query_error = smart
+            .and_then(|playlist| playlist.unconverted.as_ref())
+            .map(|rules| format!("Old rules without a query equivalent: {}", rules));
+        self.query_input.update(cx, |input, cx| {
+            input.set_text(query.unwrap_or_default(), cx)
+        });
+
+        self.refresh(cx);
+    }
+
+    fn name(&self, cx: &AppContext) -> Option<String> {
+        let playlists = self.playlists.read(cx);
+        let id = self.selected?;
+        playlists
+            .get(id)
+            .map(|playlist| playlist.name.clone())
+            .or_else(|| {
+                playlists
+                    .get_smart(id)
+                    .map(|playlist| playlist.name.clone())
+            })
+    }
+
+    fn smart<'a>(&self, cx: &'a AppContext) -> Option<&'a SmartPlaylist> {
+        self.playlists.read(cx).get_smart(self.selected?)
+    }
+
+    fn is_smart(&self, cx: &AppContext) -> bool {
+        self.smart(cx).is_some()
+    }
+
+    fn refresh(&mut self, cx: &mut ViewContext<Self>) {
+        if let Some(smart) = self.smart(cx).cloned() {
+            let tracks = smart.evaluate(self.library.read(cx), self.database.read(cx));
+            self.entries = tracks
+                .into_iter()
+                .map(|track| (track.file.clone().unwrap_or_default(), Some(track)))
+                .collect();
+            cx.notify();
+            return;
+        }
+
+        let playlists = self.playlists.read(cx);
+        let files = match self.selected.and_then(|id| playlists.get(id)) {
+            Some(playlist) => playlist.tracks.clone(),
+            None => {
+                self.selected = None;
+                Vec::new()
+            }
+        };
+
+        let tracks = self.library.find_tracks(&files, cx);
+        self.entries = files.into_iter().zip(tracks).collect();
+        cx.notify();
+    }
+
+    fn create(&mut self, cx: &mut ViewContext<Self>) {
+        let id = self.playlists.update(cx, |playlists| {
+            playlists.create(format!("Playlist {}", playlists.playlists.len() + 1))
+        });
+        self.select(Some(id), cx);
+    }
+
+    fn create_smart(&mut self, cx: &mut ViewContext<Self>) {
+        let id = self.playlists.update(cx, |playlists| {
+            playlists.create_smart(format!("Smart Playlist {}", playlists.smart.len() + 1))
+        });
+        self.select(Some(id), cx);
+    }
+
+    fn set_query(&mut self, query: String, cx: &mut ViewContext<Self>) {
+        let mut result = Ok(());
+        self.edit_smart(cx, |playlist| result = playlist.set_query(&query));
+        self.query_error = result.err().map(|err| err.to_string());
+        cx.notify();
+    }
+
+    fn rename(&mut self, name: String, cx: &mut ViewContext<Self>) {
+        let name = name.trim();
+        if let Some(id) = self.selected.filter(|_| !name.is_empty()) {
+            self.playlists
+                .update(cx, |playlists| playlists.rename(id, name));
+        }
+    }
+
+    fn duplicate(&mut self, cx: &mut ViewContext<Self>) {
+        if let Some(id) = self.selected {
+            let copy = self
+                .playlists
+                .update(cx, |playlists| playlists.duplicate(id));
+            self.select(copy, cx);
+        }
+    }
+
+    fn delete(&mut self, cx: &mut ViewContext<Self>) {
+        if let Some(id) = self.selected {
+            self.playlists.update(cx, |playlists| playlists.delete(id));
+            self.select(None, cx);
+        }
+    }
+
+    fn import(&mut self, cx: &mut ViewContext<Self>) {
+        let paths = cx.prompt_for_paths(PathPromptOptions {
+            files: true,
+            directories: false,
+            multiple: true,
+        });
+
+        cx.spawn(|this, mut cx| async move {
+            let Ok(Some(paths)) = paths.await else {
+                return;
+            };
+            this.update(&mut cx, |this, cx| {
+                for path in paths {
+                    this.import_file(&path, cx);
+                }
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn import_file(&mut self, path: &Path, cx: &mut ViewContext<Self>) {
+        let files = self.library.files(cx);
+        let report = match playlist_file::import(path, &files) {
+            Ok(report) => report,
+            Err(err) => {
+                error!("Failed to import {}: {}", path.display(), err);
+                self.report = Some((format!("Could not import {}", path.display()), vec![]));
+                cx.notify();
+                return;
+            }
+        };
+
+        let id = self.playlists.update(cx, |playlists| {
+            let id = playlists.create(report.name.clone());
+            if let Some(playlist) = playlists.get_mut(id) {
+                playlist.add(report.matched.clone());
+            }
+            id
+        });
+
+        self.report = Some((
+            format!(
+                "Imported {} tracks into \"{}\", {} not found",
+                report.matched.len(),
+                report.name,
+                report.unmatched.len()
+            ),
+            report.unmatched,
+        ));
+        self.select(Some(id), cx);
+    }
+
+    fn export(&mut self, cx: &mut ViewContext<Self>) {
+        let name = self.name(cx).unwrap_or_default();
+        let missing = self
+            .entries
+            .iter()
+            .filter(|(_, track)| track.is_none())
+            .map(|(file, _)| file.clone())
+            .collect::<Vec<_>>();
+        let exported = export(name.clone(), self.tracks(), cx);
+
+        cx.spawn(|this, mut cx| async move {
+            let Some(skipped) = exported.await else {
+                return;
+            };
+            let left_out = missing.into_iter().chain(skipped).collect::<Vec<_>>();
+            if left_out.is_empty() {
+                return;
+            }
+            this.update(&mut cx, |this, cx| {
+                this.report = Some((
+                    format!(
+                        "Exported \"{}\", {} entries not found or cut from a cue sheet were left out",
+                        name,
+                        left_out.len()
+                    ),
+                    left_out,
+                ));
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn tracks(&self) -> Vec<Arc<Track>> {
+        self.entries
+            .iter()
+            .filter_map(|(_, track)| track.clone())
+            .collect()
+    }
+
+    fn edit(&mut self, cx: &mut ViewContext<Self>, f: impl FnOnce(&mut Playlist)) {
+        if let Some(id) = self.selected {
+            self.playlists.update(cx, |playlists| {
+                if let Some(playlist) = playlists.get_mut(id) {
+                    f(playlist);
+                }
+            });
+        }
+    }
+
+    fn edit_smart(&mut self, cx: &mut ViewContext<Self>, f: impl FnOnce(&mut SmartPlaylist)) {
+        if let Some(id) = self.selected {
+            self.playlists.update(cx, |playlists| {
+                if let Some(playlist) = playlists.get_smart_mut(id) {
+                    f(playlist);
+                    playlist.touch();
+                }
+            });
+        }
+    }
+
+    fn button(
+        &self,
+        id: impl Into<SharedString>,
+        label: impl Into<SharedString>,
+        cx: &mut ViewContext<Self>,
+        on_click: impl Fn(&mut Self, &mut ViewContext<Self>) + 'static,
+    ) -> impl IntoElement {
+        div()
+            .id(ElementId::Name(id.into()))
+            .px_2()
+            .child(label.into())
+            .on_click(cx.listener(move |this, _, cx| on_click(this, cx)))
+    }
+
+    fn render_entries(
+        &mut self,
+        rows: Range<usize>,
+        cx: &mut ViewContext<Self>,
+    ) -> Vec<AnyElement> {
+        let theme = cx.global::<Theme>();
+        let (active, missing) = (theme.text, theme.overlay0);
+        let smart = self.is_smart(cx);
+
+        rows.filter_map(|ix| {
+            let (file, track) = self.entries.get(ix)?;
+            let (label, color) = match track {
+                Some(track) => (format!("{} - {}", track.title, track.artist), active),
+                None => (format!("Missing: {}", file), missing),
+            };
+            let e = div()
+                .flex()
+                .items_center()
+                .gap_1()
+                .h_6()
+                .px_3()
+                .text_color(color)
+                .child(
+                    div()
+                        .flex_grow()
+                        .overflow_hidden()
+                        .whitespace_nowrap()
+                        .child(label),
+                );
+            // smart playlists are edited through their query
+            if smart {
+                return Some(e.into_any_element());
+            }
+
+            let e = e
+                .child(
+                    self.button(format!("entry-up-{}", ix), "Up", cx, move |this, cx| {
+                        this.edit(cx, |playlist| playlist.move_entry(ix, ix.saturating_sub(1)))
+                    }),
+                )
+                .child(
+                    self.button(format!("entry-down-{}", ix), "Down", cx, move |this, cx| {
+                        this.edit(cx, |playlist| playlist.move_entry(ix, ix + 1))
+                    }),
+                )
+                .child(self.button(
+                    format!("entry-remove-{}", ix),
+                    "Remove",
+                    cx,
+                    move |this, cx| this.edit(cx, |playlist| playlist.remove(ix)),
+                ));
+            Some(e.into_any_element())
+        })
+        .collect()
+    }
+}
+
+impl Render for PlaylistsView {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (active, inactive, missing, invalid) =
+            (theme.text, theme.subtext0, theme.overlay0, theme.red);
+        // only the entries in view are laid out
+        let entries = uniform_list(
+            cx.view().clone(),
+            "playlist-entries",
+            self.entries.len(),
+            Self::render_entries,
+        )
+        .flex_grow();
+
+        let playlists = self.playlists.read(cx);
+        let names = playlists
+            .playlists
+            .iter()
+            .map(|playlist| (playlist.id, playlist.name.clone()))
+            .chain(
+                playlists
+                    .smart
+                    .iter()
+                    .map(|playlist| (playlist.id, format!("{} (smart)", playlist.name))),
+            )
+            .collect::<Vec<_>>();
+        let smart = self
+            .smart(cx)
+            .map(|playlist| (playlist.sort, playlist.limit));
+
+        let list = div()
+            .flex()
+            .flex_wrap()
+            .gap_3()
+            .px_3()
+            .py_1()
+            .child(self.button("new-playlist", "New", cx, Self::create))
+            .child(self.button("new-smart-playlist", "New Smart", cx, Self::create_smart))
+            .child(self.button("import-playlist", "Import", cx, Self::import))
+            .children(names.into_iter().map(|(id, name)| {
+                div()
+                    .id(ElementId::Name(format!("playlist-{}", id).into()))
+                    .text_color(if self.selected == Some(id) {
+                        active
+                    } else {
+                        inactive
+                    })
+                    .child(name)
+                    .on_click(cx.listener(move |this, _, cx| this.select(Some(id), cx)))
+            }));
+
+        let report = self.report.clone().map(|(summary, unmatched)| {
+            div()
+                .flex()
+                .flex_col()
+                .px_3()
+                .py_1()
+                .child(
+                    div()
+                        .flex()
+                        .gap_1()
+                        .child(div().flex_grow().child(summary))
+                        .child(self.button("dismiss-import", "Dismiss", cx, |this, cx| {
+                            this.report = None;
+                            cx.notify();
+                        })),
+                )
+                .children(
+                    unmatched
+                        .into_iter()
+                        .map(|location| div().text_color(missing).child(location)),
+                )
+        });
+
+        let e = div()
+            .flex()
+            .flex_col()
+            .size_full()
+            .gap(px(1.))
+            .child(list)
+            .children(report);
+        if self.selected.is_none() {
+            return e;
+        }
+
+        let e = e.child(
+            div()
+                .flex()
+                .items_center()
+                .gap_1()
+                .px_3()
+                .py_1()
+                .child(self.name_input.clone())
+                .child(self.button("play-playlist", "Play", cx, |this, cx| {
+                    cx.emit(Arc::new(UiEvent::PlayTracks(this.tracks())))
+                }))
+                .child(self.button("enqueue-playlist", "Enqueue", cx, |this, cx| {
+                    cx.emit(Arc::new(UiEvent::EnqueueTracks(this.tracks())))
+                }))
+                .child(self.button("export-playlist", "Export", cx, Self::export))
+                .child(self.button("duplicate-playlist", "Duplicate", cx, Self::duplicate))
+                .child(self.button("delete-playlist", "Delete", cx, Self::delete)),
+        );
+
+        if let Some((sort, limit)) = smart {
+            let limit_label = limit.map_or("No limit".to_string(), |n| format!("Limit {}", n));
+            return e
+                .child(
+                    div()
+                        .flex()
+                        .items_center()
+                        .gap_1()
+                        .px_3()
+                        .py_1()
+                        .child(self.query_input.clone())
+                        .child(self.button(
+                            "smart-sort",
+                            format!("Sort: {}", sort.label()),
+                            cx,
+                            |this, cx| {
+                                this.edit_smart(cx, |playlist| {
+                                    playlist.sort = playlist.sort.cycle()
+                                })
+                            },
+                        ))
+                        .child(self.button("smart-limit-down", "-", cx, |this, cx| {
+                            this.edit_smart(cx, |playlist| {
+                                playlist.limit = playlist
+                                    .limit
+                                    .and_then(|n| n.checked_sub(SMART_LIMIT_STEP))
+                                    .filter(|&n| n > 0)
+                            })
+                        }))
+                        .child(limit_label)
+                        .child(self.button("smart-limit-up", "+", cx, |this, cx| {
+                            this.edit_smart(cx, |playlist| {
+                                playlist.limit =
+                                    Some(playlist.limit.unwrap_or(0) + SMART_LIMIT_STEP)
+                            })
+                        })),
+                )
+                .children(
+                    self.query_error
+                        .clone()
+                        .map(|err| div().px_3().text_color(invalid).child(err)),
+                )
+                .child(entries);
+        }
+
+        e.child(entries)
+    }
+}
+
+/// Asks for the name of a playlist to start with tracks added while there
+/// was no playlist to add them to.
+pub struct NewPlaylist {
+    playlists: PlaylistsModel,
+    files: Vec<String>,
+    name_input: View<TextInput>,
+}
+
+impl NewPlaylist {
+    pub fn new(
+        cx: &mut WindowContext,
+        playlists: &PlaylistsModel,
+        files: Vec<String>,
+    ) -> View<Self> {
+        let name_input = TextInput::new(cx, "Playlist name");
+
+        cx.new_view(|cx| {
+            cx.subscribe(&name_input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Submitted(name) = event {
+                    this.create(name, cx);
+                }
+            })
+            .detach();
+
+            Self {
+                playlists: playlists.clone(),
+                files,
+                name_input,
+            }
+        })
+    }
+
+    /// Creates the playlist and makes it the one tracks are added to.
+    fn create(&mut self, name: &str, cx: &mut ViewContext<Self>) {
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+
+        let files = std::mem::take(&mut self.files);
+        self.playlists.update(cx, |playlists| {
+            let id = playlists.create(name);
+            if let Some(playlist) = playlists.get_mut(id) {
+                playlist.add(files);
+            }
+            playlists.target = Some(id);
+        });
+        cx.emit(Arc::new(UiEvent::DialogClosed));
+    }
+}
+
+impl Render for NewPlaylist {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (background, text) = (theme.base, theme.text);
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .w(px(360.))
+            .p_3()
+            .rounded_md()
+            .bg(background)
+            .text_color(text)
+            .child("New playlist")
+            .child(self.name_input.clone())
+            .child(
+                div()
+                    .flex()
+                    .justify_end()
+                    .gap_2()
+                    .child(
+                        div()
+                            .id("cancel-new-playlist")
+                            .px_2()
+                            .child("Cancel")
+                            .on_click(
+                                cx.listener(|_, _, cx| cx.emit(Arc::new(UiEvent::DialogClosed))),
+                            ),
+                    )
+                    .child(
+                        div()
+                            .id("create-new-playlist")
+                            .px_2()
+                            .child("Create")
+                            .on_click(cx.listener(|this, _, cx| {
+                                let name = this.name_input.read(cx).text().to_string();
+                                this.create(&name, cx);
+                            })),
+                    ),
+            )
+    }
+}
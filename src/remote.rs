@@ -0,0 +1,529 @@
+//! Remote control of the running player over a Unix socket, one JSON
+//! request per line, each answered by one JSON response line. `ggmusic ctl`
+//! is the client.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use gpui::{Context, Global, Model, ModelContext, WindowContext};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::{library::LibraryModel, track::Track},
+    playback::{Playback, Repeat},
+    storage,
+};
+
+const SOCKET_FILE: &str = "ggmusic.sock";
+// clients served at once, each on its own thread
+const MAX_CLIENTS: usize = 16;
+// a client that sends nothing for this long is hung up on, freeing its place
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+// how long `ggmusic ctl` waits for the player to take and answer a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the socket lives: the runtime dir, which is private to the user and
+/// cleared on logout, or the data dir where there is none.
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(storage::data_dir)
+        .join(SOCKET_FILE)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Plays `paths` in place of the queue, or resumes when there are none.
+    Play {
+        #[serde(default)]
+        paths: Vec<String>,
+    },
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    /// Goes to `seconds` into the track, or that far from where it is when
+    /// `relative`.
+    Seek {
+        seconds: f64,
+        #[serde(default)]
+        relative: bool,
+    },
+    Enqueue {
+        paths: Vec<String>,
+    },
+    Status,
+    Queue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub file: Option<String>,
+    /// In seconds.
+    pub duration: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub playing: bool,
+    pub track: Option<Entry>,
+    /// Where the track is in the queue, in play order.
+    pub index: Option<usize>,
+    /// In seconds.
+    pub position: f64,
+    pub volume: f32,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<Vec<Entry>>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Self::default()
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            error: Some(message.into()),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&Track> for Entry {
+    fn from(track: &Track) -> Self {
+        Self {
+            artist: track.artist.clone(),
+            title: track.title.clone(),
+            album: track.album.clone(),
+            file: track.file.clone(),
+            duration: track.duration().as_secs_f64(),
+        }
+    }
+}
+
+/// Connects to the socket, giving up after `REPLY_TIMEOUT` when the player
+/// is too busy to take the connection.
+fn connect(path: PathBuf) -> Result<UnixStream> {
+    let (connected, connecting) = mpsc::channel();
+    // left to finish on its own after a timeout, as the client exits anyway
+    thread::spawn(move || {
+        let _ = connected.send(UnixStream::connect(path));
+    });
+    let stream = connecting
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| anyhow!("timed out connecting to the player"))??;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Sends `request` to the running player and waits for its answer.
+pub fn send(request: &Request) -> Result<Response> {
+    let mut stream = connect(socket_path())?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    Ok(serde_json::from_str(&answer)?)
+}
+
+type Pending = (Request, mpsc::Sender<Response>);
+
+/// Binds the socket, unless another player is already listening on it.
+fn bind() -> Result<Option<UnixListener>> {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        return Ok(None);
+    }
+    // left behind by a player that did not shut down cleanly
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(Some(UnixListener::bind(&path)?))
+}
+
+fn respond(writer: &mut UnixStream, response: &Response) -> Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads requests off `stream` and writes back the answers, until the
+/// client hangs up or goes quiet for `CLIENT_TIMEOUT`.
+fn serve(stream: UnixStream, requests: UnboundedSender<Pending>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(());
+            }
+            line => line?,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (answer, answered) = mpsc::channel();
+                if requests.unbounded_send((request, answer)).is_err() {
+                    return Ok(());
+                }
+                answered
+                    .recv()
+                    .unwrap_or_else(|_| Response::error("the player is closing"))
+            }
+            Err(err) => Response::error(format!("bad request: {}", err)),
+        };
+        respond(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+/// Hands every client to a thread of its own, turning away those past
+/// `MAX_CLIENTS`.
+fn accept(listener: UnixListener, requests: UnboundedSender<Pending>) {
+    let clients = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept a remote control client: {}", err);
+                continue;
+            }
+        };
+        if clients.fetch_add(1, SeqCst) >= MAX_CLIENTS {
+            clients.fetch_sub(1, SeqCst);
+            let _ = respond(&mut stream, &Response::error("too many clients"));
+            continue;
+        }
+
+        let requests = requests.clone();
+        let clients = Arc::clone(&clients);
+        thread::spawn(move || {
+            if let Err(err) = serve(stream, requests) {
+                warn!("Remote control client failed: {}", err);
+            }
+            clients.fetch_sub(1, SeqCst);
+        });
+    }
+}
+
+/// Answers requests from the socket with what playback does.
+pub struct Remote {
+    playback: Model<Playback>,
+}
+
+struct RemoteModel(Model<Remote>);
+
+impl Global for RemoteModel {}
+
+impl Remote {
+    pub fn init(cx: &mut WindowContext, playback: &Model<Playback>) {
+        let listener = match bind() {
+            Ok(Some(listener)) => listener,
+            Ok(None) => {
+                warn!("Another player is listening on the remote control socket");
+                return;
+            }
+            Err(err) => {
+                error!("Failed to open the remote control socket: {}", err);
+                return;
+            }
+        };
+
+        let (requests, mut received) = futures::channel::mpsc::unbounded::<Pending>();
+        thread::spawn(move || accept(listener, requests));
+
+        let model = cx.new_model(|cx| {
+            // wakes up only when a client sends something
+            cx.spawn(|this, mut cx| async move {
+                while let Some((request, answer)) = received.next().await {
+                    let ran = this.update(&mut cx, |this, cx| {
+                        let response = this.run(request, cx);
+                        // the client may have hung up already
+                        let _ = answer.send(response);
+                    });
+                    if ran.is_err() {
+                        break;
+                    }
+                }
+            })
+            .detach();
+
+            Self {
+                playback: playback.clone(),
+            }
+        });
+        cx.set_global(RemoteModel(model));
+    }
+
+    fn run(&mut self, request: Request, cx: &mut ModelContext<Self>) -> Response {
+        let tracks = match &request {
+            Request::Play { paths } | Request::Enqueue { paths } => match find_tracks(paths, cx) {
+                Ok(tracks) => tracks,
+                Err(err) => return Response::error(err),
+            },
+            _ => Vec::new(),
+        };
+
+        self.playback.update(cx, |playback, cx| match request {
+            Request::Play { .. } if tracks.is_empty() => {
+                playback.resume(cx);
+                Response::ok()
+            }
+            Request::Play { .. } => {
+                playback.play_tracks(tracks, cx);
+                Response::ok()
+            }
+            Request::Enqueue { .. } => {
+                playback.enqueue(tracks, cx);
+                Response::ok()
+            }
+            Request::Pause => {
+                playback.pause(cx);
+                Response::ok()
+            }
+            Request::Toggle => {
+                if playback.is_playing() {
+                    playback.pause(cx);
+                } else {
+                    playback.resume(cx);
+                }
+                Response::ok()
+            }
+            Request::Stop => {
+                playback.stop(cx);
+                Response::ok()
+            }
+            Request::Next => {
+                playback.next(cx);
+                Response::ok()
+            }
+            Request::Previous => {
+                playback.previous(cx);
+                Response::ok()
+            }
+            Request::Seek { seconds, relative } => {
+                let Some(track) = playback.current() else {
+                    return Response::error("nothing is playing");
+                };
+                let from = if relative {
+                    playback.position().as_secs_f64()
+                } else {
+                    0.0
+                };
+                let Ok(position) = Duration::try_from_secs_f64((from + seconds).max(0.0)) else {
+                    return Response::error(format!("bad request: cannot seek to {}", seconds));
+                };
+                // seeking past the end moves on, like the end of the track would
+                if position >= track.duration() {
+                    playback.next(cx);
+                } else {
+                    playback.seek(position, cx);
+                }
+                Response::ok()
+            }
+            Request::Status => Response {
+                status: Some(Status {
+                    playing: playback.is_playing(),
+                    track: playback.current().map(|track| Entry::from(&*track)),
+                    index: playback.current_position(),
+                    position: playback.position().as_secs_f64(),
+                    volume: playback.volume(),
+                    shuffle: playback.shuffle(),
+                    repeat: playback.repeat(),
+                }),
+                ..Response::ok()
+            },
+            Request::Queue => Response {
+                queue: Some(
+                    playback
+                        .queue()
+                        .iter()
+                        .map(|track| Entry::from(&**track))
+                        .collect(),
+                ),
+                ..Response::ok()
+            },
+        })
+    }
+}
+
+/// The library tracks of `paths`, or an error naming those not in it.
+fn find_tracks(paths: &[String], cx: &ModelContext<Remote>) -> Result<Vec<Arc<Track>>, String> {
+    let library = cx.global::<LibraryModel>();
+    let mut missing = Vec::new();
+    let tracks = paths
+        .iter()
+        .filter_map(|path| {
+            let track = library.find_track(path, cx);
+            if track.is_none() {
+                missing.push(path.as_str());
+            }
+            track
+        })
+        .collect();
+    if missing.is_empty() {
+        Ok(tracks)
+    } else {
+        Err(format!("not in the library: {}", missing.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(value: serde_json::Value) -> Request {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn reads_requests_with_their_defaults() {
+        assert!(matches!(
+            request(json!({"command": "play"})),
+            Request::Play { paths } if paths.is_empty()
+        ));
+        assert!(matches!(
+            request(json!({"command": "enqueue", "paths": ["/music/a.flac"]})),
+            Request::Enqueue { paths } if paths == ["/music/a.flac"]
+        ));
+        assert!(matches!(
+            request(json!({"command": "seek", "seconds": 30})),
+            Request::Seek { seconds, relative: false } if seconds == 30.0
+        ));
+        assert!(matches!(
+            request(json!({"command": "seek", "seconds": -5.5, "relative": true})),
+            Request::Seek { seconds, relative: true } if seconds == -5.5
+        ));
+        assert!(matches!(
+            request(json!({"command": "previous"})),
+            Request::Previous
+        ));
+
+        assert!(serde_json::from_value::<Request>(json!({"command": "rewind"})).is_err());
+        assert!(serde_json::from_value::<Request>(json!({"command": "enqueue"})).is_err());
+        assert!(serde_json::from_value::<Request>(json!({"command": "seek"})).is_err());
+    }
+
+    #[test]
+    fn writes_requests_as_clients_send_them() {
+        let line = serde_json::to_value(Request::Seek {
+            seconds: 10.0,
+            relative: true,
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            json!({"command": "seek", "seconds": 10.0, "relative": true})
+        );
+        let line = serde_json::to_value(Request::Status).unwrap();
+        assert_eq!(line, json!({"command": "status"}));
+    }
+
+    #[test]
+    fn leaves_empty_parts_out_of_responses() {
+        let value = |response: &Response| serde_json::to_value(response).unwrap();
+        assert_eq!(value(&Response::ok()), json!({"ok": true}));
+        assert_eq!(
+            value(&Response::error("nothing is playing")),
+            json!({"ok": false, "error": "nothing is playing"})
+        );
+
+        let response = Response {
+            status: Some(Status {
+                playing: true,
+                track: Some(Entry {
+                    artist: "Artist".to_string(),
+                    title: "Title".to_string(),
+                    album: "Album".to_string(),
+                    file: Some("/music/a.flac".to_string()),
+                    duration: 61.5,
+                }),
+                index: Some(2),
+                position: 12.0,
+                volume: 0.5,
+                shuffle: false,
+                repeat: Repeat::All,
+            }),
+            ..Response::ok()
+        };
+        let read: Response = serde_json::from_value(value(&response)).unwrap();
+        assert!(read.ok && read.error.is_none() && read.queue.is_none());
+        let status = read.status.unwrap();
+        assert_eq!(status.index, Some(2));
+        assert_eq!(status.repeat, Repeat::All);
+        assert_eq!(status.track.unwrap().file.as_deref(), Some("/music/a.flac"));
+    }
+
+    #[test]
+    fn answers_every_line_a_client_sends() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (requests, mut received) = futures::channel::mpsc::unbounded::<Pending>();
+        thread::spawn(move || serve(server, requests));
+        // stands in for the player
+        thread::spawn(move || {
+            while let Some((request, answer)) = futures::executor::block_on(received.next()) {
+                let response = match request {
+                    Request::Status => Response::ok(),
+                    _ => Response::error("unexpected"),
+                };
+                answer.send(response).unwrap();
+            }
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        writer
+            .write_all(b"{\"command\": \"status\"}\n\nnot json\n")
+            .unwrap();
+        let mut lines = BufReader::new(client).lines();
+        let mut answer =
+            || -> Response { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+        assert!(answer().ok);
+        let bad = answer();
+        assert!(!bad.ok);
+        assert!(bad.error.unwrap().starts_with("bad request: "));
+    }
+}
@@ -1,57 +1,234 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use gpui::{
-    div, px, FontWeight, Model, ParentElement, Render, Styled, View, ViewContext, VisualContext,
-    WindowContext,
+    div, prelude::FluentBuilder, px, AnyView, FontWeight, InteractiveElement, Model, ParentElement,
+    Render, Styled, View, ViewContext, VisualContext,
 };
+use log::error;
 
 use crate::{
-    browse::Browse, events::UiEvent, metadata::library::LibraryModel, playback::Playback,
-    playing::Playing, theme::Theme,
+    browse::Browse,
+    duplicates::Duplicates,
+    events::{PlaybackEvent, UiEvent},
+    metadata::{
+        cover::CoversModel, database::DatabaseModel, fingerprint::FingerprintsModel,
+        library::LibraryModel, playlist::PlaylistsModel, tags, track::Track,
+    },
+    naming::NamingTool,
+    organizer::Organizer,
+    playback::Playback,
+    playing::Playing,
+    playlists::{self, NewPlaylist},
+    settings::Settings,
+    tag_editor::TagEditor,
+    theme::Theme,
 };
 
 pub struct Root {
     browse: View<Browse>,
     playback: Model<Playback>,
     playing: View<Playing>,
+    playlists: PlaylistsModel,
+    // shown over everything else while open
+    dialog: Option<AnyView>,
     // memu: View<Menu>,
 }
 
 impl Root {
-    pub fn new(cx: &mut WindowContext) -> Self {
+    pub fn new(cx: &mut ViewContext<Self>) -> Self {
         let library = LibraryModel::init(cx);
+        CoversModel::init(cx);
+        FingerprintsModel::init(cx, &library);
 
         let playback = Playback::init(cx);
+        let database = DatabaseModel::init(cx, &library, &playback);
+        let playlists = PlaylistsModel::init(cx);
+        #[cfg(target_os = "linux")]
+        crate::mpris::Mpris::init(cx, &playback);
+        #[cfg(unix)]
+        crate::remote::Remote::init(cx, &playback);
 
-        let browse = Browse::init(cx, library.clone());
-        let playing = Playing::init(cx, library.clone());
+        let browse = Browse::init(cx, library.clone(), database, playlists.clone());
+        let playing = Playing::init(cx, playback.clone());
+
+        cx.subscribe(&browse, |this, _, event, cx| {
+            this.handle_ui_event(event, cx)
+        })
+        .detach();
+        cx.subscribe(&playing, |this, _, event, cx| {
+            this.handle_ui_event(event, cx)
+        })
+        .detach();
+        cx.subscribe(&playback, |this, _, event: &Arc<PlaybackEvent>, cx| {
+            if let PlaybackEvent::TrackStarted(track) = &**event {
+                let file = track.file.clone();
+                this.browse
+                    .update(cx, |browse, cx| browse.set_current(file, cx));
+            }
+        })
+        .detach();
 
         Self {
             browse,
             playback,
             playing,
+            playlists,
+            dialog: None,
         }
     }
 
-    fn handle_ui_event(&mut self, event: &Arc<UiEvent>, cx: &mut WindowContext) {
+    fn handle_ui_event(&mut self, event: &Arc<UiEvent>, cx: &mut ViewContext<Self>) {
         match (**event).clone() {
             UiEvent::PlayClicked(event) => self.playback.update(cx, |this, cx| {
                 this.play(Arc::clone(&event.track), cx);
                 cx.notify();
             }),
+            UiEvent::PlayTracks(tracks) => self.playback.update(cx, |this, cx| {
+                this.play_tracks(tracks, cx);
+            }),
+            UiEvent::EnqueueTracks(tracks) => self.playback.update(cx, |this, cx| {
+                this.enqueue(tracks, cx);
+            }),
+            UiEvent::AddToPlaylistClicked(track) => {
+                if self.playlists.read(cx).has_target() {
+                    self.playlists.update(cx, |playlists| {
+                        playlists.add_to_target(track.file.clone());
+                    });
+                    return;
+                }
+                // there is no playlist to add to yet, so one is made
+                let dialog =
+                    NewPlaylist::new(cx, &self.playlists, track.file.iter().cloned().collect());
+                cx.subscribe(&dialog, |this, _, event, cx| {
+                    this.handle_ui_event(event, cx)
+                })
+                .detach();
+                self.dialog = Some(dialog.into());
+                cx.notify();
+            }
+            UiEvent::RatingChanged(track, rating) => {
+                cx.global::<DatabaseModel>()
+                    .clone()
+                    .set_rating(&track, rating, cx);
+                write_tags(&track, cx, move |file| tags::write_rating(file, rating));
+            }
+            UiEvent::LovedToggled(track) => {
+                let loved = cx
+                    .global::<DatabaseModel>()
+                    .clone()
+                    .toggle_loved(&track, cx);
+                write_tags(&track, cx, move |file| tags::write_loved(file, loved));
+            }
             UiEvent::PauseClicked => self.playback.update(cx, |this, cx| {
                 this.pause(cx);
                 cx.notify();
             }),
+            UiEvent::SpeedChanged(speed) => self.playback.update(cx, |this, cx| {
+                this.set_speed(speed, cx);
+            }),
+            UiEvent::PitchChanged(semitones) => self.playback.update(cx, |this, cx| {
+                this.set_pitch(semitones, cx);
+            }),
+            UiEvent::ShuffleToggled => self.playback.update(cx, |this, cx| {
+                this.set_shuffle(!this.shuffle(), cx);
+            }),
+            UiEvent::RepeatToggled => self.playback.update(cx, |this, cx| {
+                this.set_repeat(this.repeat().cycle(), cx);
+            }),
+            UiEvent::VolumeChanged(volume) => self.playback.update(cx, |this, cx| {
+                this.set_volume(volume, cx);
+            }),
+            UiEvent::PositionChanged(position) => self.playback.update(cx, |this, cx| {
+                this.seek(position, cx);
+            }),
+            UiEvent::ExportQueueClicked => {
+                let queue = self.playback.read(cx).queue();
+                playlists::export("Queue".to_string(), queue, cx).detach();
+            }
+            UiEvent::EditTagsClicked(tracks) => {
+                let editor = TagEditor::new(cx, &tracks);
+                cx.subscribe(&editor, |this, _, event, cx| {
+                    this.handle_ui_event(event, cx)
+                })
+                .detach();
+                self.dialog = Some(editor.into());
+                cx.notify();
+            }
+            UiEvent::RenameClicked(tracks) => {
+                let tool = NamingTool::new(cx, &tracks);
+                cx.subscribe(&tool, |this, _, event, cx| this.handle_ui_event(event, cx))
+                    .detach();
+                self.dialog = Some(tool.into());
+                cx.notify();
+            }
+            UiEvent::OrganizeClicked => {
+                let organizer = Organizer::new(cx);
+                cx.subscribe(&organizer, |this, _, event, cx| {
+                    this.handle_ui_event(event, cx)
+                })
+                .detach();
+                self.dialog = Some(organizer.into());
+                cx.notify();
+            }
+            UiEvent::DuplicatesClicked => {
+                let duplicates = Duplicates::new(cx);
+                cx.subscribe(&duplicates, |this, _, event, cx| {
+                    this.handle_ui_event(event, cx)
+                })
+                .detach();
+                self.dialog = Some(duplicates.into());
+                cx.notify();
+            }
+            UiEvent::FilesMoved(moved) => self.playback.update(cx, |this, cx| {
+                this.relocate(&moved, cx);
+            }),
+            UiEvent::DialogClosed => {
+                self.dialog = None;
+                cx.notify();
+            }
+            UiEvent::OutputDeviceSelected(device) => {
+                Settings::update(cx, |settings| settings.output_device = device.clone());
+                self.playback.update(cx, |this, cx| {
+                    this.set_output_device(device, cx);
+                })
+            }
         };
     }
 }
 
+/// Writes a rating or loved mark of `track` to its tags in the background,
+/// when the settings ask for it.
+fn write_tags(
+    track: &Track,
+    cx: &mut ViewContext<Root>,
+    write: impl FnOnce(&Path) -> anyhow::Result<()> + Send + 'static,
+) {
+    // the tags of an image hold no cue sheet track in particular
+    let file = track.file.clone().filter(|_| track.segment.is_none());
+    let Some(file) = file.filter(|_| cx.global::<Settings>().write_ratings) else {
+        return;
+    };
+    cx.background_executor()
+        .spawn(async move {
+            if let Err(err) = write(Path::new(&file)) {
+                error!("Failed to write the rating tags of {}: {}", file, err);
+            }
+        })
+        .detach();
+}
+
 impl Render for Root {
     fn render(&mut self, cx: &mut ViewContext<Self>) -> impl gpui::prelude::IntoElement {
         let theme = cx.global::<Theme>();
 
+        let overlay = {
+            let mut overlay = theme.crust;
+            overlay.fade_out(0.4);
+            overlay
+        };
+
         div()
+            .relative()
             .size_full()
             .flex()
             .flex_col()
@@ -76,5 +253,19 @@ impl Render for Root {
                     .child(self.browse.clone())
                     .child(self.playing.clone()),
             )
+            .when_some(self.dialog.clone(), |e, dialog| {
+                e.child(
+                    div()
+                        .absolute()
+                        .inset_0()
+                        // keep clicks away from what is underneath
+                        .occlude()
+                        .flex()
+                        .items_center()
+                        .justify_center()
+                        .bg(overlay)
+                        .child(dialog),
+                )
+            })
     }
 }
@@ -0,0 +1,63 @@
+use gpui::{AppContext, BorrowAppContext, Global};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{metadata::columns::TrackLayout, storage};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Name of the preferred audio output device, `None` for the system default.
+    pub output_device: Option<String>,
+    pub track_layout: TrackLayout,
+    /// Names of images next to the tracks to use as their cover, without
+    /// extension, in order of preference.
+    pub cover_names: Vec<String>,
+    /// Largest width or height of a picture embedded as a cover, in pixels.
+    pub cover_max_size: u32,
+    /// Where the organizer puts every track, under the library directory.
+    pub organize_pattern: String,
+    /// Whether ratings and loved marks are also written to the tags of the
+    /// files, not only kept in the library database.
+    pub write_ratings: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            output_device: None,
+            track_layout: TrackLayout::default(),
+            cover_names: ["cover", "folder", "front", "album", "albumart"]
+                .map(String::from)
+                .to_vec(),
+            cover_max_size: 1000,
+            organize_pattern: "%albumartist%/%album%/[%disc%-]%track% - %title%".to_string(),
+            write_ratings: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut settings = storage::load::<Self>(SETTINGS_FILE);
+        settings.track_layout.add_missing_columns();
+        settings
+    }
+
+    pub fn init(cx: &mut AppContext) {
+        cx.set_global(Self::load());
+    }
+
+    pub fn update(cx: &mut AppContext, f: impl FnOnce(&mut Self)) {
+        cx.update_global::<Self, _>(|settings, _| {
+            f(settings);
+            if let Err(err) = storage::save(SETTINGS_FILE, settings) {
+                error!("Failed to save settings: {}", err);
+            }
+        });
+    }
+}
+
+impl Global for Settings {}
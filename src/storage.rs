@@ -0,0 +1,54 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ggmusic")
+}
+
+/// Where things that can be rebuilt are kept, like cover thumbnails.
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ggmusic")
+}
+
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+    load_from(&data_dir(), name)
+}
+
+/// Loads `name` from `dir` rather than the data directory.
+pub fn load_from<T: DeserializeOwned + Default>(dir: &Path, name: &str) -> T {
+    let path = dir.join(name);
+    let Ok(bytes) = fs::read(&path) else {
+        return T::default();
+    };
+
+    serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+        error!("Failed to parse {}: {}", path.display(), err);
+        T::default()
+    })
+}
+
+pub fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    save_to(&data_dir(), name, value)
+}
+
+/// Saves `name` to `dir` rather than the data directory.
+pub fn save_to<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    // write to a temporary file first so a crash never leaves a truncated file behind
+    let tmp = dir.join(format!("{}.tmp", name));
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    fs::rename(tmp, dir.join(name))?;
+
+    Ok(())
+}
@@ -0,0 +1,350 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use gpui::{
+    div, prelude::FluentBuilder, px, ElementId, InteractiveElement, IntoElement, ParentElement,
+    PathPromptOptions, Render, SharedString, StatefulInteractiveElement, Styled, View, ViewContext,
+    VisualContext, WindowContext,
+};
+
+use crate::{
+    events::UiEvent,
+    input::TextInput,
+    metadata::{
+        cover,
+        library::LibraryModel,
+        tags::{self, FieldValue, TagField},
+        track::Track,
+    },
+    settings::Settings,
+    theme::Theme,
+};
+
+/// Edits the tags of one or more tracks at once. Fields that differ between
+/// the tracks are left alone unless something is typed into them.
+pub struct TagEditor {
+    files: Vec<String>,
+    // `None` until the tags are read
+    fields: Option<Vec<(TagField, FieldValue, View<TextInput>)>>,
+    error: Option<String>,
+    // what the last cover change did
+    status: Option<String>,
+    saving: bool,
+}
+
+impl TagEditor {
+    pub fn new(cx: &mut WindowContext, tracks: &[Arc<Track>]) -> View<Self> {
+        // the tags of an image hold no cue sheet track in particular
+        let files = tracks
+            .iter()
+            .filter(|track| track.segment.is_none())
+            .filter_map(|track| track.file.clone())
+            .collect::<Vec<_>>();
+
+        cx.new_view(|cx| {
+            let read = files.clone();
+            cx.spawn(|this, mut cx| async move {
+                let fields = cx
+                    .background_executor()
+                    .spawn(async move { tags::read_fields(&read) })
+                    .await;
+
+                this.update(&mut cx, |this, cx| {
+                    match fields {
+                        Ok(fields) => this.show(fields, cx),
+                        Err(err) => this.error = Some(format!("Failed to read tags: {}", err)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            })
+            .detach();
+
+            Self {
+                files,
+                fields: None,
+                error: None,
+                status: None,
+                saving: false,
+            }
+        })
+    }
+
+    fn show(&mut self, fields: Vec<(TagField, FieldValue)>, cx: &mut ViewContext<Self>) {
+        let fields = fields
+            .into_iter()
+            .map(|(field, value)| {
+                let placeholder = match &value {
+                    FieldValue::Same(_) => "",
+                    FieldValue::Mixed => "(keep existing)",
+                };
+                let input = TextInput::new(cx, placeholder);
+                if let FieldValue::Same(text) = &value {
+                    input.update(cx, |input, cx| input.set_text(text.clone(), cx));
+                }
+                (field, value, input)
+            })
+            .collect();
+        self.fields = Some(fields);
+    }
+
+    /// Fields whose input differs from what the files hold.
+    fn changes(&self, cx: &WindowContext) -> Vec<(TagField, String)> {
+        let Some(fields) = &self.fields else {
+            return Vec::new();
+        };
+
+        fields
+            .iter()
+            .filter_map(|(field, value, input)| {
+                let text = input.read(cx).text();
+                let changed = match value {
+                    FieldValue::Same(old) => text != old,
+                    FieldValue::Mixed => !text.is_empty(),
+                };
+                changed.then(|| (*field, text.to_string()))
+            })
+            .collect()
+    }
+
+    fn save(&mut self, cx: &mut ViewContext<Self>) {
+        let changes = self.changes(cx);
+        if changes.is_empty() {
+            cx.emit(Arc::new(UiEvent::DialogClosed));
+            return;
+        }
+        if let Err(err) = tags::validate(&changes) {
+            self.error = Some(err.to_string());
+            cx.notify();
+            return;
+        }
+
+        self.write_files(move |path| tags::write(path, &changes), None, cx);
+    }
+
+    /// Runs `write` on every file in the background, then reads them back
+    /// into the library. Closes the dialog afterwards unless there is a
+    /// `status` to show.
+    fn write_files(
+        &mut self,
+        write: impl Fn(&Path) -> Result<()> + Send + 'static,
+        status: Option<String>,
+        cx: &mut ViewContext<Self>,
+    ) {
+        self.saving = true;
+        self.error = None;
+        self.status = None;
+        cx.notify();
+
+        let files = self.files.clone();
+        let cover_names = cx.global::<Settings>().cover_names.clone();
+        let library = cx.global::<LibraryModel>().clone();
+        cx.spawn(|this, mut cx| async move {
+            let (tracks, errors) = cx
+                .background_executor()
+                .spawn(async move { tags::rewrite(&files, write, &cover_names) })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                library.replace(tracks, cx);
+                this.saving = false;
+                match (errors.is_empty(), status) {
+                    (true, None) => cx.emit(Arc::new(UiEvent::DialogClosed)),
+                    (true, Some(status)) => this.status = Some(status),
+                    (false, _) => this.error = Some(errors.join("\n")),
+                }
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Asks for an image and embeds it as the front cover of every file.
+    fn set_cover(&mut self, cx: &mut ViewContext<Self>) {
+        let paths = cx.prompt_for_paths(PathPromptOptions {
+            files: true,
+            directories: false,
+            multiple: false,
+        });
+        let max_size = cx.global::<Settings>().cover_max_size;
+
+        cx.spawn(|this, mut cx| async move {
+            let Ok(Some(paths)) = paths.await else {
+                return;
+            };
+            let Some(image) = paths.into_iter().next() else {
+                return;
+            };
+
+            let picture = cx
+                .background_executor()
+                .spawn(async move { cover::prepare_picture(&image, max_size) })
+                .await;
+            this.update(&mut cx, |this, cx| match picture {
+                Ok(picture) => {
+                    let status = format!("Cover set on {} tracks", this.files.len());
+                    this.write_files(move |path| cover::embed(path, &picture), Some(status), cx)
+                }
+                Err(err) => {
+                    this.error = Some(format!("Failed to read the image: {}", err));
+                    cx.notify();
+                }
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn remove_cover(&mut self, cx: &mut ViewContext<Self>) {
+        let status = format!("Cover removed from {} tracks", self.files.len());
+        self.write_files(cover::remove_embedded, Some(status), cx);
+    }
+
+    /// Saves the cover embedded in the first file that has one.
+    fn export_cover(&mut self, cx: &mut ViewContext<Self>) {
+        let Some(first) = self.files.first() else {
+            return;
+        };
+        let directory = Path::new(first)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let destination = cx.prompt_for_new_path(&directory);
+        let files = self.files.clone();
+
+        cx.spawn(|this, mut cx| async move {
+            let Ok(Some(destination)) = destination.await else {
+                return;
+            };
+            let exported = cx
+                .background_executor()
+                .spawn({
+                    let destination = destination.clone();
+                    async move {
+                        files.iter().find_map(|file| {
+                            cover::export_embedded(Path::new(file), &destination).ok()
+                        })
+                    }
+                })
+                .await;
+
+            this.update(&mut cx, |this, cx| {
+                match exported {
+                    Some(()) => this.status = Some(format!("Saved {}", destination.display())),
+                    None => this.error = Some("No embedded cover to export".to_string()),
+                }
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn button(
+        &self,
+        id: &'static str,
+        label: impl Into<SharedString>,
+        cx: &mut ViewContext<Self>,
+        on_click: impl Fn(&mut Self, &mut ViewContext<Self>) + 'static,
+    ) -> impl IntoElement {
+        div()
+            .id(id)
+            .px_2()
+            .child(label.into())
+            .on_click(cx.listener(move |this, _, cx| on_click(this, cx)))
+    }
+}
+
+impl Render for TagEditor {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let theme = cx.global::<Theme>();
+        let (background, text, label, invalid) =
+            (theme.base, theme.text, theme.subtext0, theme.red);
+
+        let title = match self.files.as_slice() {
+            [file] => format!("Editing {}", file),
+            files => format!("Editing {} tracks", files.len()),
+        };
+
+        let fields = self.fields.as_ref().map(|fields| {
+            fields
+                .iter()
+                .map(|(field, _, input)| {
+                    div()
+                        .id(ElementId::Name(field.label().into()))
+                        .flex()
+                        .items_center()
+                        .gap_2()
+                        .child(
+                            div()
+                                .flex_none()
+                                .w_32()
+                                .text_sm()
+                                .text_color(label)
+                                .child(field.label()),
+                        )
+                        .child(input.clone())
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let covers = div()
+            .flex()
+            .items_center()
+            .gap_2()
+            .child(
+                div()
+                    .flex_none()
+                    .w_32()
+                    .text_sm()
+                    .text_color(label)
+                    .child("Cover"),
+            )
+            .when(!self.saving, |e| {
+                e.child(self.button("set-cover", "Choose image", cx, Self::set_cover))
+                    .child(self.button("export-cover", "Export", cx, Self::export_cover))
+                    .child(self.button("remove-cover", "Remove", cx, Self::remove_cover))
+            });
+
+        let buttons = div()
+            .flex()
+            .justify_end()
+            .gap_2()
+            .child(self.button("cancel-tags", "Cancel", cx, |_, cx| {
+                cx.emit(Arc::new(UiEvent::DialogClosed))
+            }))
+            .when(self.fields.is_some() && !self.saving, |e| {
+                e.child(self.button("save-tags", "Save", cx, Self::save))
+            });
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .w(px(480.))
+            .p_3()
+            .rounded_md()
+            .bg(background)
+            .text_color(text)
+            .child(div().overflow_hidden().whitespace_nowrap().child(title))
+            .map(|e| match fields {
+                Some(fields) => e.children(fields),
+                None => e.child(div().text_sm().text_color(label).child("Reading tags…")),
+            })
+            .child(covers)
+            .when_some(self.status.clone(), |e, status| {
+                e.child(div().text_sm().text_color(label).child(status))
+            })
+            .when_some(self.error.clone(), |e, error| {
+                e.child(div().text_sm().text_color(invalid).child(error))
+            })
+            .when(self.saving, |e| {
+                e.child(div().text_sm().text_color(label).child("Saving…"))
+            })
+            .child(buttons)
+    }
+}